@@ -0,0 +1,93 @@
+// The setup shared by the doc examples, which include it via
+// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+// # use doc_setup::*;
+// The connecting helpers are meant for "no_run" examples, since no peer is listening.
+mod example_protocol {
+    include!("example_protocol.rs");
+}
+pub use self::example_protocol::*;
+pub use rust_tcp_ipc::*;
+
+pub fn config() -> TcpIpcConfig {
+    TcpIpcConfig {
+        after_connect_wait_time: None,
+        read_iteration_wait_time: None,
+        shutdown_wait_time: None,
+        check_count: 1,
+        payload_logging: PayloadLogging::LengthOnly,
+    }
+}
+
+pub fn client() -> TcpIpc<ProtocolExample> {
+    TcpIpc::client("127.0.0.1:6666", config(), None).expect("connecting failed")
+}
+
+pub fn server() -> TcpIpc<ProtocolExample> {
+    TcpIpc::server("127.0.0.1:6666", config(), None).expect("connecting failed")
+}
+
+pub fn description() -> ProtocolDescription {
+    ProtocolDescription {
+        command_width: 2,
+        length_width: 3,
+        order: HeaderOrder::LengthFirst,
+        length_encoding: LengthEncoding::BigEndian,
+        commands: vec![CommandDescription {
+            name: "Start".to_string(),
+            value: b"00".to_vec(),
+        }],
+        accept_unknown_commands: false,
+        busy_states: vec!["Idle".to_string()],
+        immediate_replies: Vec::new(),
+    }
+}
+
+// defines the protocol "CustomProtocol", which behaves like ProtocolExample except for the given methods
+// (this needs "#[macro_use] mod doc_setup")
+#[allow(unused_macros)]
+macro_rules! custom_protocol {
+    ($($method:item)*) => {
+        enum CustomProtocol {}
+        impl Protocol for CustomProtocol {
+            type Commands = CommandsExample;
+            type BusyStates = BusyStatesExample;
+            type CommandAsArray = <ProtocolExample as Protocol>::CommandAsArray;
+            type LengthAsArray = <ProtocolExample as Protocol>::LengthAsArray;
+            type HeaderAsArray = <ProtocolExample as Protocol>::HeaderAsArray;
+            fn idle() -> Self::BusyStates {
+                ProtocolExample::idle()
+            }
+            fn message_is_answered_via_immediate_route(
+                command: &Self::Commands,
+                message: &[u8],
+                busy_state: &Self::BusyStates,
+            ) -> Option<(Self::Commands, Vec<u8>)> {
+                ProtocolExample::message_is_answered_via_immediate_route(command, message, busy_state)
+            }
+            fn message_slice_to_header_array(input: &[u8]) -> Option<(&Self::HeaderAsArray, &[u8])> {
+                ProtocolExample::message_slice_to_header_array(input)
+            }
+            fn parse_command(command: &Self::CommandAsArray) -> Option<Self::Commands> {
+                ProtocolExample::parse_command(command)
+            }
+            fn parse_length(length: &Self::LengthAsArray) -> Option<usize> {
+                ProtocolExample::parse_length(length)
+            }
+            fn split_header_array(
+                header: &Self::HeaderAsArray,
+            ) -> (&Self::CommandAsArray, &Self::LengthAsArray) {
+                ProtocolExample::split_header_array(header)
+            }
+            fn command_to_array(command: Self::Commands) -> Self::CommandAsArray {
+                ProtocolExample::command_to_array(command)
+            }
+            fn get_length_as_array(command: Self::Commands, message: &[u8]) -> Option<Self::LengthAsArray> {
+                ProtocolExample::get_length_as_array(command, message)
+            }
+            fn construct_header(command: Self::CommandAsArray, length: Self::LengthAsArray) -> Vec<u8> {
+                ProtocolExample::construct_header(command, length)
+            }
+            $($method)*
+        }
+    };
+}
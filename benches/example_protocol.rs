@@ -58,7 +58,7 @@ impl Protocol for ProtocolExample {
             .iter()
             .rev()
             .enumerate()
-            .map(|(i, &x)| (u32::from(x) * 10u32.pow(i as u32)) as usize)
+            .map(|(i, &x)| (u32::from(x) * 256u32.pow(i as u32)) as usize)
             .sum();
         Some(length)
     }
@@ -96,8 +96,11 @@ impl Protocol for ProtocolExample {
             Funny => [b'4', b'2'],
         }
     }
-    fn get_length_as_array(_: Self::Commands, message: &[u8]) -> Option<Self::LengthAsArray> {
-        let length = message.len() as u64;
+    fn get_length_as_array(command: Self::Commands, message: &[u8]) -> Option<Self::LengthAsArray> {
+        Self::length_to_array(command, message.len())
+    }
+    fn length_to_array(_: Self::Commands, length: usize) -> Option<Self::LengthAsArray> {
+        let length = length as u64;
         if length >= 256u64.pow(3) {
             return None;
         }
@@ -184,10 +184,11 @@ fn speed_check_rust_tcp_ipc(c: &mut criterion::Criterion) {
         read_iteration_wait_time: None, //Some(std::time::Duration::from_nanos(500)), //None,
         shutdown_wait_time: Some(std::time::Duration::from_micros(5_000_000)),
         check_count: 10_000,
+        payload_logging: PayloadLogging::Off,
     };
 
     std::thread::spawn(move || {
-        let mut server = TcpIpc::<ProtocolExample>::server("127.0.0.1:42457", config)
+        let mut server = TcpIpc::<ProtocolExample>::server("127.0.0.1:42457", config, None)
             .expect("Unable to start server");
         loop {
             let (command, message) = server
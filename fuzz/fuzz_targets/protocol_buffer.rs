@@ -0,0 +1,34 @@
+#![no_main]
+// Feeds arbitrary bytes (split into chunks) to the ProtocolBuffer, which must neither panic nor grow unboundedly.
+// The example protocol limits the payload length to 16 MiB, so run with a malloc limit above that:
+// cargo fuzz run protocol_buffer -- -malloc_limit_mb=64
+use libfuzzer_sys::fuzz_target;
+use rust_tcp_ipc::ProtocolBuffer;
+
+#[path = "../../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+use example_protocol::ProtocolExample;
+
+fuzz_target!(|data: &[u8]| {
+    // the first byte gives the chunk size, so that splits inside headers & payloads are covered
+    let (chunk_size, bytes) = match data.split_first() {
+        Some((&chunk_size, bytes)) => (usize::from(chunk_size).max(1), bytes),
+        None => return,
+    };
+    let mut buffer = ProtocolBuffer::<ProtocolExample>::new();
+    let mut received = 0;
+    for chunk in bytes.chunks(chunk_size) {
+        for message in buffer.decode(chunk) {
+            match message {
+                Ok((_, payload)) => received += 5 + payload.len(),
+                // the stream cannot be decoded any further
+                Err(_) => return,
+            }
+        }
+    }
+    assert!(received <= bytes.len(), "more bytes decoded than received");
+    if let Some((_, truncated)) = buffer.take_truncated_message() {
+        assert!(received + truncated.len() <= bytes.len());
+    }
+});
@@ -0,0 +1,496 @@
+//! A command-line tool for talking to a protocol peer, using a protocol described at runtime (see DynamicProtocol).
+//! It is built with the feature "cli", run it with "--help" for the usage.
+use rust_tcp_ipc::*;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "\
+USAGE:
+    rust_tcp_ipc [OPTIONS] connect <ADDRESS> [ACTIONS]
+    rust_tcp_ipc [OPTIONS] listen <ADDRESS> [ACTIONS]
+    rust_tcp_ipc [OPTIONS] proxy <LISTEN ADDRESS> <SERVER ADDRESS>
+
+The actions are executed in order. Afterwards, received messages are printed until the peer closes the connection (or the duration is over).
+As proxy, clients are accepted one at a time and their traffic with the server is printed (until the duration is over).
+
+PROTOCOL OPTIONS (later options modify earlier ones):
+    --protocol <FILE>            loads a protocol description (.toml or .json), see DynamicProtocol
+    --command-width <BYTES>      width of the command (default: 2)
+    --length-width <BYTES>       width of the payload length (default: 4)
+    --length-first               the header starts with the length (default: command first)
+    --length-encoding <ENCODING> big_endian (default), little_endian, ascii_decimal or ascii_hex
+    --command <NAME>=<HEX>       a named command, can be repeated (if none is given, all commands are accepted)
+
+OPTIONS:
+    --timeout <SECONDS>          wait time for connecting & for expected messages (default: 5)
+    --duration <SECONDS>         time to print received messages after all actions (default: until the peer closes)
+    --record <FILE>              records the session (see SessionRecorder)
+
+ACTIONS:
+    --send <COMMAND> <PAYLOAD>   sends a message
+    --script <FILE>              runs a scripted exchange
+
+A COMMAND is given by its name or as hex (like 0x3030).
+A PAYLOAD is given as hex:<HEX>, ascii:<TEXT> or file:<PATH>, an empty string means no payload.
+
+SCRIPT FILES:
+    Each line holds one step, empty lines & lines starting with # are ignored:
+    send <COMMAND> [PAYLOAD]     sends a message
+    expect <COMMAND> [PAYLOAD]   awaits the next message, the script fails if it does not match
+    sleep <MILLISECONDS>         waits, printing received messages
+";
+
+enum Role {
+    Connect(String),
+    Listen(String),
+    Proxy(String, String),
+}
+
+enum Action {
+    Send(String, String),
+    Script(String),
+}
+
+struct Options {
+    description: ProtocolDescription,
+    role: Role,
+    actions: Vec<Action>,
+    timeout: Duration,
+    duration: Option<Duration>,
+    record: Option<String>,
+}
+
+fn main() {
+    let arguments = std::env::args().skip(1).collect::<Vec<_>>();
+    let options = match parse_arguments(arguments) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(err) = run(options) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+// returns None if the usage is requested via "--help" (which is only recognized in place of an option, not as the value of one)
+fn parse_arguments(arguments: Vec<String>) -> Result<Option<Options>, String> {
+    let mut description = ProtocolDescription {
+        command_width: 2,
+        length_width: 4,
+        order: HeaderOrder::CommandFirst,
+        length_encoding: LengthEncoding::BigEndian,
+        commands: Vec::new(),
+        accept_unknown_commands: true,
+        busy_states: Vec::new(),
+        immediate_replies: Vec::new(),
+    };
+    let mut role = None;
+    let mut actions = Vec::new();
+    let mut timeout = Duration::from_secs(5);
+    let mut duration = None;
+    let mut record = None;
+    let mut arguments = arguments.into_iter();
+    while let Some(argument) = arguments.next() {
+        let mut value = |name: &str| {
+            arguments
+                .next()
+                .ok_or_else(|| format!("missing value for {}", name))
+        };
+        match argument.as_str() {
+            "--help" => return Ok(None),
+            "--protocol" => {
+                let path = value(&argument)?;
+                description = ProtocolDescription::load(&path)
+                    .map_err(|err| format!("loading protocol '{}' failed: {:?}", path, err))?;
+            }
+            "--command-width" => description.command_width = parse_number(&value(&argument)?)?,
+            "--length-width" => description.length_width = parse_number(&value(&argument)?)?,
+            "--length-first" => description.order = HeaderOrder::LengthFirst,
+            "--length-encoding" => {
+                description.length_encoding = match value(&argument)?.as_str() {
+                    "big_endian" => LengthEncoding::BigEndian,
+                    "little_endian" => LengthEncoding::LittleEndian,
+                    "ascii_decimal" => LengthEncoding::AsciiDecimal,
+                    "ascii_hex" => LengthEncoding::AsciiHex,
+                    encoding => return Err(format!("unknown length encoding '{}'", encoding)),
+                }
+            }
+            "--command" => {
+                let command = value(&argument)?;
+                let mut parts = command.splitn(2, '=');
+                let name = parts.next().unwrap_or_default().to_string();
+                let value = parts
+                    .next()
+                    .ok_or_else(|| format!("invalid command '{}', expected NAME=HEX", command))?;
+                description.commands.push(CommandDescription {
+                    name,
+                    value: parse_hex(value)?,
+                });
+                description.accept_unknown_commands = false;
+            }
+            "--timeout" => timeout = parse_seconds(&value(&argument)?)?,
+            "--duration" => duration = Some(parse_seconds(&value(&argument)?)?),
+            "--send" => {
+                let command = value(&argument)?;
+                let payload = value(&argument)?;
+                actions.push(Action::Send(command, payload));
+            }
+            "--record" => record = Some(value(&argument)?),
+            "--script" => actions.push(Action::Script(value(&argument)?)),
+            "connect" if role.is_none() => role = Some(Role::Connect(value(&argument)?)),
+            "listen" if role.is_none() => role = Some(Role::Listen(value(&argument)?)),
+            "proxy" if role.is_none() => {
+                let listen_address = value(&argument)?;
+                role = Some(Role::Proxy(listen_address, value(&argument)?))
+            }
+            _ => return Err(format!("unexpected argument '{}'", argument)),
+        }
+    }
+    Ok(Some(Options {
+        description,
+        role: role.ok_or("either connect, listen or proxy has to be given")?,
+        actions,
+        timeout,
+        duration,
+        record,
+    }))
+}
+
+fn run(options: Options) -> Result<(), String> {
+    DefaultSlot::install(options.description)
+        .map_err(|err| format!("invalid protocol: {:?}", err))?;
+    let config = TcpIpcConfig {
+        after_connect_wait_time: None,
+        read_iteration_wait_time: Some(Duration::from_millis(1)),
+        shutdown_wait_time: Some(Duration::from_secs(1)),
+        check_count: 100,
+        payload_logging: PayloadLogging::Off,
+    };
+    let recorder = match &options.record {
+        Some(path) => Some(
+            SessionRecorder::create(path)
+                .map_err(|err| format!("creating recording '{}' failed: {}", path, err))?,
+        ),
+        None => None,
+    };
+    let mut connection = match options.role {
+        Role::Connect(address) => {
+            TcpIpc::<DynamicProtocol>::client(address.as_str(), config, Some(options.timeout))
+        }
+        Role::Listen(address) => {
+            let listener = TcpIpcListener::bind(address.as_str())
+                .map_err(|err| format!("binding failed: {:?}", err))?;
+            if let Ok(address) = listener.local_addr() {
+                println!("listening on {}", address);
+            }
+            listener.accept(config, None)
+        }
+        Role::Proxy(listen_address, server_address) => {
+            if !options.actions.is_empty() {
+                return Err("a proxy does not support actions".to_string());
+            }
+            return run_proxy(&listen_address, &server_address, options.duration, recorder);
+        }
+    }
+    .map_err(|err| format!("connecting failed: {:?}", err))?;
+    connection.set_recorder(recorder.clone());
+    if let Ok(address) = connection.peer_addr() {
+        println!("connected to {}", address);
+    }
+    let mut session = Session {
+        connection,
+        timeout: options.timeout,
+        peer_closed: false,
+    };
+    for action in options.actions {
+        match action {
+            Action::Send(command, payload) => session.send(&command, &payload)?,
+            Action::Script(path) => session.run_script(&path)?,
+        }
+    }
+    let start = Instant::now();
+    while !session.peer_closed {
+        let remaining = match options.duration {
+            Some(duration) => match duration.checked_sub(start.elapsed()) {
+                Some(remaining) => remaining,
+                None => break,
+            },
+            None => Duration::from_secs(1),
+        };
+        session.print_received(remaining)?;
+    }
+    let result = match session.connection.shutdown() {
+        // if the peer closed the connection, the read thread is already gone
+        Err(_) if session.peer_closed => Ok(()),
+        result => result.map_err(|err| format!("shutdown failed: {:?}", err)),
+    };
+    flush_recording(recorder)?;
+    result
+}
+
+fn run_proxy(
+    listen_address: &str,
+    server_address: &str,
+    duration: Option<Duration>,
+    recorder: Option<SessionRecorder>,
+) -> Result<(), String> {
+    let listener = std::net::TcpListener::bind(listen_address)
+        .map_err(|err| format!("binding failed: {}", err))?;
+    if let Ok(address) = listener.local_addr() {
+        println!(
+            "proxy listening on {}, forwarding to {}",
+            address, server_address
+        );
+    }
+    let cancel_handle = CancelHandle::new();
+    if let Some(duration) = duration {
+        let cancel_handle = cancel_handle.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(duration);
+            cancel_handle.cancel();
+        });
+    }
+    let mut proxy = Proxy::<DynamicProtocol>::new().on_message(|direction, command, payload| {
+        let arrow = match direction {
+            ProxyDirection::ClientToServer => "client -> server",
+            ProxyDirection::ServerToClient => "server -> client",
+        };
+        println!("{} {}", arrow, describe_message(*command, payload));
+    });
+    if let Some(recorder) = recorder.clone() {
+        proxy = proxy.recorder(recorder);
+    }
+    proxy
+        .serve(&listener, server_address, &cancel_handle)
+        .map_err(|err| format!("proxy failed: {:?}", err))?;
+    flush_recording(recorder)
+}
+
+fn flush_recording(recorder: Option<SessionRecorder>) -> Result<(), String> {
+    match recorder {
+        Some(recorder) => recorder
+            .flush()
+            .map_err(|err| format!("writing the recording failed: {}", err)),
+        None => Ok(()),
+    }
+}
+
+struct Session {
+    connection: TcpIpc<DynamicProtocol>,
+    timeout: Duration,
+    peer_closed: bool,
+}
+impl Session {
+    fn send(&mut self, command: &str, payload: &str) -> Result<(), String> {
+        let command = parse_command(command)?;
+        let payload = parse_payload(payload)?;
+        self.connection
+            .write_message(command, &payload)
+            .map_err(|err| format!("sending failed: {:?}", err))?;
+        println!("-> {}", describe_message(command, &payload));
+        Ok(())
+    }
+    // returns the next received message (which is printed), if any is received during the wait time
+    fn receive(&mut self, wait_time: Duration) -> Result<Option<Message<DynamicProtocol>>, String> {
+        if self.peer_closed {
+            return Ok(None);
+        }
+        match self
+            .connection
+            .await_message(wait_time, Some(Duration::from_millis(1)))
+        {
+            Ok(Some((command, payload))) => {
+                println!("<- {}", describe_message(command, &payload));
+                Ok(Some((command, payload)))
+            }
+            Ok(None) => Ok(None),
+            Err(ReadThreadErrors::PeerClosed) => {
+                println!("peer closed the connection");
+                self.peer_closed = true;
+                Ok(None)
+            }
+            Err(err) => Err(describe_read_error(err)),
+        }
+    }
+    fn print_received(&mut self, wait_time: Duration) -> Result<(), String> {
+        let start = Instant::now();
+        while let Some(remaining) = wait_time.checked_sub(start.elapsed()) {
+            if self.receive(remaining)?.is_none() {
+                break;
+            }
+        }
+        Ok(())
+    }
+    fn run_script(&mut self, path: &str) -> Result<(), String> {
+        let script = std::fs::read_to_string(path)
+            .map_err(|err| format!("reading script '{}' failed: {}", path, err))?;
+        for (line_number, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(3, char::is_whitespace);
+            let step = parts.next().unwrap_or_default();
+            let argument = parts.next().unwrap_or_default();
+            let payload = parts.next().unwrap_or_default().trim_start();
+            self.run_step(step, argument, payload)
+                .map_err(|err| format!("{}:{}: {}", path, line_number + 1, err))?;
+        }
+        Ok(())
+    }
+    fn run_step(&mut self, step: &str, argument: &str, payload: &str) -> Result<(), String> {
+        match step {
+            "send" => self.send(argument, payload),
+            "expect" => {
+                let command = parse_command(argument)?;
+                let expected_payload = if payload.is_empty() {
+                    None
+                } else {
+                    Some(parse_payload(payload)?)
+                };
+                match self.receive(self.timeout)? {
+                    Some((received_command, received_payload)) => {
+                        if received_command != command {
+                            Err(format!(
+                                "expected {:?}, received {:?}",
+                                command, received_command
+                            ))
+                        } else if expected_payload.is_some_and(|p| p != received_payload) {
+                            Err(format!("payload of {:?} does not match", command))
+                        } else {
+                            Ok(())
+                        }
+                    }
+                    None => Err(format!("expected {:?}, but nothing was received", command)),
+                }
+            }
+            "sleep" => {
+                let milliseconds = argument
+                    .parse()
+                    .map_err(|_| format!("invalid milliseconds '{}'", argument))?;
+                self.print_received(Duration::from_millis(milliseconds))
+            }
+            _ => Err(format!("unknown step '{}'", step)),
+        }
+    }
+}
+
+fn parse_number(text: &str) -> Result<usize, String> {
+    text.parse()
+        .map_err(|_| format!("invalid number '{}'", text))
+}
+
+fn parse_seconds(text: &str) -> Result<Duration, String> {
+    text.parse::<f64>()
+        .ok()
+        .filter(|seconds| *seconds >= 0.)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| format!("invalid seconds '{}'", text))
+}
+
+fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let text = text.trim_start_matches("0x");
+    let digits = text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16).map(|digit| digit as u8))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| format!("invalid hex '{}'", text))?;
+    if digits.len() % 2 != 0 {
+        return Err(format!("odd number of hex digits in '{}'", text));
+    }
+    Ok(digits
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair[1])
+        .collect())
+}
+
+fn parse_command(text: &str) -> Result<DynamicCommand, String> {
+    if let Some(command) = DefaultSlot::command_by_name(text) {
+        return Ok(command);
+    }
+    if text.starts_with("0x") {
+        let bytes = parse_hex(text)?;
+        let width = DefaultSlot::description().map_or(0, |d| d.command_width);
+        if bytes.len() == width {
+            if let Some(command) = DynamicCommand::new(&bytes) {
+                return Ok(command);
+            }
+        }
+        return Err(format!("command '{}' does not have {} bytes", text, width));
+    }
+    Err(format!("unknown command '{}'", text))
+}
+
+fn parse_payload(text: &str) -> Result<Vec<u8>, String> {
+    if text.is_empty() {
+        Ok(Vec::new())
+    } else if let Some(hex) = text.strip_prefix("hex:") {
+        parse_hex(hex)
+    } else if let Some(ascii) = text.strip_prefix("ascii:") {
+        Ok(ascii.as_bytes().to_vec())
+    } else if let Some(path) = text.strip_prefix("file:") {
+        std::fs::read(path).map_err(|err| format!("reading '{}' failed: {}", path, err))
+    } else {
+        Err(format!(
+            "invalid payload '{}', expected hex:..., ascii:... or file:...",
+            text
+        ))
+    }
+}
+
+fn describe_message(command: DynamicCommand, payload: &[u8]) -> String {
+    let hex = payload
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    let ascii = payload
+        .iter()
+        .map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        })
+        .collect::<String>();
+    format!(
+        "{:?} [{} bytes] {} \"{}\"",
+        command,
+        payload.len(),
+        hex,
+        ascii
+    )
+}
+
+fn describe_read_error(err: ReadThreadErrors<DynamicProtocol>) -> String {
+    match err {
+        ReadThreadErrors::WriteError(err) => format!("writing failed: {}", err),
+        ReadThreadErrors::ReadError(err) => format!("reading failed: {}", err),
+        ReadThreadErrors::ImmediateMessageConstructError((command, _)) => {
+            format!("constructing an immediate reply {:?} failed", command)
+        }
+        ReadThreadErrors::Disconnected => "the read thread stopped".to_string(),
+        ReadThreadErrors::TruncatedMessage((command, bytes)) => format!(
+            "the peer closed the connection during a message ({:?}, {} bytes received)",
+            command,
+            bytes.len()
+        ),
+        ReadThreadErrors::PeerClosed => "the peer closed the connection".to_string(),
+        ReadThreadErrors::ParseHeaderFailed((err, header)) => {
+            format!("parsing the header {:02x?} failed: {:?}", header, err)
+        }
+        ReadThreadErrors::QueueOverflow(count) => {
+            format!(
+                "{} messages were dropped, the message queue was full",
+                count
+            )
+        }
+    }
+}
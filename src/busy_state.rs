@@ -0,0 +1,219 @@
+use super::protocol::*;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+type BusyStateCallback<P> =
+    Box<dyn FnMut(<P as Protocol>::BusyStates, <P as Protocol>::BusyStates) + Send + 'static>;
+
+struct BusyStateInner<P: Protocol> {
+    busy_state: P::BusyStates,
+    callbacks: Vec<BusyStateCallback<P>>,
+    state_machine: Option<BusyStateMachine<P>>,
+}
+
+/// This is a handle to the busy_state of a connection.
+/// The busy_state is stored behind a lock, so updates are immediately visible to all clones of this handle, including the one used by the read thread.
+/// It can be cloned and moved to another thread.
+///
+/// Callbacks registered via 'add_change_callback' are called on every transition (with the old and the new busy_state).
+/// They are called while the lock is held, so they must not access the busy_state handle themselves.
+///
+/// If a state machine is installed (see TcpIpc::set_busy_state_machine), only its allowed transitions are performed.
+pub struct SharedBusyState<P: Protocol> {
+    inner: Arc<Mutex<BusyStateInner<P>>>,
+}
+impl<P: Protocol> Clone for SharedBusyState<P> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+impl<P: Protocol> std::fmt::Debug for SharedBusyState<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SharedBusyState")
+            .field("busy_state", &self.get())
+            .finish()
+    }
+}
+impl<P: Protocol> Default for SharedBusyState<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<P: Protocol> SharedBusyState<P> {
+    /// This constructs a new handle, starting with the busy_state Protocol::idle().
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(BusyStateInner {
+                busy_state: P::idle(),
+                callbacks: Vec::new(),
+                state_machine: None,
+            })),
+        }
+    }
+    // a panicking callback poisons the lock, but the busy_state itself stays consistent
+    fn lock(&self) -> MutexGuard<'_, BusyStateInner<P>> {
+        match self.inner.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+    /// This returns the current busy_state.
+    pub fn get(&self) -> P::BusyStates {
+        self.lock().busy_state
+    }
+    /// This sets the busy_state and returns the previous one.
+    /// If the busy_state changes, all callbacks are called.
+    /// If the installed state machine does not allow this transition, the busy_state is kept and None is returned.
+    pub fn set(&self, busy_state: P::BusyStates) -> Option<P::BusyStates> {
+        let mut inner = self.lock();
+        let old_busy_state = inner.busy_state;
+        if !inner.is_transition_allowed(old_busy_state, busy_state) {
+            return None;
+        }
+        inner.busy_state = busy_state;
+        if old_busy_state != busy_state {
+            for callback in inner.callbacks.iter_mut() {
+                callback(old_busy_state, busy_state);
+            }
+        }
+        Some(old_busy_state)
+    }
+    /// This sets the busy_state only if the current busy_state equals 'current' and if the installed state machine allows this transition.
+    /// Returns true if the busy_state was set.
+    pub fn compare_and_set(&self, current: P::BusyStates, busy_state: P::BusyStates) -> bool {
+        let mut inner = self.lock();
+        if inner.busy_state != current || !inner.is_transition_allowed(current, busy_state) {
+            return false;
+        }
+        inner.busy_state = busy_state;
+        if current != busy_state {
+            for callback in inner.callbacks.iter_mut() {
+                callback(current, busy_state);
+            }
+        }
+        true
+    }
+    /// This registers a callback which is called on every transition, with the old and the new busy_state.
+    pub fn add_change_callback<F>(&self, callback: F)
+    where
+        F: FnMut(P::BusyStates, P::BusyStates) + Send + 'static,
+    {
+        self.lock().callbacks.push(Box::new(callback));
+    }
+    // later updates are checked against the transitions of this state machine
+    pub(crate) fn set_state_machine(&self, state_machine: BusyStateMachine<P>) {
+        self.lock().state_machine = Some(state_machine);
+    }
+}
+impl<P: Protocol> BusyStateInner<P> {
+    fn is_transition_allowed(&self, from: P::BusyStates, to: P::BusyStates) -> bool {
+        match &self.state_machine {
+            Some(state_machine) => state_machine.is_transition_allowed(from, to),
+            None => true,
+        }
+    }
+}
+
+/// The action the read thread performs if a command is received in a certain busy_state (see BusyStateMachine).
+#[derive(Debug)]
+pub enum CommandAction<P: Protocol> {
+    /// The message is neither answered nor forwarded to the user.
+    Reject,
+    /// The message is answered immediately with the given replies (zero, one or several) and not forwarded to the user.
+    Answer(Vec<Message<P>>),
+    /// The message is forwarded to the user and the busy_state switches to the given one.
+    /// If this transition is not allowed, the busy_state is kept.
+    Transition(P::BusyStates),
+}
+impl<P: Protocol> Clone for CommandAction<P> {
+    fn clone(&self) -> Self {
+        match self {
+            CommandAction::Reject => CommandAction::Reject,
+            CommandAction::Answer(replies) => CommandAction::Answer(replies.clone()),
+            CommandAction::Transition(busy_state) => CommandAction::Transition(*busy_state),
+        }
+    }
+}
+
+/// This describes the busy_state handling of a protocol as a state machine.
+/// It consists of the allowed transitions between busy_states and of actions for commands received in a busy_state.
+/// Commands without an action in the current busy_state are passed to the handler of the immediate route (see TcpIpc::set_immediate_route_handler).
+///
+/// If no transition is declared, all transitions are allowed.
+/// # Example
+/// ```no_run
+/// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+/// # use doc_setup::*;
+/// # let mut client = client();
+/// use BusyStatesExample::*;
+/// let state_machine = BusyStateMachine::<ProtocolExample>::new()
+///     .allow_transition(Idle, Working)
+///     .allow_transition(Working, Idle)
+///     .on_command(Idle, CommandsExample::Start, CommandAction::Transition(Working))
+///     .on_command(
+///         Working,
+///         CommandsExample::Start,
+///         CommandAction::Answer(vec![(CommandsExample::Funny, b"busy".to_vec())]),
+///     );
+/// client.set_busy_state_machine(state_machine);
+/// ```
+#[derive(Debug)]
+pub struct BusyStateMachine<P: Protocol> {
+    transitions: Vec<(P::BusyStates, P::BusyStates)>,
+    actions: Vec<(P::BusyStates, P::Commands, CommandAction<P>)>,
+}
+impl<P: Protocol> Clone for BusyStateMachine<P> {
+    fn clone(&self) -> Self {
+        Self {
+            transitions: self.transitions.clone(),
+            actions: self.actions.clone(),
+        }
+    }
+}
+impl<P: Protocol> Default for BusyStateMachine<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<P: Protocol> BusyStateMachine<P> {
+    /// This constructs a state machine without declared transitions and actions.
+    pub fn new() -> Self {
+        Self {
+            transitions: Vec::new(),
+            actions: Vec::new(),
+        }
+    }
+    /// This declares the transition from one busy_state to another one as allowed.
+    pub fn allow_transition(mut self, from: P::BusyStates, to: P::BusyStates) -> Self {
+        self.transitions.push((from, to));
+        self
+    }
+    /// This declares the action for a command received in the given busy_state.
+    /// If several actions are declared for the same busy_state & command, the first one is used.
+    pub fn on_command(
+        mut self,
+        busy_state: P::BusyStates,
+        command: P::Commands,
+        action: CommandAction<P>,
+    ) -> Self {
+        self.actions.push((busy_state, command, action));
+        self
+    }
+    /// This checks if the transition from one busy_state to another one is allowed.
+    /// Keeping the busy_state is always allowed.
+    pub fn is_transition_allowed(&self, from: P::BusyStates, to: P::BusyStates) -> bool {
+        self.transitions.is_empty() || from == to || self.transitions.contains(&(from, to))
+    }
+    /// This returns the action for a command received in the given busy_state, if any.
+    pub fn action(
+        &self,
+        busy_state: P::BusyStates,
+        command: P::Commands,
+    ) -> Option<&CommandAction<P>> {
+        self.actions
+            .iter()
+            .find(|(state, cmd, _)| *state == busy_state && *cmd == command)
+            .map(|(_, _, action)| action)
+    }
+}
@@ -0,0 +1,358 @@
+use super::protocol::*;
+use super::protocol_buffer::{DecodeEvent, ProtocolBuffer};
+use std::io::Read;
+
+#[derive(Debug)]
+/// The error type for encoding & decoding frames.
+pub enum CodecErrors<P: Protocol> {
+    /// A header could not be parsed (the header bytes are included).
+    /// The stream cannot be decoded any further, since the frame boundaries are lost.
+    ParseHeaderFailed((ParseHeaderError, Vec<u8>)),
+    /// The stream ended during a frame. The command is None if the header is incomplete.
+    TruncatedMessage((Option<P::Commands>, Vec<u8>)),
+    /// Failed to construct the frame. This indicates that the payload is too long for the protocol or that the protocol implementation has a flaw.
+    MessageConstructionFailed(P::Commands),
+    /// Reading or writing failed.
+    IoError(std::io::Error),
+}
+impl<P: Protocol> From<std::io::Error> for CodecErrors<P> {
+    fn from(err: std::io::Error) -> Self {
+        CodecErrors::IoError(err)
+    }
+}
+
+/// This splits a byte stream into frames (command & payload), independent of the transport.
+///
+/// Bytes are pushed in arbitrarily sized chunks (see push) and complete frames are pulled (see next_frame), or both at once (see decode).
+/// Bytes of incomplete frames are kept until the rest arrives.
+/// If a header cannot be parsed, the frame boundaries are lost: the error is returned by every further call to next_frame, until reset is called.
+///
+/// Large payloads can be streamed instead of being buffered as a whole (see set_streaming_threshold & next_event).
+/// # Example
+/// ```
+/// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+/// # use doc_setup::*;
+/// # let bytes_from_serial_port = [0, 0, 2, b'0', b'0', b'g', b'o'];
+/// # let more_bytes_from_serial_port = [0, 0, 0, b'4', b'2'];
+/// let mut decoder = Decoder::<ProtocolExample>::new();
+/// decoder.push(&bytes_from_serial_port);
+/// while let Some((command, payload)) = decoder.next_frame().expect("parsing a header failed") {
+///     // ...
+/// }
+/// // or
+/// for frame in decoder.decode(&more_bytes_from_serial_port) {
+///     let (command, payload) = frame.expect("parsing a header failed");
+///     // ...
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Decoder<P: Protocol> {
+    protocol_buffer: ProtocolBuffer<P>,
+    failed: Option<(ParseHeaderError, Vec<u8>)>,
+    streaming_threshold: Option<usize>,
+}
+impl<P: Protocol> Default for Decoder<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<P: Protocol> Decoder<P> {
+    /// This constructs a decoder without buffered bytes.
+    pub fn new() -> Self {
+        Self {
+            protocol_buffer: ProtocolBuffer::new(),
+            failed: None,
+            streaming_threshold: None,
+        }
+    }
+    /// This appends bytes to the stream. Decoding happens in next_frame.
+    pub fn push(&mut self, bytes: &[u8]) {
+        if self.failed.is_none() {
+            self.protocol_buffer.push(bytes);
+        }
+    }
+    /// This returns the next complete frame, or None if more bytes are needed.
+    /// The streaming threshold is ignored, but a frame which is already streamed (see next_event) is skipped.
+    pub fn next_frame(&mut self) -> Result<Option<Message<P>>, CodecErrors<P>> {
+        if let Some(failed) = &self.failed {
+            return Err(CodecErrors::ParseHeaderFailed(failed.clone()));
+        }
+        match self.protocol_buffer.next_message() {
+            Ok(frame) => Ok(frame),
+            Err(failed) => {
+                self.failed = Some(failed.clone());
+                Err(CodecErrors::ParseHeaderFailed(failed))
+            }
+        }
+    }
+    /// This appends the bytes & returns an iterator over all frames which are complete (see next_frame).
+    /// A parse error is the last item. Frames not taken from the iterator are returned by the next call.
+    pub fn decode(&mut self, bytes: &[u8]) -> DecodedMessages<'_, P> {
+        self.push(bytes);
+        DecodedMessages {
+            decoder: self,
+            failed: false,
+        }
+    }
+    /// This sets the payload length from which on frames are streamed by next_event (None disables streaming, which is the default).
+    pub fn set_streaming_threshold(&mut self, threshold: Option<usize>) {
+        self.streaming_threshold = threshold;
+    }
+    /// This returns the next part of the stream, or None if more bytes are needed.
+    /// Frames with a payload below the streaming threshold are returned completely.
+    /// For larger ones, the header is returned first, followed by the payload in chunks as the bytes are pushed.
+    pub fn next_event(&mut self) -> Result<Option<DecodeEvent<P>>, CodecErrors<P>> {
+        if let Some(failed) = &self.failed {
+            return Err(CodecErrors::ParseHeaderFailed(failed.clone()));
+        }
+        match self.protocol_buffer.next_event(self.streaming_threshold) {
+            Ok(event) => Ok(event),
+            Err(failed) => {
+                self.failed = Some(failed.clone());
+                Err(CodecErrors::ParseHeaderFailed(failed))
+            }
+        }
+    }
+    /// This returns the count of buffered bytes, which are not yet returned as part of a frame.
+    pub fn buffered_len(&self) -> usize {
+        self.protocol_buffer.buffered_len()
+    }
+    /// This is to be called at the end of the stream: it returns an error if the stream ended during a frame.
+    /// Afterwards, the decoder is reset.
+    pub fn finish(&mut self) -> Result<(), CodecErrors<P>> {
+        self.failed = None;
+        match self.protocol_buffer.take_truncated_message() {
+            Some(truncated_message) => Err(CodecErrors::TruncatedMessage(truncated_message)),
+            None => Ok(()),
+        }
+    }
+    /// This drops all buffered bytes and a previous parse error, so that a new stream can be decoded.
+    pub fn reset(&mut self) {
+        self.protocol_buffer = ProtocolBuffer::new();
+        self.failed = None;
+    }
+}
+
+/// The frames decoded from a chunk of bytes (see Decoder::decode).
+/// A parse error is the last item.
+#[derive(Debug)]
+pub struct DecodedMessages<'a, P: Protocol> {
+    decoder: &'a mut Decoder<P>,
+    failed: bool,
+}
+impl<'a, P: Protocol> Iterator for DecodedMessages<'a, P> {
+    type Item = Result<Message<P>, CodecErrors<P>>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.decoder.next_frame() {
+            Ok(frame) => frame.map(Ok),
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+impl<'a, P: Protocol> std::iter::FusedIterator for DecodedMessages<'a, P> {}
+
+/// This encodes frames (command & payload) into a caller-provided buffer, independent of the transport.
+/// # Example
+/// ```
+/// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+/// # use doc_setup::*;
+/// # use std::io::Write;
+/// # let mut serial_port = Vec::new();
+/// let encoder = Encoder::<ProtocolExample>::new();
+/// let mut bytes = Vec::new();
+/// encoder.encode(CommandsExample::Start, b"go", &mut bytes).expect("encoding failed");
+/// encoder.encode(CommandsExample::Funny, b"", &mut bytes).expect("encoding failed");
+/// serial_port.write_all(&bytes).expect("writing failed");
+/// ```
+#[derive(Debug)]
+pub struct Encoder<P: Protocol> {
+    protocol: std::marker::PhantomData<P>,
+}
+impl<P: Protocol> Default for Encoder<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<P: Protocol> Encoder<P> {
+    /// This constructs an encoder.
+    pub fn new() -> Self {
+        Self {
+            protocol: std::marker::PhantomData,
+        }
+    }
+    /// This appends the frame to the buffer. On failure, the buffer is unchanged.
+    pub fn encode(
+        &self,
+        command: P::Commands,
+        payload: &[u8],
+        buffer: &mut Vec<u8>,
+    ) -> Result<(), CodecErrors<P>> {
+        let header = P::construct_message_header(command, payload)
+            .ok_or(CodecErrors::MessageConstructionFailed(command))?;
+        buffer.reserve(header.len() + payload.len());
+        buffer.extend_from_slice(&header);
+        buffer.extend_from_slice(payload);
+        Ok(())
+    }
+    /// This appends the header of a frame with the given payload length to the buffer. The payload has to follow.
+    /// This allows to send payloads which are not in memory as a whole (the protocol has to implement Protocol::length_to_array).
+    pub fn encode_header(
+        &self,
+        command: P::Commands,
+        length: usize,
+        buffer: &mut Vec<u8>,
+    ) -> Result<(), CodecErrors<P>> {
+        let header = P::construct_header_for_length(command, length)
+            .ok_or(CodecErrors::MessageConstructionFailed(command))?;
+        buffer.extend_from_slice(&header);
+        Ok(())
+    }
+}
+
+/// This reads frames from a reader (for example a file, a serial port or a recorded capture).
+/// It is also an iterator over the frames, which ends at the end of the stream (or after the first error).
+/// # Example
+/// ```no_run
+/// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+/// # use doc_setup::*;
+/// let file = std::fs::File::open("capture.bin").expect("opening failed");
+/// for frame in FrameReader::<_, ProtocolExample>::new(file) {
+///     let (command, payload) = frame.expect("decoding failed");
+///     // ...
+/// }
+/// ```
+#[derive(Debug)]
+pub struct FrameReader<R, P: Protocol> {
+    reader: R,
+    decoder: Decoder<P>,
+    buffer: Vec<u8>,
+    done: bool,
+}
+impl<R: Read, P: Protocol> FrameReader<R, P> {
+    /// This wraps the given reader.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            decoder: Decoder::new(),
+            buffer: vec![0; 4096],
+            done: false,
+        }
+    }
+    /// This returns the next frame, or None at the end of the stream.
+    /// If the stream ends during a frame, a TruncatedMessage error is returned.
+    pub fn read_frame(&mut self) -> Result<Option<Message<P>>, CodecErrors<P>> {
+        loop {
+            if let Some(frame) = self.decoder.next_frame()? {
+                return Ok(Some(frame));
+            }
+            let length = match self.reader.read(&mut self.buffer) {
+                Ok(length) => length,
+                Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(CodecErrors::IoError(err)),
+            };
+            if length == 0 {
+                self.decoder.finish()?;
+                return Ok(None);
+            }
+            self.decoder.push(&self.buffer[..length]);
+        }
+    }
+    /// This returns a reference to the reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+    /// This returns the reader. Buffered bytes are lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+impl<R: Read, P: Protocol> Iterator for FrameReader<R, P> {
+    type Item = Result<Message<P>, CodecErrors<P>>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_frame() {
+            Ok(Some(frame)) => Some(Ok(frame)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio-codec")]
+/// An adapter to tokio_util::codec, for example to use a protocol with tokio_util::codec::Framed.
+/// It decodes frames (see Decoder) and encodes frames (like Encoder).
+/// # Example
+/// ```
+/// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+/// # use doc_setup::*;
+/// use tokio_util::codec::{Decoder as _, Encoder as _};
+/// // typically used via tokio_util::codec::Framed::new(stream, TokioCodec::<ProtocolExample>::new())
+/// let mut codec = TokioCodec::<ProtocolExample>::new();
+/// let mut bytes = bytes::BytesMut::new();
+/// codec.encode((CommandsExample::Start, b"go".to_vec()), &mut bytes).expect("encoding failed");
+/// let frame = codec.decode(&mut bytes).expect("decoding failed");
+/// assert_eq!(frame, Some((CommandsExample::Start, b"go".to_vec())));
+/// ```
+#[derive(Debug)]
+pub struct TokioCodec<P: Protocol> {
+    decoder: Decoder<P>,
+}
+#[cfg(feature = "tokio-codec")]
+impl<P: Protocol> Default for TokioCodec<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+#[cfg(feature = "tokio-codec")]
+impl<P: Protocol> TokioCodec<P> {
+    /// This constructs a codec without buffered bytes.
+    pub fn new() -> Self {
+        Self {
+            decoder: Decoder::new(),
+        }
+    }
+}
+#[cfg(feature = "tokio-codec")]
+impl<P: Protocol> tokio_util::codec::Decoder for TokioCodec<P> {
+    type Item = Message<P>;
+    type Error = CodecErrors<P>;
+    fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        // the bytes are moved into the decoder, which keeps incomplete frames
+        self.decoder.push(src);
+        src.clear();
+        self.decoder.next_frame()
+    }
+    fn decode_eof(&mut self, src: &mut bytes::BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decode(src)? {
+            Some(frame) => Ok(Some(frame)),
+            None => self.decoder.finish().map(|_| None),
+        }
+    }
+}
+#[cfg(feature = "tokio-codec")]
+impl<P: Protocol> tokio_util::codec::Encoder<Message<P>> for TokioCodec<P> {
+    type Error = CodecErrors<P>;
+    fn encode(&mut self, item: Message<P>, dst: &mut bytes::BytesMut) -> Result<(), Self::Error> {
+        let (command, payload) = item;
+        let header = P::construct_message_header(command, &payload)
+            .ok_or(CodecErrors::MessageConstructionFailed(command))?;
+        dst.reserve(header.len() + payload.len());
+        dst.extend_from_slice(&header);
+        dst.extend_from_slice(&payload);
+        Ok(())
+    }
+}
@@ -0,0 +1,215 @@
+use super::protocol::*;
+use super::tcp_ipc::*;
+use log::*;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+type Handler<P> =
+    Arc<dyn Fn(<P as Protocol>::Commands, Vec<u8>, &Responder<P>) + Send + Sync + 'static>;
+type Predicate<P> = Box<dyn Fn(&<P as Protocol>::Commands) -> bool + Send + Sync + 'static>;
+type Job<P> = (Handler<P>, Message<P>);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The result type for a reply via a Responder
+pub enum ReplyResult {
+    /// The reply was queued and will be sent by the dispatcher
+    Success,
+    /// The dispatcher is not running anymore
+    Disconnected,
+}
+
+/// This is handed to each handler of a Dispatcher to send replies.
+/// The replies are queued and written by the dispatcher.
+/// It can be cloned, for example to reply later from another thread.
+pub struct Responder<P: Protocol> {
+    reply_sender: Sender<Message<P>>,
+}
+impl<P: Protocol> Clone for Responder<P> {
+    fn clone(&self) -> Self {
+        Self {
+            reply_sender: self.reply_sender.clone(),
+        }
+    }
+}
+impl<P: Protocol> Responder<P> {
+    /// This queues a reply, consisting of a command & a payload/message.
+    pub fn reply(&self, command: P::Commands, message: Vec<u8>) -> ReplyResult {
+        match self.reply_sender.send((command, message)) {
+            Ok(()) => ReplyResult::Success,
+            Err(_) => ReplyResult::Disconnected,
+        }
+    }
+}
+
+#[derive(Debug)]
+/// The error type for running a Dispatcher
+pub enum DispatchErrors<P: Protocol> {
+    /// Receiving a message failed. For example, this happens if the peer closed the connection.
+    ReceiveFailed(ReadThreadErrors<P>),
+    /// Writing a reply failed.
+    ReplyFailed(WriteMessageErrors),
+}
+
+/// This is a small command server on top of a TcpIpc.
+/// Handlers are registered per command (or per command predicate).
+/// Each received message is passed to the first matching handler, together with a Responder to send replies.
+/// Messages without a matching handler are passed to the fallback handler (or dropped, if there is none).
+///
+/// The handlers are run on the thread calling 'run', or on a pool of worker threads (see 'worker_threads').
+/// # Example
+/// ```no_run
+/// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+/// # use doc_setup::*;
+/// # let mut server = server();
+/// let dispatcher = Dispatcher::<ProtocolExample>::new()
+///     .on(CommandsExample::Start, |_command, message, responder| {
+///         responder.reply(CommandsExample::Funny, message);
+///     })
+///     .fallback(|command, _message, _responder| println!("unhandled: {:?}", command))
+///     .worker_threads(4);
+/// let stop_handle = CancelHandle::new();
+/// dispatcher.run(&mut server, &stop_handle, Some(std::time::Duration::from_micros(100)));
+/// ```
+pub struct Dispatcher<P: Protocol> {
+    routes: Vec<(Predicate<P>, Handler<P>)>,
+    fallback: Option<Handler<P>>,
+    worker_threads: usize,
+}
+impl<P: Protocol> Default for Dispatcher<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<P: Protocol> Dispatcher<P> {
+    /// This constructs a dispatcher without handlers, which runs on the caller's thread.
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            fallback: None,
+            worker_threads: 0,
+        }
+    }
+    /// This registers a handler for the given command.
+    pub fn on<F>(self, command: P::Commands, handler: F) -> Self
+    where
+        F: Fn(P::Commands, Vec<u8>, &Responder<P>) + Send + Sync + 'static,
+    {
+        self.on_matching(move |received| *received == command, handler)
+    }
+    /// This registers a handler for all commands for which the predicate is true.
+    pub fn on_matching<Q, F>(mut self, predicate: Q, handler: F) -> Self
+    where
+        Q: Fn(&P::Commands) -> bool + Send + Sync + 'static,
+        F: Fn(P::Commands, Vec<u8>, &Responder<P>) + Send + Sync + 'static,
+    {
+        self.routes.push((Box::new(predicate), Arc::new(handler)));
+        self
+    }
+    /// This registers the handler for messages without a matching handler.
+    pub fn fallback<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(P::Commands, Vec<u8>, &Responder<P>) + Send + Sync + 'static,
+    {
+        self.fallback = Some(Arc::new(handler));
+        self
+    }
+    /// This sets the number of worker threads the handlers are run on.
+    /// Zero (the default) runs the handlers on the thread calling 'run'.
+    pub fn worker_threads(mut self, worker_threads: usize) -> Self {
+        self.worker_threads = worker_threads;
+        self
+    }
+    fn handler(&self, command: &P::Commands) -> Option<Handler<P>> {
+        self.routes
+            .iter()
+            .find(|(predicate, _)| predicate(command))
+            .map(|(_, handler)| handler.clone())
+            .or_else(|| self.fallback.clone())
+    }
+    /// This receives messages from the TcpIpc and dispatches them, until the stop handle is cancelled or an error occurs.
+    /// Replies are written after each iteration.
+    /// If no message is available, the thread sleeps for 'iteration_wait_time'.
+    /// When stopping, all worker threads are finished and their remaining replies are written.
+    pub fn run(
+        &self,
+        tcp_ipc: &mut TcpIpc<P>,
+        stop_handle: &CancelHandle,
+        iteration_wait_time: Option<std::time::Duration>,
+    ) -> Result<(), DispatchErrors<P>> {
+        let (reply_sender, reply_receiver) = std::sync::mpsc::channel();
+        let responder = Responder { reply_sender };
+        let (job_sender, workers) = self.start_workers(&responder);
+        let result = loop {
+            if stop_handle.is_cancelled() {
+                break Ok(());
+            }
+            if let Err(err) = write_replies(tcp_ipc, &reply_receiver) {
+                break Err(err);
+            }
+            match tcp_ipc.get_message() {
+                Ok(Some((command, message))) => match self.handler(&command) {
+                    Some(handler) => match &job_sender {
+                        Some(job_sender) => {
+                            // the workers only stop if the job sender is dropped
+                            let _ = job_sender.send((handler, (command, message)));
+                        }
+                        None => handler(command, message, &responder),
+                    },
+                    None => warn!("No handler for command: {:?}", command),
+                },
+                Ok(None) => {
+                    if let Some(iteration_wait_time) = iteration_wait_time {
+                        std::thread::sleep(iteration_wait_time)
+                    }
+                }
+                Err(err) => break Err(DispatchErrors::ReceiveFailed(err)),
+            }
+        };
+        drop(job_sender);
+        for worker in workers {
+            if worker.join().is_err() {
+                warn!("Dispatcher worker thread panicked");
+            }
+        }
+        result.and(write_replies(tcp_ipc, &reply_receiver))
+    }
+    fn start_workers(
+        &self,
+        responder: &Responder<P>,
+    ) -> (Option<Sender<Job<P>>>, Vec<std::thread::JoinHandle<()>>) {
+        if self.worker_threads == 0 {
+            return (None, Vec::new());
+        }
+        let (job_sender, job_receiver) = std::sync::mpsc::channel::<Job<P>>();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let workers = (0..self.worker_threads)
+            .map(|_| {
+                let job_receiver = job_receiver.clone();
+                let responder = responder.clone();
+                std::thread::spawn(move || loop {
+                    let job = match job_receiver.lock() {
+                        Ok(job_receiver) => job_receiver.recv(),
+                        Err(_) => break,
+                    };
+                    match job {
+                        Ok((handler, (command, message))) => handler(command, message, &responder),
+                        Err(std::sync::mpsc::RecvError) => break,
+                    }
+                })
+            })
+            .collect();
+        (Some(job_sender), workers)
+    }
+}
+
+fn write_replies<P: Protocol>(
+    tcp_ipc: &mut TcpIpc<P>,
+    reply_receiver: &Receiver<Message<P>>,
+) -> Result<(), DispatchErrors<P>> {
+    for (command, message) in reply_receiver.try_iter() {
+        tcp_ipc
+            .write_message(command, &message)
+            .map_err(DispatchErrors::ReplyFailed)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,757 @@
+//! A protocol which is described at runtime instead of being compiled in.
+//! Since the functions of the Protocol trait carry no state, the description is installed into a slot type (see DescriptionSlot).
+//! With the feature "protocol-files", descriptions can be loaded from TOML or JSON.
+use super::protocol::*;
+#[cfg(feature = "protocol-files")]
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+use std::sync::OnceLock;
+
+// the widest command & binary length fields which are supported, in bytes
+const MAXIMAL_FIELD_WIDTH: usize = 8;
+// the widest ASCII encoded length field which is supported (which still fits into an u64), in digits
+const MAXIMAL_ASCII_LENGTH_WIDTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(
+    feature = "protocol-files",
+    derive(Deserialize, Serialize),
+    serde(rename_all = "snake_case")
+)]
+/// The order of command & length in the header.
+pub enum HeaderOrder {
+    /// The header starts with the command, followed by the length.
+    CommandFirst,
+    /// The header starts with the length, followed by the command.
+    LengthFirst,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(
+    feature = "protocol-files",
+    derive(Deserialize, Serialize),
+    serde(rename_all = "snake_case")
+)]
+/// The encoding of the payload length.
+pub enum LengthEncoding {
+    /// Binary, the most significant byte comes first.
+    BigEndian,
+    /// Binary, the least significant byte comes first.
+    LittleEndian,
+    /// ASCII decimal digits, like "00042" (leading spaces are accepted when parsing).
+    AsciiDecimal,
+    /// ASCII hexadecimal digits, like "002a" (leading spaces are accepted when parsing).
+    AsciiHex,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "protocol-files", derive(Deserialize, Serialize))]
+/// A named command of a ProtocolDescription.
+pub struct CommandDescription {
+    /// The name of the command, like "Start".
+    pub name: String,
+    /// The bytes representing the command. Their count has to match the command width.
+    /// In description files, this is given as text (like "00") or as an array of bytes.
+    #[cfg_attr(feature = "protocol-files", serde(with = "bytes_or_text"))]
+    pub value: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "protocol-files", derive(Deserialize, Serialize))]
+/// An immediate reply of a ProtocolDescription, see Protocol::message_is_answered_via_immediate_route.
+/// The first matching reply is sent.
+pub struct ImmediateReplyDescription {
+    /// The name of the command which is answered.
+    pub command: String,
+    /// If given, only messages with exactly this payload are answered.
+    #[cfg_attr(
+        feature = "protocol-files",
+        serde(default, with = "optional_bytes_or_text")
+    )]
+    pub payload: Option<Vec<u8>>,
+    /// The names of the busy states during which the command is answered. If this is empty, the command is always answered.
+    #[cfg_attr(feature = "protocol-files", serde(default))]
+    pub busy_states: Vec<String>,
+    /// The name of the reply's command.
+    pub reply_command: String,
+    /// The reply's payload.
+    #[cfg_attr(feature = "protocol-files", serde(default, with = "bytes_or_text"))]
+    pub reply_payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "protocol-files", derive(Deserialize, Serialize))]
+/// This describes a protocol at runtime, see DynamicProtocol.
+/// # Example
+/// ```
+/// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+/// # use doc_setup::*;
+/// let description = ProtocolDescription {
+///     command_width: 2,
+///     length_width: 3,
+///     order: HeaderOrder::LengthFirst,
+///     length_encoding: LengthEncoding::BigEndian,
+///     commands: vec![
+///         CommandDescription { name: "Ping".to_string(), value: b"00".to_vec() },
+///         CommandDescription { name: "Pong".to_string(), value: b"01".to_vec() },
+///     ],
+///     accept_unknown_commands: false,
+///     busy_states: vec!["Idle".to_string(), "Working".to_string()],
+///     immediate_replies: vec![ImmediateReplyDescription {
+///         command: "Ping".to_string(),
+///         payload: None,
+///         busy_states: Vec::new(),
+///         reply_command: "Pong".to_string(),
+///         reply_payload: Vec::new(),
+///     }],
+/// };
+/// ```
+/// The same description in TOML (see ProtocolDescription::from_toml):
+/// ```text
+/// command_width = 2
+/// length_width = 3
+/// order = "length_first"
+/// length_encoding = "big_endian"
+/// busy_states = ["Idle", "Working"]
+///
+/// [[commands]]
+/// name = "Ping"
+/// value = "00"
+///
+/// [[commands]]
+/// name = "Pong"
+/// value = [48, 49]
+///
+/// [[immediate_replies]]
+/// command = "Ping"
+/// reply_command = "Pong"
+/// ```
+pub struct ProtocolDescription {
+    /// The number of bytes encoding the command (1 up to 8).
+    pub command_width: usize,
+    /// The number of bytes encoding the payload length (1 up to 8 for binary encodings, 1 up to 16 for ASCII encodings).
+    pub length_width: usize,
+    /// The order of command & length in the header.
+    pub order: HeaderOrder,
+    /// The encoding of the length.
+    pub length_encoding: LengthEncoding,
+    /// The known commands.
+    #[cfg_attr(feature = "protocol-files", serde(default))]
+    pub commands: Vec<CommandDescription>,
+    /// If true, commands which are not listed are accepted (and shown as hex).
+    /// Otherwise, receiving an unknown command is a parse error.
+    #[cfg_attr(feature = "protocol-files", serde(default))]
+    pub accept_unknown_commands: bool,
+    /// The names of the busy states. The first one is the idle state.
+    /// If this is empty, there is only the busy state "Idle".
+    #[cfg_attr(feature = "protocol-files", serde(default))]
+    pub busy_states: Vec<String>,
+    /// The messages which are answered immediately (in the given order of precedence).
+    #[cfg_attr(feature = "protocol-files", serde(default))]
+    pub immediate_replies: Vec<ImmediateReplyDescription>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The error type for an invalid ProtocolDescription.
+pub enum ProtocolDescriptionError {
+    /// The command width is zero or larger than 8 bytes.
+    InvalidCommandWidth(usize),
+    /// The length width is zero or too large for the length encoding.
+    InvalidLengthWidth(usize),
+    /// The value of the given command does not match the command width.
+    CommandWidthMismatch(String),
+    /// The given name is used for two commands.
+    DuplicateCommandName(String),
+    /// The value of the given command is used for two commands.
+    DuplicateCommandValue(String),
+    /// The given name is used for two busy states.
+    DuplicateBusyStateName(String),
+    /// An immediate reply refers to the given command, which is not listed.
+    UnknownCommand(String),
+    /// An immediate reply refers to the given busy state, which is not listed.
+    UnknownBusyState(String),
+    /// The slot already holds a description, which cannot be replaced since open connections rely on it (see DescriptionSlot::install).
+    AlreadyInstalled,
+}
+
+#[cfg(feature = "protocol-files")]
+#[derive(Debug)]
+/// The error type for loading a ProtocolDescription.
+pub enum ProtocolDescriptionLoadError {
+    /// Reading the file failed.
+    Io(std::io::Error),
+    /// The file extension is neither "toml" nor "json".
+    UnknownFileExtension,
+    /// The TOML description could not be parsed.
+    Toml(toml::de::Error),
+    /// The JSON description could not be parsed.
+    Json(serde_json::Error),
+    /// The description was parsed, but it is inconsistent.
+    Invalid(ProtocolDescriptionError),
+}
+
+impl ProtocolDescription {
+    /// This returns the size of the header, in bytes.
+    pub fn header_width(&self) -> usize {
+        self.command_width + self.length_width
+    }
+    /// This checks the description for consistency.
+    pub fn validate(&self) -> Result<(), ProtocolDescriptionError> {
+        if self.command_width == 0 || self.command_width > MAXIMAL_FIELD_WIDTH {
+            return Err(ProtocolDescriptionError::InvalidCommandWidth(
+                self.command_width,
+            ));
+        }
+        let maximal_length_width = match self.length_encoding {
+            LengthEncoding::BigEndian | LengthEncoding::LittleEndian => MAXIMAL_FIELD_WIDTH,
+            LengthEncoding::AsciiDecimal | LengthEncoding::AsciiHex => MAXIMAL_ASCII_LENGTH_WIDTH,
+        };
+        if self.length_width == 0 || self.length_width > maximal_length_width {
+            return Err(ProtocolDescriptionError::InvalidLengthWidth(
+                self.length_width,
+            ));
+        }
+        for (i, command) in self.commands.iter().enumerate() {
+            if command.value.len() != self.command_width {
+                return Err(ProtocolDescriptionError::CommandWidthMismatch(
+                    command.name.clone(),
+                ));
+            }
+            for other in &self.commands[..i] {
+                if other.name == command.name {
+                    return Err(ProtocolDescriptionError::DuplicateCommandName(
+                        command.name.clone(),
+                    ));
+                }
+                if other.value == command.value {
+                    return Err(ProtocolDescriptionError::DuplicateCommandValue(
+                        command.name.clone(),
+                    ));
+                }
+            }
+        }
+        for (i, busy_state) in self.busy_states.iter().enumerate() {
+            if self.busy_states[..i].contains(busy_state) {
+                return Err(ProtocolDescriptionError::DuplicateBusyStateName(
+                    busy_state.clone(),
+                ));
+            }
+        }
+        for reply in &self.immediate_replies {
+            for command in &[&reply.command, &reply.reply_command] {
+                if !self.commands.iter().any(|known| &known.name == *command) {
+                    return Err(ProtocolDescriptionError::UnknownCommand(
+                        command.to_string(),
+                    ));
+                }
+            }
+            for busy_state in &reply.busy_states {
+                if self.busy_state_index(busy_state).is_none() {
+                    return Err(ProtocolDescriptionError::UnknownBusyState(
+                        busy_state.clone(),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+    /// This parses (and validates) a description given in TOML.
+    #[cfg(feature = "protocol-files")]
+    pub fn from_toml(text: &str) -> Result<ProtocolDescription, ProtocolDescriptionLoadError> {
+        let description: ProtocolDescription =
+            toml::from_str(text).map_err(ProtocolDescriptionLoadError::Toml)?;
+        description
+            .validate()
+            .map_err(ProtocolDescriptionLoadError::Invalid)?;
+        Ok(description)
+    }
+    /// This parses (and validates) a description given in JSON.
+    #[cfg(feature = "protocol-files")]
+    pub fn from_json(text: &str) -> Result<ProtocolDescription, ProtocolDescriptionLoadError> {
+        let description: ProtocolDescription =
+            serde_json::from_str(text).map_err(ProtocolDescriptionLoadError::Json)?;
+        description
+            .validate()
+            .map_err(ProtocolDescriptionLoadError::Invalid)?;
+        Ok(description)
+    }
+    /// This loads (and validates) a description from a file, whose extension ("toml" or "json") determines the format.
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// let description = ProtocolDescription::load("device.toml").expect("loading the protocol failed");
+    /// DefaultSlot::install(description).expect("invalid description");
+    /// ```
+    #[cfg(feature = "protocol-files")]
+    pub fn load<T: AsRef<std::path::Path>>(
+        path: T,
+    ) -> Result<ProtocolDescription, ProtocolDescriptionLoadError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        let text = std::fs::read_to_string(path).map_err(ProtocolDescriptionLoadError::Io)?;
+        match extension.as_deref() {
+            Some("toml") => Self::from_toml(&text),
+            Some("json") => Self::from_json(&text),
+            _ => Err(ProtocolDescriptionLoadError::UnknownFileExtension),
+        }
+    }
+    fn busy_state_index(&self, name: &str) -> Option<usize> {
+        if self.busy_states.is_empty() && name == "Idle" {
+            Some(0)
+        } else {
+            self.busy_states
+                .iter()
+                .position(|busy_state| busy_state == name)
+        }
+    }
+    fn immediate_reply<S: DescriptionSlot>(
+        &self,
+        command: &DynamicCommand<S>,
+        message: &[u8],
+        busy_state: &DynamicBusyState<S>,
+    ) -> Option<(DynamicCommand<S>, Vec<u8>)> {
+        let name = self.command_name(command)?;
+        let reply = self.immediate_replies.iter().find(|reply| {
+            reply.command == name
+                && reply
+                    .payload
+                    .as_ref()
+                    .map_or(true, |payload| payload.as_slice() == message)
+                && (reply.busy_states.is_empty()
+                    || reply.busy_states.iter().any(|busy_state_name| {
+                        self.busy_state_index(busy_state_name) == Some(busy_state.index() as usize)
+                    }))
+        })?;
+        let reply_command = self
+            .commands
+            .iter()
+            .find(|command| command.name == reply.reply_command)?;
+        Some((
+            DynamicCommand::new(&reply_command.value)?,
+            reply.reply_payload.clone(),
+        ))
+    }
+    fn command_name<S: DescriptionSlot>(&self, command: &DynamicCommand<S>) -> Option<&str> {
+        self.commands
+            .iter()
+            .find(|description| description.value == command.as_bytes())
+            .map(|description| description.name.as_str())
+    }
+    fn parse_length(&self, length: &[u8]) -> Option<usize> {
+        let value = match self.length_encoding {
+            LengthEncoding::BigEndian => length
+                .iter()
+                .fold(0u64, |value, byte| value << 8 | u64::from(*byte)),
+            LengthEncoding::LittleEndian => length
+                .iter()
+                .rev()
+                .fold(0u64, |value, byte| value << 8 | u64::from(*byte)),
+            LengthEncoding::AsciiDecimal | LengthEncoding::AsciiHex => {
+                let radix = if self.length_encoding == LengthEncoding::AsciiHex {
+                    16
+                } else {
+                    10
+                };
+                let digits = std::str::from_utf8(length).ok()?.trim_start_matches(' ');
+                if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+                    return None;
+                }
+                u64::from_str_radix(digits, radix).ok()?
+            }
+        };
+        if value > usize::MAX as u64 {
+            None
+        } else {
+            Some(value as usize)
+        }
+    }
+    fn length_to_bytes(&self, length: usize) -> Option<Vec<u8>> {
+        let width = self.length_width;
+        let bytes = match self.length_encoding {
+            LengthEncoding::BigEndian | LengthEncoding::LittleEndian => {
+                let length = length as u64;
+                if width < MAXIMAL_FIELD_WIDTH && length >> (8 * width) != 0 {
+                    return None;
+                }
+                let mut bytes = length.to_be_bytes()[MAXIMAL_FIELD_WIDTH - width..].to_vec();
+                if self.length_encoding == LengthEncoding::LittleEndian {
+                    bytes.reverse();
+                }
+                bytes
+            }
+            LengthEncoding::AsciiDecimal => {
+                format!("{:0width$}", length, width = width).into_bytes()
+            }
+            LengthEncoding::AsciiHex => format!("{:0width$x}", length, width = width).into_bytes(),
+        };
+        if bytes.len() == width {
+            Some(bytes)
+        } else {
+            None
+        }
+    }
+}
+
+/// The storage of the description of a DynamicProtocol, typically an uninhabited enum.
+/// Each slot holds its own description, so several dynamic protocols can be used side by side in one process.
+/// The description is installed once per slot, so the framing of open connections never changes.
+/// # Example
+/// ```
+/// # use rust_tcp_ipc::{DescriptionCell, DescriptionSlot, DynamicProtocol};
+/// #[derive(Debug)]
+/// enum DeviceSlot {}
+/// impl DescriptionSlot for DeviceSlot {
+///     fn cell() -> &'static DescriptionCell {
+///         static CELL: DescriptionCell = DescriptionCell::new();
+///         &CELL
+///     }
+/// }
+/// type DeviceProtocol = DynamicProtocol<DeviceSlot>;
+/// ```
+pub trait DescriptionSlot: std::fmt::Debug + Sized + 'static {
+    /// This returns the cell holding the description of this slot, typically a static (see the example).
+    fn cell() -> &'static DescriptionCell;
+    /// This validates the given description and installs it into this slot.
+    /// If the slot already holds a description, ProtocolDescriptionError::AlreadyInstalled is returned.
+    fn install(description: ProtocolDescription) -> Result<(), ProtocolDescriptionError> {
+        description.validate()?;
+        Self::cell()
+            .description
+            .set(description)
+            .map_err(|_| ProtocolDescriptionError::AlreadyInstalled)
+    }
+    /// This returns the installed description, if any.
+    fn description() -> Option<&'static ProtocolDescription> {
+        Self::cell().description.get()
+    }
+    /// This looks up a command of the installed description by its name.
+    fn command_by_name(name: &str) -> Option<DynamicCommand<Self>> {
+        Self::description()?
+            .commands
+            .iter()
+            .find(|command| command.name == name)
+            .and_then(|command| DynamicCommand::new(&command.value))
+    }
+    /// This looks up a busy state of the installed description by its name.
+    fn busy_state_by_name(name: &str) -> Option<DynamicBusyState<Self>> {
+        Self::description()?
+            .busy_state_index(name)
+            .map(|index| DynamicBusyState::new(index as u16))
+    }
+}
+
+/// The cell of a DescriptionSlot, which holds at most one description.
+#[derive(Debug, Default)]
+pub struct DescriptionCell {
+    description: OnceLock<ProtocolDescription>,
+}
+impl DescriptionCell {
+    /// This constructs an empty cell (also usable for statics).
+    pub const fn new() -> Self {
+        Self {
+            description: OnceLock::new(),
+        }
+    }
+}
+
+/// The slot used by DynamicProtocol if no other slot is given.
+#[derive(Debug)]
+pub enum DefaultSlot {}
+impl DescriptionSlot for DefaultSlot {
+    fn cell() -> &'static DescriptionCell {
+        static CELL: DescriptionCell = DescriptionCell::new();
+        &CELL
+    }
+}
+
+/// A command of the DynamicProtocol, which stores the bytes representing it.
+/// The Debug representation shows the command's name, if it is listed in the description of the slot.
+pub struct DynamicCommand<S: DescriptionSlot = DefaultSlot> {
+    bytes: [u8; MAXIMAL_FIELD_WIDTH],
+    width: u8,
+    slot: PhantomData<fn() -> S>,
+}
+impl<S: DescriptionSlot> DynamicCommand<S> {
+    /// This constructs a command from its byte representation (1 up to 8 bytes).
+    pub fn new(bytes: &[u8]) -> Option<DynamicCommand<S>> {
+        if bytes.is_empty() || bytes.len() > MAXIMAL_FIELD_WIDTH {
+            return None;
+        }
+        let mut command = DynamicCommand {
+            bytes: [0; MAXIMAL_FIELD_WIDTH],
+            width: bytes.len() as u8,
+            slot: PhantomData,
+        };
+        command.bytes[..bytes.len()].copy_from_slice(bytes);
+        Some(command)
+    }
+    /// This returns the bytes representing the command.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.width as usize]
+    }
+    /// This returns the name of the command, if it is listed in the description of the slot.
+    pub fn name(&self) -> Option<String> {
+        S::description().and_then(|description| description.command_name(self).map(str::to_string))
+    }
+}
+// (implemented manually, since the slot type implements none of these traits)
+impl<S: DescriptionSlot> Clone for DynamicCommand<S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<S: DescriptionSlot> Copy for DynamicCommand<S> {}
+impl<S: DescriptionSlot> PartialEq for DynamicCommand<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+impl<S: DescriptionSlot> Eq for DynamicCommand<S> {}
+impl<S: DescriptionSlot> std::hash::Hash for DynamicCommand<S> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.as_bytes().hash(state)
+    }
+}
+impl<S: DescriptionSlot> std::fmt::Debug for DynamicCommand<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(name) = self.name() {
+            write!(f, "{}", name)
+        } else {
+            write!(f, "0x")?;
+            for byte in self.as_bytes() {
+                write!(f, "{:02x}", byte)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// A busy state of the DynamicProtocol, given by its index in the description of the slot.
+/// The Debug representation shows the busy state's name.
+pub struct DynamicBusyState<S: DescriptionSlot = DefaultSlot> {
+    index: u16,
+    slot: PhantomData<fn() -> S>,
+}
+impl<S: DescriptionSlot> DynamicBusyState<S> {
+    /// This constructs the busy state with the given index (0 is the idle state).
+    pub fn new(index: u16) -> Self {
+        Self {
+            index,
+            slot: PhantomData,
+        }
+    }
+    /// This returns the index of the busy state.
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+}
+impl<S: DescriptionSlot> Clone for DynamicBusyState<S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<S: DescriptionSlot> Copy for DynamicBusyState<S> {}
+impl<S: DescriptionSlot> PartialEq for DynamicBusyState<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+impl<S: DescriptionSlot> Eq for DynamicBusyState<S> {}
+impl<S: DescriptionSlot> std::hash::Hash for DynamicBusyState<S> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.index.hash(state)
+    }
+}
+impl<S: DescriptionSlot> std::fmt::Debug for DynamicBusyState<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = S::description().and_then(|description| {
+            description
+                .busy_states
+                .get(self.index as usize)
+                .map(String::to_string)
+        });
+        match (name, self.index) {
+            (Some(name), _) => write!(f, "{}", name),
+            (None, 0) => write!(f, "Idle"),
+            (None, index) => write!(f, "BusyState#{}", index),
+        }
+    }
+}
+
+/// This protocol is described at runtime by a ProtocolDescription, so tools can talk to a peer without compiling its protocol in.
+/// It is used with TcpIpc like any other protocol, but the description has to be installed into its slot before connecting (see DescriptionSlot).
+/// Otherwise, connecting fails with ConnectErrors::ProtocolNotReady & every header fails to parse.
+/// Several dynamic protocols are used side by side via different slot types.
+///
+/// The header is decoded & encoded as a whole (see parse_header & construct_header_for_length), so the split command & length representations of the Protocol trait are not used and given as ().
+/// # Example
+/// ```no_run
+/// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+/// # use doc_setup::*;
+/// # let description = description();
+/// # let config = config();
+/// DefaultSlot::install(description).expect("invalid description");
+/// let mut client = TcpIpc::<DynamicProtocol>::client("127.0.0.1:6666", config, None).expect("connecting failed");
+/// let start = DefaultSlot::command_by_name("Start").expect("unknown command");
+/// client.write_message(start, b"payload").expect("writing failed");
+/// ```
+#[derive(Debug)]
+pub struct DynamicProtocol<S: DescriptionSlot = DefaultSlot> {
+    // this type is never constructed, it only bundles the functions of the Protocol trait
+    slot: PhantomData<fn() -> S>,
+}
+
+impl<S: DescriptionSlot> Protocol for DynamicProtocol<S> {
+    type Commands = DynamicCommand<S>;
+    type BusyStates = DynamicBusyState<S>;
+    type CommandAsArray = ();
+    type LengthAsArray = ();
+    type HeaderAsArray = [u8];
+    fn idle() -> Self::BusyStates {
+        DynamicBusyState::new(0)
+    }
+    fn is_ready() -> bool {
+        S::description().is_some()
+    }
+    fn message_is_answered_via_immediate_route(
+        command: &Self::Commands,
+        message: &[u8],
+        busy_state: &Self::BusyStates,
+    ) -> Option<(Self::Commands, Vec<u8>)> {
+        S::description()?.immediate_reply(command, message, busy_state)
+    }
+    fn parse_command(_command: &Self::CommandAsArray) -> Option<Self::Commands> {
+        None
+    }
+    fn parse_length(_length: &Self::LengthAsArray) -> Option<usize> {
+        None
+    }
+    fn message_slice_to_header_array(input: &[u8]) -> Option<(&Self::HeaderAsArray, &[u8])> {
+        // without a description, the received bytes are handed to parse_header, which rejects them
+        let header_width = match S::description() {
+            Some(description) => description.header_width(),
+            None => input.len().max(1),
+        };
+        if input.len() >= header_width {
+            Some(input.split_at(header_width))
+        } else {
+            None
+        }
+    }
+    fn split_header_array(
+        _header: &Self::HeaderAsArray,
+    ) -> (&Self::CommandAsArray, &Self::LengthAsArray) {
+        (&(), &())
+    }
+    fn command_to_array(_command: Self::Commands) -> Self::CommandAsArray {}
+    fn get_length_as_array(
+        _command: Self::Commands,
+        _message: &[u8],
+    ) -> Option<Self::LengthAsArray> {
+        Some(())
+    }
+    fn length_to_array(_command: Self::Commands, length: usize) -> Option<Self::LengthAsArray> {
+        S::description()?.length_to_bytes(length).map(|_| ())
+    }
+    fn construct_header(_command: Self::CommandAsArray, _length: Self::LengthAsArray) -> Vec<u8> {
+        Vec::new()
+    }
+    fn parse_header(
+        header: &Self::HeaderAsArray,
+    ) -> Result<(Self::Commands, usize), (ParseHeaderError, &Self::HeaderAsArray)> {
+        let description = match S::description() {
+            Some(description) => description,
+            None => return Err((ParseHeaderError::CommandParseFailed, header)),
+        };
+        let (command, length) = match description.order {
+            HeaderOrder::CommandFirst => header.split_at(description.command_width),
+            HeaderOrder::LengthFirst => {
+                let (length, command) = header.split_at(description.length_width);
+                (command, length)
+            }
+        };
+        let command = match DynamicCommand::new(command) {
+            Some(command)
+                if description.accept_unknown_commands
+                    || description.command_name(&command).is_some() =>
+            {
+                command
+            }
+            _ => return Err((ParseHeaderError::CommandParseFailed, header)),
+        };
+        match description.parse_length(length) {
+            Some(length) => Ok((command, length)),
+            None => Err((ParseHeaderError::LengthParseFailed, header)),
+        }
+    }
+    fn construct_header_for_length(command: Self::Commands, length: usize) -> Option<Vec<u8>> {
+        let description = S::description()?;
+        if command.as_bytes().len() != description.command_width {
+            return None;
+        }
+        let length = description.length_to_bytes(length)?;
+        let mut header = Vec::with_capacity(description.header_width());
+        match description.order {
+            HeaderOrder::CommandFirst => {
+                header.extend_from_slice(command.as_bytes());
+                header.extend_from_slice(&length);
+            }
+            HeaderOrder::LengthFirst => {
+                header.extend_from_slice(&length);
+                header.extend_from_slice(command.as_bytes());
+            }
+        }
+        Some(header)
+    }
+    fn construct_message_header(command: Self::Commands, message: &[u8]) -> Option<Vec<u8>> {
+        Self::construct_header_for_length(command, message.len())
+    }
+}
+
+// (de)serializes bytes either as text or as an array of bytes
+#[cfg(feature = "protocol-files")]
+mod bytes_or_text {
+    use serde::{Deserialize, Deserializer, Serializer};
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BytesOrText {
+        Text(String),
+        Bytes(Vec<u8>),
+    }
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => serializer.serialize_str(text),
+            Err(_) => serializer.collect_seq(bytes),
+        }
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        Ok(match BytesOrText::deserialize(deserializer)? {
+            BytesOrText::Text(text) => text.into_bytes(),
+            BytesOrText::Bytes(bytes) => bytes,
+        })
+    }
+}
+#[cfg(feature = "protocol-files")]
+mod optional_bytes_or_text {
+    use serde::{Deserialize, Deserializer, Serializer};
+    #[derive(Deserialize)]
+    struct Bytes(#[serde(with = "super::bytes_or_text")] Vec<u8>);
+    pub fn serialize<S: Serializer>(
+        bytes: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => super::bytes_or_text::serialize(bytes, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        Ok(Option::<Bytes>::deserialize(deserializer)?.map(|bytes| bytes.0))
+    }
+}
@@ -0,0 +1,323 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A deterministic program of faults for one direction of a connection, for resilience testing.
+/// It is applied to a TcpIpc (see TcpIpc::set_incoming_faults & TcpIpc::set_outgoing_faults) or to any stream (see FaultyStream).
+///
+/// The faults are applied in the following order to each chunk of bytes passing (each write, or each read, respectively):
+/// 1. coalescing: the bytes of several chunks are held back & passed on together
+/// 2. bit flips: bits at the given stream offsets are flipped
+/// 3. disconnect: the stream is cut after the given number of bytes
+/// 4. fragmentation: the bytes are split into chunks of the given sizes
+/// 5. delay: each resulting chunk is delayed
+///
+/// This is only available with the feature "testing".
+/// # Example
+/// ```no_run
+/// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+/// # use doc_setup::*;
+/// # let mut client = client();
+/// // split every frame into single bytes, flip the lowest bit of the 10th byte & drop the connection after 100 bytes
+/// let plan = FaultPlan::new().fragment(vec![1]).flip_bit(9, 0).disconnect_after(100);
+/// client.set_outgoing_faults(Some(plan));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FaultPlan {
+    fragment_sizes: Vec<usize>,
+    coalesce: usize,
+    delay: Option<Duration>,
+    disconnect_after: Option<usize>,
+    bit_flips: Vec<(usize, u8)>,
+}
+impl FaultPlan {
+    /// This constructs a plan without any faults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// This splits the bytes into chunks of the given sizes, which are used cyclically (for example [1, 3] gives chunks of 1, 3, 1, 3, ... bytes).
+    /// Sizes of zero are ignored.
+    pub fn fragment(mut self, sizes: Vec<usize>) -> Self {
+        self.fragment_sizes = sizes.into_iter().filter(|size| *size > 0).collect();
+        self
+    }
+    /// This holds back the bytes until the given count of chunks (typically frames) is collected, which are then passed on together.
+    /// Bytes which are held back when the connection closes are lost.
+    pub fn coalesce(mut self, chunks: usize) -> Self {
+        self.coalesce = chunks;
+        self
+    }
+    /// This delays each chunk by the given time.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+    /// This drops the connection after the given count of bytes passed (which can be in the middle of a frame).
+    pub fn disconnect_after(mut self, bytes: usize) -> Self {
+        self.disconnect_after = Some(bytes);
+        self
+    }
+    /// This flips the given bit (0 is the least significant one) of the byte at the given stream offset (counted from the start of the connection).
+    pub fn flip_bit(mut self, offset: usize, bit: u8) -> Self {
+        self.bit_flips.push((offset, bit % 8));
+        self
+    }
+}
+
+// the bytes to be passed on after applying a fault plan
+#[derive(Debug)]
+pub(crate) struct FaultOutput {
+    pub(crate) chunks: Vec<Vec<u8>>,
+    pub(crate) delay: Option<Duration>,
+    // the connection has to be dropped after passing on the chunks
+    pub(crate) disconnect: bool,
+}
+impl FaultOutput {
+    // writes the chunks (with delays), returns true if the connection is to be dropped afterwards
+    pub(crate) fn write_to<W: Write>(self, writer: &mut W) -> std::io::Result<bool> {
+        for chunk in self.chunks {
+            if let Some(delay) = self.delay {
+                std::thread::sleep(delay);
+            }
+            writer.write_all(&chunk)?;
+            writer.flush()?;
+        }
+        Ok(self.disconnect)
+    }
+}
+
+// the state of a fault plan applied to a stream
+#[derive(Debug)]
+pub(crate) struct FaultInjector {
+    plan: FaultPlan,
+    position: usize,
+    fragment_index: usize,
+    held: Vec<u8>,
+    held_count: usize,
+    disconnected: bool,
+}
+impl FaultInjector {
+    pub(crate) fn new(plan: FaultPlan) -> Self {
+        Self {
+            plan,
+            position: 0,
+            fragment_index: 0,
+            held: Vec::new(),
+            held_count: 0,
+            disconnected: false,
+        }
+    }
+    pub(crate) fn apply(&mut self, bytes: &[u8]) -> FaultOutput {
+        let mut output = FaultOutput {
+            chunks: Vec::new(),
+            delay: self.plan.delay,
+            disconnect: self.disconnected,
+        };
+        if self.disconnected {
+            return output;
+        }
+        self.held.extend_from_slice(bytes);
+        self.held_count += 1;
+        if self.held_count < self.plan.coalesce {
+            return output;
+        }
+        self.held_count = 0;
+        let mut bytes = std::mem::take(&mut self.held);
+        for &(offset, bit) in &self.plan.bit_flips {
+            if offset >= self.position && offset < self.position + bytes.len() {
+                bytes[offset - self.position] ^= 1 << bit;
+            }
+        }
+        if let Some(disconnect_after) = self.plan.disconnect_after {
+            if self.position + bytes.len() >= disconnect_after {
+                bytes.truncate(disconnect_after.saturating_sub(self.position));
+                self.disconnected = true;
+                output.disconnect = true;
+            }
+        }
+        self.position += bytes.len();
+        if self.plan.fragment_sizes.is_empty() {
+            if !bytes.is_empty() {
+                output.chunks.push(bytes);
+            }
+        } else {
+            let mut remaining = bytes.as_slice();
+            while !remaining.is_empty() {
+                let size = self.plan.fragment_sizes[self.fragment_index].min(remaining.len());
+                self.fragment_index = (self.fragment_index + 1) % self.plan.fragment_sizes.len();
+                output.chunks.push(remaining[..size].to_vec());
+                remaining = &remaining[size..];
+            }
+        }
+        output
+    }
+}
+
+// the fault plans of a connection, shared between the main thread & the read thread
+#[derive(Debug, Clone)]
+pub(crate) struct FaultSlot {
+    incoming: Arc<Mutex<Option<FaultInjector>>>,
+    outgoing: Arc<Mutex<Option<FaultInjector>>>,
+}
+impl FaultSlot {
+    pub(crate) fn new() -> Self {
+        Self {
+            incoming: Arc::new(Mutex::new(None)),
+            outgoing: Arc::new(Mutex::new(None)),
+        }
+    }
+    pub(crate) fn set_incoming(&self, plan: Option<FaultPlan>) {
+        Self::set(&self.incoming, plan)
+    }
+    pub(crate) fn set_outgoing(&self, plan: Option<FaultPlan>) {
+        Self::set(&self.outgoing, plan)
+    }
+    pub(crate) fn has_outgoing(&self) -> bool {
+        match self.outgoing.lock() {
+            Ok(injector) => injector.is_some(),
+            Err(poisoned) => poisoned.into_inner().is_some(),
+        }
+    }
+    // returns None if no faults are to be injected
+    pub(crate) fn incoming(&self, bytes: &[u8]) -> Option<FaultOutput> {
+        Self::apply(&self.incoming, bytes)
+    }
+    // returns None if no faults are to be injected
+    pub(crate) fn outgoing(&self, bytes: &[u8]) -> Option<FaultOutput> {
+        Self::apply(&self.outgoing, bytes)
+    }
+    fn set(injector: &Mutex<Option<FaultInjector>>, plan: Option<FaultPlan>) {
+        let plan = plan.map(FaultInjector::new);
+        match injector.lock() {
+            Ok(mut injector) => *injector = plan,
+            Err(poisoned) => *poisoned.into_inner() = plan,
+        }
+    }
+    fn apply(injector: &Mutex<Option<FaultInjector>>, bytes: &[u8]) -> Option<FaultOutput> {
+        let mut injector = match injector.lock() {
+            Ok(injector) => injector,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        injector.as_mut().map(|injector| injector.apply(bytes))
+    }
+}
+
+/// A wrapper around a stream (or any reader/writer), which injects faults into the bytes read and written (see FaultPlan).
+/// Fragmentation is visible to the reader, since each fragment is returned by a separate read.
+/// After the disconnect point, reads return the end of the stream and writes fail with BrokenPipe (the inner stream itself is not closed).
+/// This is only available with the feature "testing".
+/// # Example
+/// ```no_run
+/// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+/// # use doc_setup::*;
+/// # use std::io::Write;
+/// # let frame = Vec::new();
+/// let stream = std::net::TcpStream::connect("127.0.0.1:6666").expect("connecting failed");
+/// let mut stream = FaultyStream::new(stream).write_faults(FaultPlan::new().fragment(vec![1, 2]));
+/// stream.write_all(&frame).expect("writing failed");
+/// ```
+#[cfg(feature = "testing")]
+#[derive(Debug)]
+pub struct FaultyStream<S> {
+    inner: S,
+    read_faults: Option<FaultInjector>,
+    write_faults: Option<FaultInjector>,
+    pending: std::collections::VecDeque<Vec<u8>>,
+    read_delay: Option<Duration>,
+    read_disconnected: bool,
+    write_disconnected: bool,
+}
+#[cfg(feature = "testing")]
+impl<S> FaultyStream<S> {
+    /// This wraps the given stream, initially without any faults.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            read_faults: None,
+            write_faults: None,
+            pending: std::collections::VecDeque::new(),
+            read_delay: None,
+            read_disconnected: false,
+            write_disconnected: false,
+        }
+    }
+    /// This sets the faults for the bytes read.
+    pub fn read_faults(mut self, plan: FaultPlan) -> Self {
+        self.read_faults = Some(FaultInjector::new(plan));
+        self
+    }
+    /// This sets the faults for the bytes written.
+    pub fn write_faults(mut self, plan: FaultPlan) -> Self {
+        self.write_faults = Some(FaultInjector::new(plan));
+        self
+    }
+    /// This returns a reference to the inner stream.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+    /// This returns a mutable reference to the inner stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+    /// This returns the inner stream. Pending (already read) bytes are lost.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+#[cfg(feature = "testing")]
+impl<S: std::io::Read> std::io::Read for FaultyStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.read_faults.is_none() {
+            return self.inner.read(buf);
+        }
+        loop {
+            if let Some(mut chunk) = self.pending.pop_front() {
+                if let Some(delay) = self.read_delay {
+                    std::thread::sleep(delay);
+                }
+                let length = chunk.len().min(buf.len());
+                buf[..length].copy_from_slice(&chunk[..length]);
+                if length < chunk.len() {
+                    self.pending.push_front(chunk.split_off(length));
+                }
+                return Ok(length);
+            }
+            if self.read_disconnected {
+                return Ok(0);
+            }
+            let mut incoming = vec![0; buf.len().max(1)];
+            let length = self.inner.read(&mut incoming)?;
+            if length == 0 {
+                return Ok(0);
+            }
+            if let Some(read_faults) = self.read_faults.as_mut() {
+                let output = read_faults.apply(&incoming[..length]);
+                self.read_delay = output.delay;
+                self.read_disconnected = output.disconnect;
+                self.pending.extend(output.chunks);
+            }
+        }
+    }
+}
+#[cfg(feature = "testing")]
+impl<S: Write> Write for FaultyStream<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.write_disconnected {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "connection dropped by fault injection",
+            ));
+        }
+        match self.write_faults.as_mut() {
+            Some(write_faults) => {
+                let output = write_faults.apply(buf);
+                self.write_disconnected = output.write_to(&mut self.inner)?;
+                Ok(buf.len())
+            }
+            None => self.inner.write(buf),
+        }
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
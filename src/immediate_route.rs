@@ -0,0 +1,128 @@
+use super::busy_state::*;
+use super::protocol::*;
+use log::*;
+
+/// This trait represents a handler for the immediate route, which is invoked by the read thread for each received message.
+/// In contrast to Protocol::message_is_answered_via_immediate_route, a handler can carry (mutable) state.
+/// To share this state with the application, wrap it into a handle like std::sync::Arc<std::sync::Mutex<_>>.
+///
+/// Closures with the signature of 'handle' implement this trait.
+/// # Example
+/// ```no_run
+/// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+/// # use doc_setup::*;
+/// # let mut client = client();
+/// let progress = std::sync::Arc::new(std::sync::atomic::AtomicU8::new(0));
+/// let progress_handle = progress.clone();
+/// client.set_immediate_route_handler(
+///     move |command: &CommandsExample, _message: &[u8], _busy_state: &BusyStatesExample| {
+///         if *command == CommandsExample::Funny {
+///             let progress = progress_handle.load(std::sync::atomic::Ordering::SeqCst);
+///             Some(vec![(CommandsExample::Funny, vec![progress])])
+///         } else {
+///             None
+///         }
+///     },
+/// );
+/// progress.store(42, std::sync::atomic::Ordering::SeqCst);
+/// ```
+pub trait ImmediateRouteHandler<P: Protocol>: Send + 'static {
+    /// This function checks if a message has to be answered immediately and not be forwarded to the user.
+    /// If the message is to be answered immediately, the replies are returned (zero, one or several).
+    /// If the message should be forwarded to the user, answer None.
+    fn handle(
+        &mut self,
+        command: &P::Commands,
+        message: &[u8],
+        busy_state: &P::BusyStates,
+    ) -> Option<Vec<Message<P>>>;
+}
+impl<P, F> ImmediateRouteHandler<P> for F
+where
+    P: Protocol,
+    F: FnMut(&P::Commands, &[u8], &P::BusyStates) -> Option<Vec<Message<P>>> + Send + 'static,
+{
+    fn handle(
+        &mut self,
+        command: &P::Commands,
+        message: &[u8],
+        busy_state: &P::BusyStates,
+    ) -> Option<Vec<Message<P>>> {
+        self(command, message, busy_state)
+    }
+}
+
+/// This is the default handler for the immediate route.
+/// It forwards to Protocol::message_is_answered_via_immediate_route.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ProtocolImmediateRoute;
+impl<P: Protocol> ImmediateRouteHandler<P> for ProtocolImmediateRoute {
+    fn handle(
+        &mut self,
+        command: &P::Commands,
+        message: &[u8],
+        busy_state: &P::BusyStates,
+    ) -> Option<Vec<Message<P>>> {
+        P::message_is_answered_via_immediate_route(command, message, busy_state)
+            .map(|reply| vec![reply])
+    }
+}
+
+// an update of the immediate route, sent from the main thread to the read thread
+pub(crate) enum ImmediateRouteUpdate<P: Protocol> {
+    Handler(Box<dyn ImmediateRouteHandler<P>>),
+    StateMachine(BusyStateMachine<P>),
+}
+
+// the immediate route of the read thread: the actions of the state machine come first, all other commands are passed to the handler
+pub(crate) struct ImmediateRoute<P: Protocol> {
+    handler: Box<dyn ImmediateRouteHandler<P>>,
+    state_machine: Option<BusyStateMachine<P>>,
+    busy_state: SharedBusyState<P>,
+}
+impl<P: Protocol> ImmediateRoute<P> {
+    pub(crate) fn new(busy_state: SharedBusyState<P>) -> Self {
+        Self {
+            handler: Box::new(ProtocolImmediateRoute),
+            state_machine: None,
+            busy_state,
+        }
+    }
+    // the handler and the state machine are replaced independently
+    pub(crate) fn update(&mut self, update: ImmediateRouteUpdate<P>) {
+        match update {
+            ImmediateRouteUpdate::Handler(handler) => self.handler = handler,
+            ImmediateRouteUpdate::StateMachine(state_machine) => {
+                self.state_machine = Some(state_machine)
+            }
+        }
+    }
+    pub(crate) fn handle(
+        &mut self,
+        command: &P::Commands,
+        message: &[u8],
+    ) -> Option<Vec<Message<P>>> {
+        let busy_state = self.busy_state.get();
+        let action = self
+            .state_machine
+            .as_ref()
+            .and_then(|state_machine| state_machine.action(busy_state, *command));
+        match action {
+            Some(CommandAction::Reject) => {
+                debug!("Command rejected in {:?}: {:?}", busy_state, command);
+                Some(Vec::new())
+            }
+            Some(CommandAction::Answer(replies)) => Some(replies.clone()),
+            Some(CommandAction::Transition(new_busy_state)) => {
+                if !self.busy_state.compare_and_set(busy_state, *new_busy_state) {
+                    warn!(
+                        "Transition not performed: {:?} -> {:?}",
+                        busy_state, new_busy_state
+                    );
+                }
+                None
+            }
+            None => self.handler.handle(command, message, &busy_state),
+        }
+    }
+}
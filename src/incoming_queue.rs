@@ -0,0 +1,185 @@
+use super::protocol::*;
+use super::tcp_ipc::ReadThreadErrorsInternal;
+use log::*;
+use std::collections::VecDeque;
+use std::sync::mpsc::{SendError, TryRecvError};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// The behaviour of the read thread if the message queue is full (see IncomingQueueLimit).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IncomingQueuePolicy {
+    /// The read thread stops reading from the Tcp-Stream until messages are taken from the queue, so TCP slows down the peer.
+    /// Meanwhile, no immediate replies are sent.
+    StopReading,
+    /// The oldest queued message is dropped to make room.
+    DropOldest,
+    /// The received message is dropped.
+    DropNewest,
+    /// The received message is dropped and this is reported via ReadThreadErrors::QueueOverflow, after the messages queued before.
+    ReportOverflow,
+}
+
+/// This bounds the queue of received messages (see TcpIpc::set_incoming_queue_limit).
+/// Errors of the read thread are always queued, since they are not counted.
+/// # Example
+/// ```
+/// # use rust_tcp_ipc::{IncomingQueueLimit, IncomingQueuePolicy};
+/// let limit = IncomingQueueLimit {
+///     capacity: 1024,
+///     full_policy: IncomingQueuePolicy::StopReading,
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IncomingQueueLimit {
+    /// This is the maximal count of queued messages (at least one).
+    pub capacity: usize,
+    /// This is the behaviour if the queue is full.
+    pub full_policy: IncomingQueuePolicy,
+}
+
+type Item<P> = Result<Message<P>, ReadThreadErrorsInternal<P>>;
+
+struct QueueState<P: Protocol> {
+    items: VecDeque<Item<P>>,
+    // the count of queued messages, i.e. of the items which are no errors
+    messages: usize,
+    dropped: u64,
+    limit: Option<IncomingQueueLimit>,
+    sender_alive: bool,
+    receiver_alive: bool,
+}
+
+// creates the queue of received messages, which replaces a channel between the read thread & the main thread
+pub(crate) fn incoming_queue<P: Protocol>() -> (IncomingSender<P>, IncomingReceiver<P>) {
+    let state = Arc::new(Mutex::new(QueueState {
+        items: VecDeque::new(),
+        messages: 0,
+        dropped: 0,
+        limit: None,
+        sender_alive: true,
+        receiver_alive: true,
+    }));
+    (
+        IncomingSender {
+            state: state.clone(),
+        },
+        IncomingReceiver { state },
+    )
+}
+
+fn lock<P: Protocol>(state: &Mutex<QueueState<P>>) -> MutexGuard<'_, QueueState<P>> {
+    match state.lock() {
+        Ok(state) => state,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+pub(crate) struct IncomingSender<P: Protocol> {
+    state: Arc<Mutex<QueueState<P>>>,
+}
+impl<P: Protocol> IncomingSender<P> {
+    // like a channel, this fails only if the receiver is gone
+    pub(crate) fn send(&self, item: Item<P>) -> Result<(), SendError<Item<P>>> {
+        let mut state = lock(&self.state);
+        if !state.receiver_alive {
+            return Err(SendError(item));
+        }
+        let message = match item {
+            Ok(message) => message,
+            Err(_) => {
+                state.items.push_back(item);
+                return Ok(());
+            }
+        };
+        let policy = match state.limit {
+            Some(limit) if state.messages >= limit.capacity.max(1) => limit.full_policy,
+            _ => IncomingQueuePolicy::StopReading,
+        };
+        match policy {
+            IncomingQueuePolicy::StopReading => {}
+            IncomingQueuePolicy::DropOldest => {
+                if let Some(position) = state.items.iter().position(Result::is_ok) {
+                    state.items.remove(position);
+                    state.messages -= 1;
+                    state.dropped += 1;
+                    debug!("Message queue is full, the oldest message was dropped");
+                }
+            }
+            IncomingQueuePolicy::DropNewest => {
+                state.dropped += 1;
+                debug!("Message queue is full, the received message was dropped");
+                return Ok(());
+            }
+            IncomingQueuePolicy::ReportOverflow => {
+                state.dropped += 1;
+                debug!("Message queue is full, the received message was dropped");
+                // consecutive overflows are reported once
+                match state.items.back_mut() {
+                    Some(Err(ReadThreadErrorsInternal::QueueOverflow(count))) => *count += 1,
+                    _ => state
+                        .items
+                        .push_back(Err(ReadThreadErrorsInternal::QueueOverflow(1))),
+                }
+                return Ok(());
+            }
+        }
+        state.items.push_back(Ok(message));
+        state.messages += 1;
+        Ok(())
+    }
+    // the read thread stops reading while this is true (see IncomingQueuePolicy::StopReading)
+    // without a receiver, sending fails anyway
+    pub(crate) fn is_full(&self) -> bool {
+        let state = lock(&self.state);
+        if !state.receiver_alive {
+            return false;
+        }
+        match state.limit {
+            Some(limit) => {
+                limit.full_policy == IncomingQueuePolicy::StopReading
+                    && state.messages >= limit.capacity.max(1)
+            }
+            None => false,
+        }
+    }
+}
+impl<P: Protocol> Drop for IncomingSender<P> {
+    fn drop(&mut self) {
+        lock(&self.state).sender_alive = false;
+    }
+}
+
+pub(crate) struct IncomingReceiver<P: Protocol> {
+    state: Arc<Mutex<QueueState<P>>>,
+}
+impl<P: Protocol> IncomingReceiver<P> {
+    // like a channel, this is disconnected only after all items are taken
+    pub(crate) fn try_recv(&self) -> Result<Item<P>, TryRecvError> {
+        let mut state = lock(&self.state);
+        match state.items.pop_front() {
+            Some(item) => {
+                if item.is_ok() {
+                    state.messages -= 1;
+                }
+                Ok(item)
+            }
+            None if state.sender_alive => Err(TryRecvError::Empty),
+            None => Err(TryRecvError::Disconnected),
+        }
+    }
+    // applies to messages received afterwards, already queued messages are kept
+    pub(crate) fn set_limit(&self, limit: Option<IncomingQueueLimit>) {
+        lock(&self.state).limit = limit;
+    }
+    pub(crate) fn len(&self) -> usize {
+        lock(&self.state).messages
+    }
+    pub(crate) fn dropped(&self) -> u64 {
+        lock(&self.state).dropped
+    }
+}
+impl<P: Protocol> Drop for IncomingReceiver<P> {
+    fn drop(&mut self) {
+        lock(&self.state).receiver_alive = false;
+    }
+}
@@ -0,0 +1,90 @@
+use super::protocol::*;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// This trait represents a middleware for messages, see TcpIpc::add_interceptor.
+/// An interceptor can inspect, transform, drop or synthesise messages:
+/// each message is replaced by the returned messages (none, one or several).
+///
+/// The outgoing path covers write_message and the replies of the immediate route.
+/// The incoming path is applied in the read thread after the message was received completely,
+/// before the immediate route and before the message is forwarded to the user.
+///
+/// Both default implementations pass the message unchanged.
+/// # Example
+/// ```no_run
+/// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+/// # use doc_setup::*;
+/// # let mut client = client();
+/// struct PayloadLogger;
+/// impl Interceptor<ProtocolExample> for PayloadLogger {
+///     fn incoming(&mut self, message: Message<ProtocolExample>) -> Vec<Message<ProtocolExample>> {
+///         println!("received {:?} with {} bytes", message.0, message.1.len());
+///         vec![message]
+///     }
+/// }
+/// client.add_interceptor(PayloadLogger);
+/// ```
+pub trait Interceptor<P: Protocol>: Send + 'static {
+    /// This is called for each message before it is sent.
+    fn outgoing(&mut self, message: Message<P>) -> Vec<Message<P>> {
+        vec![message]
+    }
+    /// This is called for each message after it was received.
+    fn incoming(&mut self, message: Message<P>) -> Vec<Message<P>> {
+        vec![message]
+    }
+}
+
+// the interceptors of a connection, shared between the main thread & the read thread
+pub(crate) struct InterceptorChain<P: Protocol> {
+    interceptors: Arc<Mutex<Vec<Box<dyn Interceptor<P>>>>>,
+}
+impl<P: Protocol> Clone for InterceptorChain<P> {
+    fn clone(&self) -> Self {
+        Self {
+            interceptors: self.interceptors.clone(),
+        }
+    }
+}
+impl<P: Protocol> InterceptorChain<P> {
+    pub(crate) fn new() -> Self {
+        Self {
+            interceptors: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+    // a panicking interceptor poisons the lock, but the chain itself stays usable
+    fn lock(&self) -> MutexGuard<'_, Vec<Box<dyn Interceptor<P>>>> {
+        match self.interceptors.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+    pub(crate) fn push(&self, interceptor: Box<dyn Interceptor<P>>) {
+        self.lock().push(interceptor);
+    }
+    pub(crate) fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+    // outgoing messages pass the interceptors in registration order
+    pub(crate) fn outgoing(&self, message: Message<P>) -> Vec<Message<P>> {
+        let mut messages = vec![message];
+        for interceptor in self.lock().iter_mut() {
+            messages = messages
+                .into_iter()
+                .flat_map(|message| interceptor.outgoing(message))
+                .collect();
+        }
+        messages
+    }
+    // incoming messages pass the interceptors in reverse registration order
+    pub(crate) fn incoming(&self, message: Message<P>) -> Vec<Message<P>> {
+        let mut messages = vec![message];
+        for interceptor in self.lock().iter_mut().rev() {
+            messages = messages
+                .into_iter()
+                .flat_map(|message| interceptor.incoming(message))
+                .collect();
+        }
+        messages
+    }
+}
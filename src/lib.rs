@@ -13,7 +13,43 @@
 //! Further received bytes form the next message.
 //!
 //! An example is given in the Examples.
+mod busy_state;
+mod codec;
+mod dispatcher;
+mod dynamic_protocol;
+#[cfg(feature = "testing")]
+mod fault_injection;
+mod immediate_route;
+mod incoming_queue;
+mod interceptor;
+mod logging;
+#[cfg(feature = "testing")]
+mod mock_peer;
+mod outgoing_queue;
 mod protocol;
 mod protocol_buffer;
+mod proxy;
+mod recording;
+mod reply_queue;
+mod stats;
+mod streaming;
 mod tcp_ipc;
+pub use self::busy_state::*;
+pub use self::codec::*;
+pub use self::dispatcher::*;
+pub use self::dynamic_protocol::*;
+#[cfg(feature = "testing")]
+pub use self::fault_injection::{FaultPlan, FaultyStream};
+pub use self::immediate_route::*;
+pub use self::incoming_queue::{IncomingQueueLimit, IncomingQueuePolicy};
+pub use self::interceptor::*;
+pub use self::logging::PayloadLogging;
+#[cfg(feature = "testing")]
+pub use self::mock_peer::*;
+pub use self::outgoing_queue::{BackgroundWriterConfig, OutgoingQueuePolicy};
+pub use self::protocol_buffer::DecodeEvent;
+pub use self::proxy::*;
+pub use self::recording::*;
+pub use self::stats::*;
+pub use self::streaming::IncomingStream;
 pub use self::tcp_ipc::*;
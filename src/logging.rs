@@ -0,0 +1,111 @@
+use std::fmt::{Debug, Display, Formatter};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// This configures how payloads are logged (see TcpIpcConfig::payload_logging).
+/// Payloads are formatted lazily, so nothing is copied if the log level is disabled.
+pub enum PayloadLogging {
+    /// Payloads are not logged at all.
+    Off,
+    /// Only the payload length is logged.
+    LengthOnly,
+    /// The payload length and a hexadecimal preview of (at most) the given number of bytes is logged.
+    HexPreview(usize),
+}
+/// Only the payload length is logged by default.
+impl Default for PayloadLogging {
+    fn default() -> Self {
+        PayloadLogging::LengthOnly
+    }
+}
+
+// formats a payload according to the payload logging mode, without copying it
+pub(crate) struct PayloadDisplay<'a> {
+    payload: &'a [u8],
+    payload_logging: PayloadLogging,
+}
+impl<'a> PayloadDisplay<'a> {
+    pub(crate) fn new(payload: &'a [u8], payload_logging: PayloadLogging) -> Self {
+        Self {
+            payload,
+            payload_logging,
+        }
+    }
+}
+impl<'a> Display for PayloadDisplay<'a> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self.payload_logging {
+            PayloadLogging::Off => Ok(()),
+            PayloadLogging::LengthOnly => write!(f, "{} bytes", self.payload.len()),
+            PayloadLogging::HexPreview(preview_length) => {
+                write!(f, "{} bytes [", self.payload.len())?;
+                for (i, byte) in self.payload.iter().take(preview_length).enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{:02x}", byte)?;
+                }
+                if self.payload.len() > preview_length {
+                    write!(f, " ...")?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+// the span of a connection, the parent of all message spans
+#[cfg(feature = "tracing")]
+#[derive(Debug, Clone)]
+pub(crate) struct ConnectionSpan(tracing::Span);
+#[cfg(not(feature = "tracing"))]
+#[derive(Debug, Clone)]
+pub(crate) struct ConnectionSpan;
+
+impl ConnectionSpan {
+    #[cfg(feature = "tracing")]
+    pub(crate) fn new(peer_address: Option<std::net::SocketAddr>) -> Self {
+        ConnectionSpan(tracing::info_span!("connection", peer = ?peer_address))
+    }
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn new(_peer_address: Option<std::net::SocketAddr>) -> Self {
+        ConnectionSpan
+    }
+    // logs a message (at debug level) inside its own span, with command, length and direction as fields
+    #[cfg(feature = "tracing")]
+    pub(crate) fn message<C: Debug>(
+        &self,
+        direction: &'static str,
+        command: &C,
+        payload: &[u8],
+        payload_logging: PayloadLogging,
+    ) {
+        let span = tracing::debug_span!(
+            parent: &self.0,
+            "message",
+            command = ?command,
+            length = payload.len(),
+            direction = direction
+        );
+        let _entered = span.enter();
+        tracing::debug!(
+            payload = %PayloadDisplay::new(payload, payload_logging),
+            "Message {}",
+            direction
+        );
+    }
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn message<C: Debug>(
+        &self,
+        direction: &'static str,
+        command: &C,
+        payload: &[u8],
+        payload_logging: PayloadLogging,
+    ) {
+        log::debug!(
+            "Message {}: {:?} {}",
+            direction,
+            command,
+            PayloadDisplay::new(payload, payload_logging)
+        );
+    }
+}
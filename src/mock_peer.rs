@@ -0,0 +1,423 @@
+use super::protocol::*;
+use super::protocol_buffer::*;
+use super::tcp_ipc::CancelHandle;
+use log::*;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+// the interval in which the mock peer checks for the end of the test
+const MOCK_PEER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+const MOCK_PEER_DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+type PayloadMatcherFn = Box<dyn Fn(&[u8]) -> bool + Send>;
+
+enum PayloadMatcher {
+    Any,
+    Exact(Vec<u8>),
+    Matching(PayloadMatcherFn, String),
+}
+impl PayloadMatcher {
+    fn matches(&self, payload: &[u8]) -> bool {
+        match self {
+            PayloadMatcher::Any => true,
+            PayloadMatcher::Exact(expected) => expected.as_slice() == payload,
+            PayloadMatcher::Matching(matcher, _) => matcher(payload),
+        }
+    }
+}
+
+enum Step<P: Protocol> {
+    Expect(P::Commands, PayloadMatcher),
+    Send(P::Commands, Vec<u8>),
+    Wait(Duration),
+    Close,
+}
+impl<P: Protocol> std::fmt::Display for Step<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Step::Expect(command, PayloadMatcher::Any) => write!(f, "expect {:?}", command),
+            Step::Expect(command, PayloadMatcher::Exact(payload)) => {
+                write!(f, "expect {:?} with payload {:?}", command, payload)
+            }
+            Step::Expect(command, PayloadMatcher::Matching(_, description)) => {
+                write!(
+                    f,
+                    "expect {:?} with payload matching {}",
+                    command, description
+                )
+            }
+            Step::Send(command, payload) => write!(f, "send {:?} {:?}", command, payload),
+            Step::Wait(duration) => write!(f, "wait {:?}", duration),
+            Step::Close => write!(f, "close"),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A failure reported by a MockPeer.
+pub enum MockPeerFailure<P: Protocol> {
+    /// No client connected before the first expectation timed out.
+    NotConnected,
+    /// The given step (index & description) was not met: nothing matching was received in time (or the connection was closed).
+    Unmet((usize, String)),
+    /// At the given step (index & description), a different message was received.
+    Mismatch((usize, String, Message<P>)),
+    /// A message was received after all expectations were met.
+    Unexpected(Message<P>),
+    /// The message of the given step (index & description) could not be constructed.
+    MessageConstructionFailed((usize, String)),
+    /// A received header could not be parsed (the header bytes are included). The mock peer stops afterwards.
+    ParseHeaderFailed((ParseHeaderError, Vec<u8>)),
+    /// Reading or writing failed. The mock peer stops afterwards.
+    Io(std::io::Error),
+}
+impl<P: Protocol> std::fmt::Display for MockPeerFailure<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MockPeerFailure::NotConnected => write!(f, "no client connected"),
+            MockPeerFailure::Unmet((index, step)) => {
+                write!(f, "step {} ({}) was not met", index, step)
+            }
+            MockPeerFailure::Mismatch((index, step, (command, payload))) => write!(
+                f,
+                "step {} ({}) failed, received {:?} {:?}",
+                index, step, command, payload
+            ),
+            MockPeerFailure::Unexpected((command, payload)) => {
+                write!(f, "unexpected message {:?} {:?}", command, payload)
+            }
+            MockPeerFailure::MessageConstructionFailed((index, step)) => {
+                write!(
+                    f,
+                    "step {} ({}) failed, constructing the message failed",
+                    index, step
+                )
+            }
+            MockPeerFailure::ParseHeaderFailed((err, header)) => {
+                write!(f, "parsing header {:?} failed: {:?}", header, err)
+            }
+            MockPeerFailure::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// A scripted peer for tests, which plays the server side of a connection.
+/// The steps are executed in order: expected messages are awaited (each with a timeout) & replies are sent.
+/// Afterwards, every further message is reported as unexpected.
+/// This is only available with the feature "testing".
+/// # Example
+/// ```
+/// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+/// # use doc_setup::*;
+/// # let config = config();
+/// let peer = MockPeer::<ProtocolExample>::new()
+///     .expect_payload(CommandsExample::Start, b"go".to_vec())
+///     .send(CommandsExample::Funny, b"ok".to_vec())
+///     .start()
+///     .expect("starting the mock peer failed");
+/// let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config, None).expect("connecting failed");
+/// client.write_message(CommandsExample::Start, b"go").expect("writing failed");
+/// // ...
+/// peer.assert_finished();
+/// ```
+pub struct MockPeer<P: Protocol> {
+    steps: Vec<Step<P>>,
+    timeout: Duration,
+}
+impl<P: Protocol> Default for MockPeer<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<P: Protocol> MockPeer<P> {
+    /// This constructs a mock peer without any steps.
+    pub fn new() -> Self {
+        Self {
+            steps: Vec::new(),
+            timeout: MOCK_PEER_DEFAULT_TIMEOUT,
+        }
+    }
+    /// This sets the time to wait for each expected message (the default is 5 seconds).
+    /// The time to wait for the client to connect counts towards the first expectation.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// This expects a message with the given command and an arbitrary payload.
+    pub fn expect(mut self, command: P::Commands) -> Self {
+        self.steps.push(Step::Expect(command, PayloadMatcher::Any));
+        self
+    }
+    /// This expects a message with the given command and payload.
+    pub fn expect_payload(mut self, command: P::Commands, payload: Vec<u8>) -> Self {
+        self.steps
+            .push(Step::Expect(command, PayloadMatcher::Exact(payload)));
+        self
+    }
+    /// This expects a message with the given command and a payload for which the matcher returns true.
+    /// The description is used in failure reports.
+    pub fn expect_matching<F>(mut self, command: P::Commands, description: &str, matcher: F) -> Self
+    where
+        F: Fn(&[u8]) -> bool + Send + 'static,
+    {
+        self.steps.push(Step::Expect(
+            command,
+            PayloadMatcher::Matching(Box::new(matcher), description.to_string()),
+        ));
+        self
+    }
+    /// This sends a message (typically as reply to the previously expected one).
+    pub fn send(mut self, command: P::Commands, payload: Vec<u8>) -> Self {
+        self.steps.push(Step::Send(command, payload));
+        self
+    }
+    /// This waits for the given time. Messages received meanwhile are handled by the next expectation.
+    pub fn wait(mut self, duration: Duration) -> Self {
+        self.steps.push(Step::Wait(duration));
+        self
+    }
+    /// This closes the connection. Later steps are not executed.
+    pub fn close(mut self) -> Self {
+        self.steps.push(Step::Close);
+        self
+    }
+    /// This binds an ephemeral port on 127.0.0.1 and starts executing the steps as soon as a client connects.
+    pub fn start(self) -> std::io::Result<MockPeerHandle<P>> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        listener.set_nonblocking(true)?;
+        let cancel_handle = CancelHandle::new();
+        let cancel_handle_thread = cancel_handle.clone();
+        let thread = std::thread::spawn(move || {
+            let mut run = MockPeerRun {
+                protocol_buffer: ProtocolBuffer::new(),
+                received: std::collections::VecDeque::new(),
+                failures: Vec::new(),
+                cancel_handle: cancel_handle_thread,
+            };
+            run.run(listener, self);
+            run.failures
+        });
+        Ok(MockPeerHandle {
+            address,
+            cancel_handle,
+            thread,
+        })
+    }
+}
+
+/// A running MockPeer.
+#[derive(Debug)]
+pub struct MockPeerHandle<P: Protocol> {
+    address: SocketAddr,
+    cancel_handle: CancelHandle,
+    thread: std::thread::JoinHandle<Vec<MockPeerFailure<P>>>,
+}
+impl<P: Protocol> MockPeerHandle<P> {
+    /// This returns the address to connect to.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+    /// This waits until all steps are executed (or failed), stops the mock peer & returns all failures.
+    /// Messages which are received after all steps were executed are reported as unexpected.
+    pub fn finish(self) -> Result<(), Vec<MockPeerFailure<P>>> {
+        self.cancel_handle.cancel();
+        let failures = match self.thread.join() {
+            Ok(failures) => failures,
+            Err(_) => vec![MockPeerFailure::Io(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "the mock peer thread panicked",
+            ))],
+        };
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures)
+        }
+    }
+    /// This calls finish & panics (failing the test) if there are any failures.
+    pub fn assert_finished(self) {
+        if let Err(failures) = self.finish() {
+            let failures = failures
+                .iter()
+                .map(|failure| format!("  {}", failure))
+                .collect::<Vec<_>>()
+                .join("\n");
+            panic!("mock peer failed:\n{}", failures);
+        }
+    }
+}
+
+// the result of waiting for the next message
+enum Received<P: Protocol> {
+    Message(Message<P>),
+    // nothing was received in time
+    TimedOut,
+    // the client closed the connection
+    Closed,
+}
+
+struct MockPeerRun<P: Protocol> {
+    protocol_buffer: ProtocolBuffer<P>,
+    received: std::collections::VecDeque<Message<P>>,
+    failures: Vec<MockPeerFailure<P>>,
+    cancel_handle: CancelHandle,
+}
+impl<P: Protocol> MockPeerRun<P> {
+    fn run(&mut self, listener: TcpListener, peer: MockPeer<P>) {
+        let mut stream = match self.accept(&listener, peer.timeout) {
+            Some(stream) => stream,
+            None => {
+                self.failures.push(MockPeerFailure::NotConnected);
+                return;
+            }
+        };
+        for (index, step) in peer.steps.iter().enumerate() {
+            let result = match step {
+                Step::Expect(command, matcher) => {
+                    match self.next_message(&mut stream, peer.timeout) {
+                        Ok(Received::Message((received_command, payload))) => {
+                            if received_command != *command || !matcher.matches(&payload) {
+                                self.failures.push(MockPeerFailure::Mismatch((
+                                    index,
+                                    step.to_string(),
+                                    (received_command, payload),
+                                )));
+                            }
+                            Ok(true)
+                        }
+                        Ok(Received::TimedOut) | Ok(Received::Closed) => {
+                            self.failures
+                                .push(MockPeerFailure::Unmet((index, step.to_string())));
+                            Ok(false)
+                        }
+                        Err(failure) => Err(failure),
+                    }
+                }
+                Step::Send(command, payload) => match P::construct_message(*command, payload) {
+                    Some(frame) => stream
+                        .write_all(&frame)
+                        .map(|_| true)
+                        .map_err(MockPeerFailure::Io),
+                    None => {
+                        self.failures
+                            .push(MockPeerFailure::MessageConstructionFailed((
+                                index,
+                                step.to_string(),
+                            )));
+                        Ok(true)
+                    }
+                },
+                Step::Wait(duration) => {
+                    std::thread::sleep(*duration);
+                    Ok(true)
+                }
+                Step::Close => {
+                    let _ = stream.shutdown(std::net::Shutdown::Both);
+                    return;
+                }
+            };
+            match result {
+                Ok(true) => {}
+                Ok(false) => {
+                    // the connection was closed or timed out, so the later expectations cannot be met either
+                    for (index, step) in peer.steps.iter().enumerate().skip(index + 1) {
+                        if let Step::Expect(..) = step {
+                            self.failures
+                                .push(MockPeerFailure::Unmet((index, step.to_string())));
+                        }
+                    }
+                    return;
+                }
+                Err(failure) => {
+                    self.failures.push(failure);
+                    return;
+                }
+            }
+        }
+        // report everything received until the end of the test
+        loop {
+            match self.next_message(&mut stream, MOCK_PEER_POLL_INTERVAL) {
+                Ok(Received::Message(message)) => {
+                    self.failures.push(MockPeerFailure::Unexpected(message))
+                }
+                Ok(Received::TimedOut) => {
+                    if self.cancel_handle.is_cancelled() {
+                        break;
+                    }
+                }
+                // nothing can be received anymore
+                Ok(Received::Closed) => break,
+                Err(failure) => {
+                    self.failures.push(failure);
+                    break;
+                }
+            }
+        }
+    }
+    fn accept(&self, listener: &TcpListener, timeout: Duration) -> Option<TcpStream> {
+        let start = Instant::now();
+        loop {
+            match listener.accept() {
+                Ok((stream, address)) => {
+                    debug!("Mock peer: client {} connected", address);
+                    if stream.set_nonblocking(false).is_err()
+                        || stream
+                            .set_read_timeout(Some(MOCK_PEER_POLL_INTERVAL))
+                            .is_err()
+                    {
+                        return None;
+                    }
+                    let _ = stream.set_nodelay(true);
+                    return Some(stream);
+                }
+                Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                    if start.elapsed() > timeout {
+                        return None;
+                    }
+                    std::thread::sleep(MOCK_PEER_POLL_INTERVAL);
+                }
+                Err(err) => {
+                    warn!("Mock peer: accepting failed: {:?}", err);
+                    return None;
+                }
+            }
+        }
+    }
+    fn next_message(
+        &mut self,
+        stream: &mut TcpStream,
+        timeout: Duration,
+    ) -> Result<Received<P>, MockPeerFailure<P>> {
+        let start = Instant::now();
+        let mut buffer = [0; 4096];
+        loop {
+            if let Some(message) = self.received.pop_front() {
+                return Ok(Received::Message(message));
+            }
+            if start.elapsed() > timeout {
+                return Ok(Received::TimedOut);
+            }
+            let length = match stream.read(&mut buffer) {
+                Ok(0) => return Ok(Received::Closed),
+                Ok(length) => length,
+                Err(ref err)
+                    if err.kind() == std::io::ErrorKind::WouldBlock
+                        || err.kind() == std::io::ErrorKind::TimedOut =>
+                {
+                    continue
+                }
+                Err(err) => return Err(MockPeerFailure::Io(err)),
+            };
+            self.protocol_buffer.push(&buffer[..length]);
+            while let Some(message) = self
+                .protocol_buffer
+                .next_message()
+                .map_err(MockPeerFailure::ParseHeaderFailed)?
+            {
+                self.received.push_back(message);
+            }
+        }
+    }
+}
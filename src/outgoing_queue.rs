@@ -0,0 +1,228 @@
+use super::tcp_ipc::WriteMessageErrors;
+use log::*;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// The behaviour of the background writer if its queue is full (see BackgroundWriterConfig).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutgoingQueuePolicy {
+    /// Writing waits until the queue has room again, so the caller is slowed down to the speed of the connection.
+    Block,
+    /// Writing fails with WriteMessageErrors::QueueFull and nothing is queued.
+    Fail,
+    /// The oldest queued frames are dropped (i.e. never sent) to make room.
+    DropOldest,
+}
+
+/// This configures the background writer (see TcpIpc::set_background_writer).
+/// # Example
+/// ```
+/// # use rust_tcp_ipc::{BackgroundWriterConfig, OutgoingQueuePolicy};
+/// let config = BackgroundWriterConfig {
+///     queue_capacity: 1024,
+///     full_policy: OutgoingQueuePolicy::Block,
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackgroundWriterConfig {
+    /// This is the maximal count of frames which are queued, but not yet written (at least one).
+    /// A batch of messages (see TcpIpc::set_write_batching) counts as one frame.
+    pub queue_capacity: usize,
+    /// This is the behaviour if the queue is full.
+    pub full_policy: OutgoingQueuePolicy,
+}
+
+#[derive(Debug)]
+struct QueueState {
+    frames: VecDeque<Vec<u8>>,
+    // a frame was taken by the writer thread, but is not yet written completely
+    writing: bool,
+    closed: bool,
+    // after a write failed, nothing is written anymore
+    failed: Option<std::io::ErrorKind>,
+}
+
+// the frames to be written, shared between the main thread & the writer thread
+#[derive(Debug, Clone)]
+pub(crate) struct OutgoingQueue {
+    config: BackgroundWriterConfig,
+    state: Arc<(Mutex<QueueState>, Condvar)>,
+}
+impl OutgoingQueue {
+    pub(crate) fn new(config: BackgroundWriterConfig) -> Self {
+        Self {
+            config,
+            state: Arc::new((
+                Mutex::new(QueueState {
+                    frames: VecDeque::new(),
+                    writing: false,
+                    closed: false,
+                    failed: None,
+                }),
+                Condvar::new(),
+            )),
+        }
+    }
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        match self.state.0.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+    fn wait<'a>(&self, state: MutexGuard<'a, QueueState>) -> MutexGuard<'a, QueueState> {
+        match self.state.1.wait(state) {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+    // each frame is written as a whole, so frames never interleave
+    pub(crate) fn push(&self, frames: Vec<Vec<u8>>) -> Result<(), WriteMessageErrors> {
+        let capacity = self.config.queue_capacity.max(1);
+        let mut state = self.lock();
+        if let Some(kind) = state.failed {
+            return Err(failed_error(kind));
+        }
+        if self.config.full_policy == OutgoingQueuePolicy::Fail
+            && state.frames.len() + frames.len() > capacity
+        {
+            return Err(WriteMessageErrors::QueueFull);
+        }
+        for frame in frames {
+            while state.frames.len() >= capacity {
+                match self.config.full_policy {
+                    OutgoingQueuePolicy::Block => {
+                        state = self.wait(state);
+                        if let Some(kind) = state.failed {
+                            return Err(failed_error(kind));
+                        }
+                    }
+                    OutgoingQueuePolicy::Fail => return Err(WriteMessageErrors::QueueFull),
+                    OutgoingQueuePolicy::DropOldest => {
+                        if let Some(dropped) = state.frames.pop_front() {
+                            warn!(
+                                "Outgoing queue is full, a frame of {} bytes was dropped",
+                                dropped.len()
+                            );
+                        }
+                    }
+                }
+            }
+            state.frames.push_back(frame);
+            self.state.1.notify_all();
+        }
+        Ok(())
+    }
+    // waits for the next frame, None is returned if the queue is closed (& empty) or failed
+    pub(crate) fn pop(&self) -> Option<Vec<u8>> {
+        let mut state = self.lock();
+        loop {
+            if state.failed.is_some() {
+                return None;
+            }
+            if let Some(frame) = state.frames.pop_front() {
+                state.writing = true;
+                self.state.1.notify_all();
+                return Some(frame);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.wait(state);
+        }
+    }
+    // reports the result of writing the last frame taken by pop
+    pub(crate) fn written(&self, result: Result<(), std::io::ErrorKind>) {
+        let mut state = self.lock();
+        state.writing = false;
+        if let Err(kind) = result {
+            state.failed = Some(kind);
+            if !state.frames.is_empty() {
+                warn!(
+                    "Writing failed, {} queued frames are dropped",
+                    state.frames.len()
+                );
+                state.frames.clear();
+            }
+        }
+        self.state.1.notify_all();
+    }
+    // waits until all queued frames are written (a None timeout waits without limit)
+    pub(crate) fn wait_until_written(
+        &self,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<(), WriteMessageErrors> {
+        let deadline = timeout.map(|timeout| std::time::Instant::now() + timeout);
+        let mut state = self.lock();
+        loop {
+            if let Some(kind) = state.failed {
+                return Err(failed_error(kind));
+            }
+            if state.frames.is_empty() && !state.writing {
+                return Ok(());
+            }
+            state = match deadline {
+                Some(deadline) => {
+                    let now = std::time::Instant::now();
+                    if now >= deadline {
+                        return Err(WriteMessageErrors::FlushTimedOut);
+                    }
+                    match self.state.1.wait_timeout(state, deadline - now) {
+                        Ok((state, _)) => state,
+                        Err(poisoned) => poisoned.into_inner().0,
+                    }
+                }
+                None => self.wait(state),
+            };
+        }
+    }
+    pub(crate) fn len(&self) -> usize {
+        let state = self.lock();
+        state.frames.len() + if state.writing { 1 } else { 0 }
+    }
+    // the writer thread finishes after writing the queued frames
+    pub(crate) fn close(&self) {
+        self.lock().closed = true;
+        self.state.1.notify_all();
+    }
+}
+
+fn failed_error(kind: std::io::ErrorKind) -> WriteMessageErrors {
+    WriteMessageErrors::MessageSendFailed(std::io::Error::new(
+        kind,
+        "the background writer failed to write a frame",
+    ))
+}
+
+// the writer thread is finished (after writing the queued frames) if this is dropped
+#[derive(Debug)]
+pub(crate) struct BackgroundWriter {
+    queue: OutgoingQueue,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+impl BackgroundWriter {
+    pub(crate) fn new(queue: OutgoingQueue, thread: std::thread::JoinHandle<()>) -> Self {
+        Self {
+            queue,
+            thread: Some(thread),
+        }
+    }
+    pub(crate) fn queue(&self) -> &OutgoingQueue {
+        &self.queue
+    }
+    // waits until the queued frames are written & the writer thread is finished
+    pub(crate) fn stop(mut self) -> Result<(), WriteMessageErrors> {
+        let written = self.queue.wait_until_written(None);
+        self.queue.close();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                warn!("Writer thread panicked");
+            }
+        }
+        written
+    }
+}
+impl Drop for BackgroundWriter {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
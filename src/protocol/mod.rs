@@ -21,7 +21,6 @@ pub enum ParseHeaderError {
 /// ```
 /// enum ProtocolExample {}
 /// ```
-
 pub trait Protocol: 'static {
     /// This type models the possible commands, like Start, Stop, Pause. It typical is represented by an enum.
     /// # Example
@@ -50,23 +49,30 @@ pub trait Protocol: 'static {
     /// This type represents the header' underlying u8-array.
     /// The array size is usually the sum of the command-array size & the length-array size.
     /// (Currently, Rust supports no integer generics.)
+    /// For a header size which is only known at runtime, a slice can be used (see DynamicProtocol).
     /// # Example
     /// ```
     /// type HeaderAsArray = [u8;5];
     /// ```
-    type HeaderAsArray: Debug;
+    type HeaderAsArray: Debug + ?Sized;
     /// This function returns a default BusyState "Idle".
     /// # Example
-    /// ```
+    /// ```ignore
     /// fn idle() -> Self::BusyStates {ExampleBusyStates::Idle}
     /// ```
     fn idle() -> Self::BusyStates;
+    /// This function returns false if the protocol cannot be used yet, for example since its description is not installed (see DynamicProtocol).
+    /// Connecting then fails with ConnectErrors::ProtocolNotReady.
+    /// The default implementation returns true.
+    fn is_ready() -> bool {
+        true
+    }
     /// This function checks if a message has to be answered immediately and not be forwarded to the user.
     /// If the message is to be answered immediately, a command and a message must be constructed.
     /// If the message should be forwarded to the user, answer None.
     /// A possible application is for "heartbeat" checks while the user is doing a computation.
     /// # Example
-    /// ```
+    /// ```ignore
     /// fn message_is_answered_via_immediate_route(
     ///      command: &Self::Commands,
     ///      message: &[u8],
@@ -82,7 +88,7 @@ pub trait Protocol: 'static {
     ) -> Option<(Self::Commands, Vec<u8>)>;
     /// This function parses a command-array into a command (enum-variant). If this fails, None is return.
     /// # Example
-    /// ```
+    /// ```ignore
     /// fn parse_command(command: &Self::CommandAsArray) -> Option<Self::Commands> {
     ///     use self::ExampleCommands::*;
     ///     match command {
@@ -97,7 +103,7 @@ pub trait Protocol: 'static {
     /// This function parses a length-array into a payload-length. If this fails, None is return.
     /// It is to be used only internally.
     /// # Example
-    /// ```
+    /// ```ignore
     /// fn parse_length(length: &Self::LengthAsArray) -> Option<Self::usize> {
     ///     length[0] as usize +length[1] as usize * 256
     /// }
@@ -106,7 +112,7 @@ pub trait Protocol: 'static {
     /// This function splits an incoming message into header-array & payload-slice. If this fails (because the message is too short), None is returned.
     /// It is to be used only internally.
     /// # Example
-    /// ```
+    /// ```ignore
     /// fn message_slice_to_header_array(input: &[u8]) -> Option<(&Self::HeaderAsArray, &[u8])> {
     ///     const HEADER_SIZE_EXAMPLE:usize = 5;
     ///     if input.len() >= HEADER_SIZE_EXAMPLE {
@@ -126,7 +132,7 @@ pub trait Protocol: 'static {
     /// It is to be used only internally.
     /// # Example
     /// The following example is "length first", so the payload length takes the first (two) bytes from the incoming header. The remaining bytes encode the command.
-    /// ```
+    /// ```ignore
     /// fn split_header_array(header: &Self::HeaderAsArray) -> (&Self::CommandAsArray, &Self::LengthAsArray) {
     ///     const LENGTH_SIZE_EXAMPLE : usize = 2;
     ///     const HEADER_SIZE_EXAMPLE : usize = 5;
@@ -147,7 +153,7 @@ pub trait Protocol: 'static {
     /// This function converts a command (enum-variant) to an array. This has to be the inverse of "parse_command".
     /// It is to be used only internally.
     /// # Example
-    /// ```
+    /// ```ignore
     /// fn command_to_array(command: Self::Commands) -> Self::CommandAsArray {
     ///     use self::ExampleCommands::*;
     ///     match command {
@@ -162,7 +168,7 @@ pub trait Protocol: 'static {
     /// If this fails (for example, if the message is too long), None is return.
     /// It is to be used only internally.
     /// # Example
-    /// ```
+    /// ```ignore
     /// fn get_length_as_array(command: Self::Commands, message: &[u8]) -> Option<Self::LengthAsArray> {
     ///     let length = message.len() as u64;
     ///     if length >= 256u64.pow(3) {
@@ -179,7 +185,7 @@ pub trait Protocol: 'static {
     /// This function constructs the message header from a command and a length.
     /// The implementation below should work (I'm just unable to get it to work generically).
     /// # Example
-    /// ```
+    /// ```ignore
     /// fn construct_header(command: Self::CommandAsArray, length: Self::LengthAsArray) -> Vec<u8> {
     ///     let mut header = Vec::new();
     ///     header.extend_from_slice(&length);
@@ -189,8 +195,60 @@ pub trait Protocol: 'static {
     /// ```
     fn construct_header(command: Self::CommandAsArray, length: Self::LengthAsArray) -> Vec<u8>;
 
+    /// This function defines the command & payload which is sent to request a graceful shutdown (see TcpIpc::graceful_shutdown).
+    /// The default implementation returns None, so the protocol supports no graceful shutdown.
+    /// To answer such a request of the peer, use the immediate route (see message_is_answered_via_immediate_route).
+    /// # Example
+    /// ```
+    /// # #[macro_use] mod doc_setup { include!("../../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # custom_protocol! {
+    /// fn shutdown_request() -> Option<(Self::Commands, Vec<u8>)> {
+    ///     Some((CommandsExample::Start, b"bye".to_vec()))
+    /// }
+    /// # }
+    /// # assert_eq!(CustomProtocol::shutdown_request(), Some((CommandsExample::Start, b"bye".to_vec())));
+    /// ```
+    fn shutdown_request() -> Option<(Self::Commands, Vec<u8>)> {
+        None
+    }
+    /// This function checks if a received message acknowledges a shutdown request.
+    /// The default implementation returns false.
+    /// # Example
+    /// ```
+    /// # #[macro_use] mod doc_setup { include!("../../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # custom_protocol! {
+    /// fn is_shutdown_acknowledgement(command: &Self::Commands, _message: &[u8]) -> bool {
+    ///     *command == CommandsExample::Funny
+    /// }
+    /// # }
+    /// # assert!(CustomProtocol::is_shutdown_acknowledgement(&CommandsExample::Funny, b"bye"));
+    /// ```
+    fn is_shutdown_acknowledgement(_command: &Self::Commands, _message: &[u8]) -> bool {
+        false
+    }
+    /// This function checks if a received message answers a heartbeat (see TcpIpc::send_heartbeat).
+    /// It is used to measure the heartbeat round trip time (see TcpIpc::stats).
+    /// The default implementation returns false.
+    /// # Example
+    /// ```
+    /// # #[macro_use] mod doc_setup { include!("../../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # custom_protocol! {
+    /// fn is_heartbeat_reply(command: &Self::Commands, _message: &[u8]) -> bool {
+    ///     *command == CommandsExample::Funny
+    /// }
+    /// # }
+    /// # assert!(CustomProtocol::is_heartbeat_reply(&CommandsExample::Funny, b""));
+    /// ```
+    fn is_heartbeat_reply(_command: &Self::Commands, _message: &[u8]) -> bool {
+        false
+    }
+
     /// This function parses a header into a command & a message length.
     /// The default implementation is fine.
+    #[allow(clippy::type_complexity)]
     fn parse_header(
         header: &Self::HeaderAsArray,
     ) -> Result<(Self::Commands, usize), (ParseHeaderError, &Self::HeaderAsArray)> {
@@ -205,17 +263,52 @@ pub trait Protocol: 'static {
             Err((ParseHeaderError::CommandParseFailed, header))
         }
     }
-    /// This function construct a message from a command & a payloay/message.
+    /// This function computes a length (as array-representation) from a command and a payload length, without the payload.
+    /// It is used to send payloads which are not in memory as a whole (see TcpIpc::write_message_from_reader).
+    /// The default implementation returns None (allocating a payload for get_length_as_array would defeat the purpose), so such payloads cannot be sent.
+    /// Since the length array typically depends only on the payload length, it is easily implemented.
+    /// # Example
+    /// ```
+    /// # #[macro_use] mod doc_setup { include!("../../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # custom_protocol! {
+    /// fn length_to_array(_command: Self::Commands, length: usize) -> Option<Self::LengthAsArray> {
+    ///     if length >= 256usize.pow(3) {
+    ///         return None;
+    ///     }
+    ///     Some([(length >> 16) as u8, (length >> 8) as u8, length as u8])
+    /// }
+    /// # }
+    /// # assert_eq!(CustomProtocol::length_to_array(CommandsExample::Start, 258), Some([0, 1, 2]));
+    /// ```
+    fn length_to_array(_command: Self::Commands, _length: usize) -> Option<Self::LengthAsArray> {
+        None
+    }
+    /// This function constructs the header of a message with the given command & payload length, without the payload.
+    /// The default implementation is fine (see length_to_array).
+    fn construct_header_for_length(command: Self::Commands, length: usize) -> Option<Vec<u8>> {
+        let length = Self::length_to_array(command, length)?;
+        Some(Self::construct_header(
+            Self::command_to_array(command),
+            length,
+        ))
+    }
+    /// This function constructs the header of a message from a command & a payload/message, without copying the payload.
+    /// It is used to write header & payload without concatenating them (see TcpIpc::write_message).
     /// The default implementation is fine.
+    fn construct_message_header(command: Self::Commands, message: &[u8]) -> Option<Vec<u8>> {
+        let length = Self::get_length_as_array(command, message)?;
+        Some(Self::construct_header(
+            Self::command_to_array(command),
+            length,
+        ))
+    }
+    /// This function construct a message from a command & a payloay/message.
+    /// The default implementation is fine. If it is overridden, construct_message_header has to be overridden consistently.
     fn construct_message(command: Self::Commands, message: &[u8]) -> Option<Vec<u8>> {
-        if let Some(length) = Self::get_length_as_array(command, message) {
-            let command = Self::command_to_array(command);
-            let mut new_message = Self::construct_header(command, length);
-            new_message.extend_from_slice(message);
-            Some(new_message)
-        } else {
-            None
-        }
+        let mut new_message = Self::construct_message_header(command, message)?;
+        new_message.extend_from_slice(message);
+        Some(new_message)
     }
 }
 
@@ -1,82 +1,168 @@
 pub use super::protocol::*;
 use log::*;
 
+// reassembles messages from byte chunks, which can be split or joined arbitrarily (like reads from a TCP stream)
+// it is used by the read thread & the public Decoder
 #[derive(Debug, Clone, PartialEq)]
-pub struct ProtocolBuffer<P: Protocol> {
-    current_command: Option<P::Commands>,
-    current_target: usize,
-    current_message: Vec<u8>,
-    incoming_buffer_vec: Vec<u8>,
-    busy_state: P::BusyStates,
+pub(crate) struct ProtocolBuffer<P: Protocol> {
+    // the command & payload length of the current message, if its header is complete
+    current_header: Option<(P::Commands, usize)>,
+    // the command & remaining payload length of the current streamed message (see next_event)
+    current_stream: Option<(P::Commands, usize)>,
+    // the received bytes, of which the first 'consumed' ones are already decoded
+    buffer: Vec<u8>,
+    consumed: usize,
+    // the offset of the header of the current message, which is kept until the message is complete (see next_frame)
+    frame_start: usize,
+}
+
+#[derive(Debug)]
+/// A decoded part of the byte stream, if large payloads are streamed (see Decoder::next_event).
+pub enum DecodeEvent<P: Protocol> {
+    /// A complete message (with a payload below the streaming threshold).
+    Message(Message<P>),
+    /// The header of a streamed message: the command & the payload length. The payload follows as chunks.
+    StreamStart((P::Commands, usize)),
+    /// A part of the payload of the current streamed message, as it was received.
+    StreamChunk(Vec<u8>),
+    /// The payload of the current streamed message is complete.
+    StreamEnd,
+}
+impl<P: Protocol> PartialEq for DecodeEvent<P> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (DecodeEvent::Message(a), DecodeEvent::Message(b)) => a == b,
+            (DecodeEvent::StreamStart(a), DecodeEvent::StreamStart(b)) => a == b,
+            (DecodeEvent::StreamChunk(a), DecodeEvent::StreamChunk(b)) => a == b,
+            (DecodeEvent::StreamEnd, DecodeEvent::StreamEnd) => true,
+            _ => false,
+        }
+    }
+}
+impl<P: Protocol> Default for ProtocolBuffer<P> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl<P: Protocol> ProtocolBuffer<P> {
-    pub fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
-            current_command: None,
-            current_target: 0,
-            current_message: Vec::new(),
-            incoming_buffer_vec: Vec::new(),
-            busy_state: P::idle(),
+            current_header: None,
+            current_stream: None,
+            buffer: Vec::new(),
+            consumed: 0,
+            frame_start: 0,
         }
     }
-    pub fn process_new_buffer(&mut self, incoming_buffer: &[u8]) -> Option<(P::Commands, Vec<u8>)> {
-        self.incoming_buffer_vec.extend_from_slice(incoming_buffer);
-        if let Some(command) = self.current_command {
-            if self.incoming_buffer_vec.len() + self.current_message.len() < self.current_target {
-                self.current_message.append(&mut self.incoming_buffer_vec);
-                None
-            } else {
-                let mut completed_message = self.current_message.split_off(0);
-                let mut to_append = self
-                    .incoming_buffer_vec
-                    .split_off(self.current_target - completed_message.len());
-                completed_message.append(&mut self.incoming_buffer_vec);
-                info!(
-                    "Message received: {:?}",
-                    (command, completed_message.clone())
-                );
-                self.incoming_buffer_vec.append(&mut to_append);
-                self.current_target = 0; //not strictly necessary
-                self.current_command = None;
-                Some((command, completed_message))
+    // appends the chunk, bytes of incomplete messages are kept for the next chunk
+    pub(crate) fn push(&mut self, chunk: &[u8]) {
+        // drop the decoded bytes once per chunk, instead of once per message
+        let decoded = match self.current_header {
+            Some(_) => self.frame_start,
+            None => self.consumed,
+        };
+        self.buffer.drain(..decoded);
+        self.consumed -= decoded;
+        self.frame_start = 0;
+        self.buffer.extend_from_slice(chunk);
+    }
+    // the count of bytes which are not yet returned as part of a message
+    pub(crate) fn buffered_len(&self) -> usize {
+        self.buffer.len() - self.consumed
+    }
+    // returns a partially received message (if any) and resets the buffer
+    // the command is None if the header is not yet complete
+    pub(crate) fn take_truncated_message(&mut self) -> Option<(Option<P::Commands>, Vec<u8>)> {
+        let remaining = self.buffer.split_off(self.consumed);
+        self.buffer.clear();
+        self.consumed = 0;
+        self.frame_start = 0;
+        match self.current_header.take().or(self.current_stream.take()) {
+            Some((command, _)) => Some((Some(command), remaining)),
+            None if !remaining.is_empty() => Some((None, remaining)),
+            None => None,
+        }
+    }
+    // a streamed message (which was started by next_event) is skipped
+    pub(crate) fn next_message(
+        &mut self,
+    ) -> Result<Option<Message<P>>, (ParseHeaderError, Vec<u8>)> {
+        loop {
+            match self.next_event(None)? {
+                Some(DecodeEvent::Message(message)) => return Ok(Some(message)),
+                Some(DecodeEvent::StreamStart(_)) => {}
+                Some(DecodeEvent::StreamChunk(chunk)) => {
+                    warn!("Skipped {} bytes of a streamed message", chunk.len())
+                }
+                Some(DecodeEvent::StreamEnd) => {}
+                None => return Ok(None),
             }
-        } else if let Some((header, message)) =
-            P::message_slice_to_header_array(self.incoming_buffer_vec.as_slice())
-        {
-            let (command, length) = match P::parse_header(header) {
-                Ok((command, length)) => (command, length),
-                Err((err, message)) => {
+        }
+    }
+    // returns the next message together with its bytes (header & payload), exactly as received
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn next_frame(
+        &mut self,
+    ) -> Result<Option<(Message<P>, Vec<u8>)>, (ParseHeaderError, Vec<u8>)> {
+        Ok(self.next_message()?.map(|message| {
+            let frame = self.buffer[self.frame_start..self.consumed].to_vec();
+            (message, frame)
+        }))
+    }
+    // messages with a payload length of at least the threshold are streamed
+    pub(crate) fn next_event(
+        &mut self,
+        streaming_threshold: Option<usize>,
+    ) -> Result<Option<DecodeEvent<P>>, (ParseHeaderError, Vec<u8>)> {
+        loop {
+            let remaining = &self.buffer[self.consumed..];
+            if let Some((command, length)) = self.current_stream {
+                if length == 0 {
+                    self.current_stream = None;
+                    return Ok(Some(DecodeEvent::StreamEnd));
+                }
+                if remaining.is_empty() {
+                    return Ok(None);
+                }
+                let chunk = remaining[..length.min(remaining.len())].to_vec();
+                self.consumed += chunk.len();
+                self.current_stream = Some((command, length - chunk.len()));
+                return Ok(Some(DecodeEvent::StreamChunk(chunk)));
+            }
+            if let Some((command, length)) = self.current_header {
+                if remaining.len() < length {
+                    return Ok(None);
+                }
+                let message = remaining[..length].to_vec();
+                self.consumed += length;
+                self.current_header = None;
+                return Ok(Some(DecodeEvent::Message((command, message))));
+            }
+            let (header, message) = match P::message_slice_to_header_array(remaining) {
+                Some(split) => split,
+                None => return Ok(None),
+            };
+            let header_length = remaining.len() - message.len();
+            match P::parse_header(header) {
+                Ok((command, length)) => {
+                    debug!("New message started: {:?}, length {}", command, length);
+                    self.frame_start = self.consumed;
+                    self.consumed += header_length;
+                    if streaming_threshold.is_some_and(|threshold| length >= threshold) {
+                        self.current_stream = Some((command, length));
+                        return Ok(Some(DecodeEvent::StreamStart((command, length))));
+                    }
+                    self.current_header = Some((command, length));
+                }
+                Err((err, header)) => {
                     // this should happen only in two cases:
                     // a) the command is not-known
                     // b) the length of the message is too large
-                    // Since both cases should never happen, a panic seems reasonable
-                    error!("parse error: {:?}, incoming header: {:?}", err, message);
-                    panic!("parse error: {:?}\r\n, incoming header: {:?}", err, message)
+                    // Both cases should never happen, so the caller has to give up on this stream
+                    error!("parse error: {:?}, incoming header: {:?}", err, header);
+                    return Err((err, remaining[..header_length].to_vec()));
                 }
-            };
-            self.current_command = Some(command);
-            self.current_target = length;
-            self.current_message = message.to_vec(); // capacity can also be set already
-            if length > self.current_message.len() {
-                self.incoming_buffer_vec = Vec::new();
-            } else {
-                self.incoming_buffer_vec = self.current_message.split_off(length);
-                self.current_message
-                    .reserve(length - self.current_message.len());
             }
-            debug!(
-                "New message started: {:?}",
-                (command, self.current_message.clone())
-            );
-            self.process_new_buffer(&[]) // process remaining buffer
-        } else {
-            None
         }
     }
-    pub fn update_busy_state(&mut self, busy_state: P::BusyStates) {
-        self.busy_state = busy_state;
-    }
-    pub fn get_busy_state(&self) -> P::BusyStates {
-        self.busy_state
-    }
 }
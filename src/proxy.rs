@@ -0,0 +1,358 @@
+use super::protocol::*;
+use super::protocol_buffer::*;
+use super::recording::*;
+use super::tcp_ipc::CancelHandle;
+use log::*;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// the interval in which blocking operations check for cancellation
+const PROXY_POLL_INTERVAL: Duration = Duration::from_millis(10);
+const PROXY_BUFFER_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The direction of a frame passing the proxy.
+pub enum ProxyDirection {
+    /// The frame was sent by the client (the process connecting to the proxy).
+    ClientToServer,
+    /// The frame was sent by the server (the process the proxy connects to).
+    ServerToClient,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// This decides what the proxy does with a decoded frame, see Proxy::on_frame.
+pub enum FrameAction {
+    /// The frame is forwarded unchanged.
+    Forward,
+    /// The frame is forwarded after the given delay (which also delays all later frames of this direction).
+    Delay(Duration),
+    /// The frame is dropped.
+    Drop,
+    /// The given bytes are sent instead of the frame (for example a frame with flipped bits).
+    Replace(Vec<u8>),
+    /// Both connections are closed, without forwarding the frame.
+    Disconnect,
+}
+
+/// The error type for the proxy.
+#[derive(Debug)]
+pub enum ProxyErrors {
+    /// Configuring the listener failed.
+    ListenerError(std::io::Error),
+    /// Accepting a client failed.
+    AcceptError(std::io::Error),
+}
+
+type FrameObserver<P> = dyn FnMut(ProxyDirection, &<P as Protocol>::Commands, &[u8]) + Send;
+type FrameDecision<P> =
+    dyn FnMut(ProxyDirection, &<P as Protocol>::Commands, &[u8]) -> FrameAction + Send;
+
+/// A man-in-the-middle proxy, which forwards the traffic between a client and a server.
+/// Each direction is decoded via the protocol, so every frame can be inspected (see on_message), recorded (see recorder) and manipulated (see on_frame).
+/// Forwarded frames are sent exactly as received, they are not re-encoded.
+/// Frames are forwarded once they are received completely, so a frame which is only partially received before the connection closes is not forwarded.
+///
+/// If a header cannot be parsed, the remaining bytes of this direction are forwarded without decoding.
+/// # Example
+/// ```no_run
+/// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+/// # use doc_setup::*;
+/// let listener = std::net::TcpListener::bind("127.0.0.1:7000").expect("binding failed");
+/// let cancel_handle = CancelHandle::new();
+/// Proxy::<ProtocolExample>::new()
+///     .on_message(|direction, command, payload| println!("{:?} {:?} {:?}", direction, command, payload))
+///     .on_frame(|_direction, command, _payload| match command {
+///         CommandsExample::Funny => FrameAction::Drop,
+///         _ => FrameAction::Forward,
+///     })
+///     .serve(&listener, "127.0.0.1:6666", &cancel_handle)
+///     .expect("proxy failed");
+/// ```
+pub struct Proxy<P: Protocol> {
+    observer: Option<Arc<Mutex<Box<FrameObserver<P>>>>>,
+    decision: Option<Arc<Mutex<Box<FrameDecision<P>>>>>,
+    recorder: Option<SessionRecorder>,
+}
+impl<P: Protocol> std::fmt::Debug for Proxy<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Proxy")
+            .field("observer", &self.observer.is_some())
+            .field("decision", &self.decision.is_some())
+            .field("recorder", &self.recorder)
+            .finish()
+    }
+}
+impl<P: Protocol> Default for Proxy<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<P: Protocol> Proxy<P> {
+    /// This constructs a proxy which forwards all frames unchanged.
+    pub fn new() -> Self {
+        Self {
+            observer: None,
+            decision: None,
+            recorder: None,
+        }
+    }
+    /// This sets a callback, which is called for each decoded frame (before on_frame decides about it).
+    pub fn on_message<F>(mut self, observer: F) -> Self
+    where
+        F: FnMut(ProxyDirection, &P::Commands, &[u8]) + Send + 'static,
+    {
+        self.observer = Some(Arc::new(Mutex::new(Box::new(observer))));
+        self
+    }
+    /// This sets a callback, which decides for each decoded frame what to do with it (fault injection).
+    pub fn on_frame<F>(mut self, decision: F) -> Self
+    where
+        F: FnMut(ProxyDirection, &P::Commands, &[u8]) -> FrameAction + Send + 'static,
+    {
+        self.decision = Some(Arc::new(Mutex::new(Box::new(decision))));
+        self
+    }
+    /// This records the traffic, as seen by the client: bytes forwarded to the server are outgoing, bytes forwarded to the client are incoming.
+    /// So a replay of the recording (see SessionReplay::spawn_peer) acts as the server.
+    /// The bytes are recorded as they are forwarded, so injected faults are included.
+    pub fn recorder(mut self, recorder: SessionRecorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+    /// This accepts clients on the given listener, one at a time.
+    /// For each client, a connection to the server is established and the traffic is forwarded until both sides closed their connection (closing is forwarded as well).
+    /// If connecting to the server fails, the client is disconnected.
+    /// This returns after the cancel handle is cancelled.
+    pub fn serve<A: ToSocketAddrs>(
+        &self,
+        listener: &TcpListener,
+        server_address: A,
+        cancel_handle: &CancelHandle,
+    ) -> Result<(), ProxyErrors> {
+        listener
+            .set_nonblocking(true)
+            .map_err(ProxyErrors::ListenerError)?;
+        while !cancel_handle.is_cancelled() {
+            let client = match listener.accept() {
+                Ok((client, client_address)) => {
+                    info!("Proxy: client {} connected", client_address);
+                    client
+                }
+                Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(PROXY_POLL_INTERVAL);
+                    continue;
+                }
+                Err(err) => return Err(ProxyErrors::AcceptError(err)),
+            };
+            let server = match TcpStream::connect(&server_address) {
+                Ok(server) => server,
+                Err(err) => {
+                    warn!("Proxy: connecting to the server failed: {:?}", err);
+                    continue;
+                }
+            };
+            if let Err(err) = self.forward(client, server, cancel_handle) {
+                warn!("Proxy: forwarding failed: {:?}", err);
+            }
+            info!("Proxy: connection closed");
+        }
+        Ok(())
+    }
+    // forwards both directions until both are finished (or cancelled)
+    fn forward(
+        &self,
+        client: TcpStream,
+        server: TcpStream,
+        cancel_handle: &CancelHandle,
+    ) -> std::io::Result<()> {
+        for stream in &[&client, &server] {
+            stream.set_nonblocking(false)?;
+            stream.set_nodelay(true)?;
+            stream.set_read_timeout(Some(PROXY_POLL_INTERVAL))?;
+        }
+        let server_to_client = {
+            let forwarder = self.forwarder(
+                ProxyDirection::ServerToClient,
+                server.try_clone()?,
+                client.try_clone()?,
+                cancel_handle.clone(),
+            );
+            std::thread::spawn(move || forwarder.run())
+        };
+        self.forwarder(
+            ProxyDirection::ClientToServer,
+            client,
+            server,
+            cancel_handle.clone(),
+        )
+        .run();
+        if server_to_client.join().is_err() {
+            error!("Proxy: forwarding thread panicked");
+        }
+        Ok(())
+    }
+    fn forwarder(
+        &self,
+        direction: ProxyDirection,
+        source: TcpStream,
+        destination: TcpStream,
+        cancel_handle: CancelHandle,
+    ) -> Forwarder<P> {
+        Forwarder {
+            direction,
+            source,
+            destination,
+            cancel_handle,
+            observer: self.observer.clone(),
+            decision: self.decision.clone(),
+            recorder: self.recorder.clone(),
+            protocol_buffer: Some(ProtocolBuffer::new()),
+        }
+    }
+}
+
+// forwards one direction of a proxied connection
+struct Forwarder<P: Protocol> {
+    direction: ProxyDirection,
+    source: TcpStream,
+    destination: TcpStream,
+    cancel_handle: CancelHandle,
+    observer: Option<Arc<Mutex<Box<FrameObserver<P>>>>>,
+    decision: Option<Arc<Mutex<Box<FrameDecision<P>>>>>,
+    recorder: Option<SessionRecorder>,
+    // None after a parse error, then bytes are forwarded without decoding
+    protocol_buffer: Option<ProtocolBuffer<P>>,
+}
+impl<P: Protocol> Forwarder<P> {
+    fn run(mut self) {
+        let mut buffer = [0; PROXY_BUFFER_SIZE];
+        let closed_by_fault = loop {
+            if self.cancel_handle.is_cancelled() {
+                break true;
+            }
+            let length = match self.source.read(&mut buffer) {
+                Ok(0) => break false,
+                Ok(length) => length,
+                Err(ref err)
+                    if err.kind() == std::io::ErrorKind::WouldBlock
+                        || err.kind() == std::io::ErrorKind::TimedOut
+                        || err.kind() == std::io::ErrorKind::Interrupted =>
+                {
+                    continue
+                }
+                Err(err) => {
+                    debug!("Proxy: reading ({:?}) failed: {:?}", self.direction, err);
+                    break false;
+                }
+            };
+            match self.process(&buffer[..length]) {
+                Ok(true) => {}
+                Ok(false) => break true,
+                Err(err) => {
+                    debug!("Proxy: writing ({:?}) failed: {:?}", self.direction, err);
+                    break false;
+                }
+            }
+        };
+        if closed_by_fault {
+            let _ = self.source.shutdown(Shutdown::Both);
+            let _ = self.destination.shutdown(Shutdown::Both);
+        } else {
+            if let Some((command, bytes)) = self
+                .protocol_buffer
+                .as_mut()
+                .and_then(ProtocolBuffer::take_truncated_message)
+            {
+                warn!(
+                    "Proxy: connection closed during a frame ({:?}, {:?}, {} bytes), which is not forwarded",
+                    self.direction,
+                    command,
+                    bytes.len()
+                );
+            }
+            let _ = self.destination.shutdown(Shutdown::Write);
+        }
+    }
+    // returns false if the connection is to be closed
+    fn process(&mut self, bytes: &[u8]) -> std::io::Result<bool> {
+        let protocol_buffer = match self.protocol_buffer.as_mut() {
+            Some(protocol_buffer) => protocol_buffer,
+            None => {
+                self.send(bytes)?;
+                return Ok(true);
+            }
+        };
+        protocol_buffer.push(bytes);
+        let mut frames = Vec::new();
+        let parse_error = loop {
+            match protocol_buffer.next_frame() {
+                Ok(Some(frame)) => frames.push(frame),
+                Ok(None) => break None,
+                Err(err) => break Some(err),
+            }
+        };
+        for ((command, payload), frame) in frames {
+            if let Some(observer) = &self.observer {
+                let mut observer = match observer.lock() {
+                    Ok(observer) => observer,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                observer(self.direction, &command, &payload);
+            }
+            let action = match &self.decision {
+                Some(decision) => {
+                    let mut decision = match decision.lock() {
+                        Ok(decision) => decision,
+                        Err(poisoned) => poisoned.into_inner(),
+                    };
+                    decision(self.direction, &command, &payload)
+                }
+                None => FrameAction::Forward,
+            };
+            match action {
+                FrameAction::Forward => self.send(&frame)?,
+                FrameAction::Delay(delay) => {
+                    std::thread::sleep(delay);
+                    self.send(&frame)?
+                }
+                FrameAction::Drop => debug!("Proxy: dropped {:?} ({:?})", command, self.direction),
+                FrameAction::Replace(bytes) => self.send(&bytes)?,
+                FrameAction::Disconnect => {
+                    info!(
+                        "Proxy: disconnecting at {:?} ({:?})",
+                        command, self.direction
+                    );
+                    return Ok(false);
+                }
+            }
+        }
+        if let Some((err, header)) = parse_error {
+            warn!(
+                "Proxy: parsing header {:?} ({:?}) failed: {:?}, forwarding without decoding",
+                header, self.direction, err
+            );
+            let bytes = self
+                .protocol_buffer
+                .take()
+                .and_then(|mut protocol_buffer| protocol_buffer.take_truncated_message())
+                .map(|(_, bytes)| bytes)
+                .unwrap_or_default();
+            self.send(&bytes)?;
+        }
+        Ok(true)
+    }
+    fn send(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        if let Some(recorder) = &self.recorder {
+            let direction = match self.direction {
+                ProxyDirection::ClientToServer => Direction::Outgoing,
+                ProxyDirection::ServerToClient => Direction::Incoming,
+            };
+            if let Err(err) = recorder.record(direction, bytes) {
+                warn!("Recording failed: {:?}", err);
+            }
+        }
+        self.destination.write_all(bytes)
+    }
+}
@@ -0,0 +1,338 @@
+use super::protocol::*;
+use super::protocol_buffer::*;
+use log::*;
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const MAGIC: &[u8; 8] = b"RTIPCREC";
+const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The direction of a recorded transfer, seen from the recording side.
+pub enum Direction {
+    /// The bytes were received from the peer.
+    Incoming,
+    /// The bytes were sent to the peer.
+    Outgoing,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A single record of a recorded session.
+pub struct SessionRecord {
+    /// The time since the start of the recording.
+    pub timestamp: Duration,
+    /// The direction of the transfer.
+    pub direction: Direction,
+    /// The bytes, exactly as transferred via TCP.
+    pub bytes: Vec<u8>,
+}
+
+/// This writes all bytes sent and received by a TcpIpc to a file (or any other writer), see TcpIpc::set_recorder.
+/// The recording can be read via SessionReplay.
+///
+/// The format of a recording (all integers little endian):
+/// - the file starts with the 8 magic bytes "RTIPCREC", followed by the format version (1 byte, currently 1)
+/// - then records follow until the end of the file, each consisting of
+///   - the timestamp in microseconds since the start of the recording (8 bytes)
+///   - the direction, 0 for incoming and 1 for outgoing (1 byte)
+///   - the number of bytes (4 bytes), so a record holds less than 4 GiB
+///   - the bytes, exactly as transferred via TCP
+///
+/// Outgoing records always contain complete messages (header & payload).
+/// Incoming records contain the bytes as they were read from the socket, so a message may be split over several records (or a record may contain several messages).
+/// It can be cloned, all clones write to the same recording.
+/// # Example
+/// ```no_run
+/// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+/// # use doc_setup::*;
+/// # let mut client = client();
+/// let recorder = SessionRecorder::create("session.rec").expect("unable to create recording");
+/// client.set_recorder(Some(recorder));
+/// ```
+#[derive(Clone)]
+pub struct SessionRecorder {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    start: Instant,
+}
+impl std::fmt::Debug for SessionRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SessionRecorder")
+            .field("start", &self.start)
+            .finish()
+    }
+}
+impl SessionRecorder {
+    /// This creates (or truncates) the given file and starts a recording.
+    pub fn create<T: AsRef<std::path::Path>>(path: T) -> std::io::Result<SessionRecorder> {
+        let file = std::fs::File::create(path)?;
+        Self::new(std::io::BufWriter::new(file))
+    }
+    /// This starts a recording into the given writer.
+    pub fn new<W: Write + Send + 'static>(mut writer: W) -> std::io::Result<SessionRecorder> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        Ok(SessionRecorder {
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+            start: Instant::now(),
+        })
+    }
+    /// This appends a record, using the time since the start of the recording as timestamp.
+    /// Records of 4 GiB or more are not supported (see the recording format), they are rejected with InvalidInput.
+    pub fn record(&self, direction: Direction, bytes: &[u8]) -> std::io::Result<()> {
+        self.record_parts(direction, &[bytes])
+    }
+    // records the concatenated parts as one record
+    pub(crate) fn record_parts(
+        &self,
+        direction: Direction,
+        parts: &[&[u8]],
+    ) -> std::io::Result<()> {
+        let length: usize = parts.iter().map(|part| part.len()).sum();
+        // checked before writing anything, so the recording stays readable
+        let length = u32::try_from(length).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "a record is limited to 4 GiB",
+            )
+        })?;
+        let timestamp = self.start.elapsed();
+        let mut writer = match self.writer.lock() {
+            Ok(writer) => writer,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let timestamp = timestamp.as_secs() * 1_000_000 + u64::from(timestamp.subsec_micros());
+        writer.write_all(&timestamp.to_le_bytes())?;
+        writer.write_all(&[match direction {
+            Direction::Incoming => 0,
+            Direction::Outgoing => 1,
+        }])?;
+        writer.write_all(&length.to_le_bytes())?;
+        for part in parts {
+            writer.write_all(part)?;
+        }
+        Ok(())
+    }
+    /// This flushes the underlying writer.
+    pub fn flush(&self) -> std::io::Result<()> {
+        match self.writer.lock() {
+            Ok(mut writer) => writer.flush(),
+            Err(poisoned) => poisoned.into_inner().flush(),
+        }
+    }
+}
+
+// the recorder of a connection, shared between the main thread & the read thread
+#[derive(Debug, Clone)]
+pub(crate) struct RecorderSlot {
+    recorder: Arc<Mutex<Option<SessionRecorder>>>,
+}
+impl RecorderSlot {
+    pub(crate) fn new() -> Self {
+        Self {
+            recorder: Arc::new(Mutex::new(None)),
+        }
+    }
+    pub(crate) fn set(&self, recorder: Option<SessionRecorder>) {
+        match self.recorder.lock() {
+            Ok(mut slot) => *slot = recorder,
+            Err(poisoned) => *poisoned.into_inner() = recorder,
+        }
+    }
+    // recording is best effort, failures are only logged
+    pub(crate) fn record(&self, direction: Direction, bytes: &[u8]) {
+        self.record_parts(direction, &[bytes])
+    }
+    pub(crate) fn record_parts(&self, direction: Direction, parts: &[&[u8]]) {
+        let recorder = match self.recorder.lock() {
+            Ok(slot) => slot.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+        if let Some(recorder) = recorder {
+            if let Err(err) = recorder.record_parts(direction, parts) {
+                warn!("Recording failed: {:?}", err);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The timing of a replay.
+pub enum ReplayTiming {
+    /// The records are replayed with the original delays.
+    Original,
+    /// The delays are divided by the given factor (so 2.0 replays twice as fast).
+    /// The factor has to be positive, otherwise the replay fails with InvalidInput.
+    Accelerated(f64),
+    /// The records are replayed without any delay.
+    Immediate,
+}
+
+/// A recorded session (see SessionRecorder), which can be replayed.
+/// # Example
+/// ```no_run
+/// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+/// # use doc_setup::*;
+/// # let config = config();
+/// let replay = SessionReplay::open("session.rec").expect("unable to read recording");
+/// // act as the recorded peer
+/// let (address, peer) = replay.spawn_peer(ReplayTiming::Accelerated(10.0)).expect("unable to start peer");
+/// let mut client = TcpIpc::<ProtocolExample>::client(address, config, None).expect("connecting failed");
+/// // or decode the received messages directly
+/// let messages = replay.incoming_messages::<ProtocolExample>();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionReplay {
+    records: Vec<SessionRecord>,
+}
+impl SessionReplay {
+    /// This reads a recording from the given file.
+    pub fn open<T: AsRef<std::path::Path>>(path: T) -> std::io::Result<SessionReplay> {
+        let file = std::fs::File::open(path)?;
+        Self::from_reader(std::io::BufReader::new(file))
+    }
+    /// This reads a recording from the given reader.
+    pub fn from_reader<R: Read>(mut reader: R) -> std::io::Result<SessionReplay> {
+        let invalid_data = |text| std::io::Error::new(std::io::ErrorKind::InvalidData, text);
+        let mut header = [0; 9];
+        reader.read_exact(&mut header)?;
+        if &header[0..8] != MAGIC {
+            return Err(invalid_data("not a recording"));
+        }
+        if header[8] != VERSION {
+            return Err(invalid_data("unsupported recording version"));
+        }
+        let mut records = Vec::new();
+        loop {
+            let mut timestamp = [0; 8];
+            match reader.read_exact(&mut timestamp) {
+                Ok(()) => {}
+                Err(ref err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            }
+            let mut direction = [0; 1];
+            reader.read_exact(&mut direction)?;
+            let direction = match direction[0] {
+                0 => Direction::Incoming,
+                1 => Direction::Outgoing,
+                _ => return Err(invalid_data("invalid direction")),
+            };
+            let mut length = [0; 4];
+            reader.read_exact(&mut length)?;
+            let mut bytes = Vec::new();
+            reader
+                .by_ref()
+                .take(u64::from(u32::from_le_bytes(length)))
+                .read_to_end(&mut bytes)?;
+            if bytes.len() != u32::from_le_bytes(length) as usize {
+                return Err(invalid_data("truncated record"));
+            }
+            records.push(SessionRecord {
+                timestamp: Duration::from_micros(u64::from_le_bytes(timestamp)),
+                direction,
+                bytes,
+            });
+        }
+        Ok(SessionReplay { records })
+    }
+    /// This constructs a replay from the given records.
+    pub fn from_records(records: Vec<SessionRecord>) -> SessionReplay {
+        SessionReplay { records }
+    }
+    /// This returns all records.
+    pub fn records(&self) -> &[SessionRecord] {
+        &self.records
+    }
+    /// This decodes the incoming bytes into messages, together with the timestamp of the record completing each message.
+    /// If a header could not be parsed, the error (and the header bytes) are returned.
+    #[allow(clippy::type_complexity)]
+    pub fn incoming_messages<P: Protocol>(
+        &self,
+    ) -> Result<Vec<(Duration, Message<P>)>, (ParseHeaderError, Vec<u8>)> {
+        let mut protocol_buffer = ProtocolBuffer::<P>::new();
+        let mut messages = Vec::new();
+        for record in self
+            .records
+            .iter()
+            .filter(|record| record.direction == Direction::Incoming)
+        {
+            protocol_buffer.push(&record.bytes);
+            while let Some(message) = protocol_buffer.next_message()? {
+                messages.push((record.timestamp, message));
+            }
+        }
+        Ok(messages)
+    }
+    /// This writes the incoming bytes to the given writer, with the given timing.
+    /// So the writer receives exactly the bytes the recording side received.
+    pub fn replay_incoming<W: Write>(
+        &self,
+        writer: &mut W,
+        timing: ReplayTiming,
+    ) -> std::io::Result<()> {
+        check_timing(timing)?;
+        let start = Instant::now();
+        for record in self
+            .records
+            .iter()
+            .filter(|record| record.direction == Direction::Incoming)
+        {
+            let due = match timing {
+                ReplayTiming::Original => Some(record.timestamp),
+                // the delay is limited, since tiny factors would overflow a Duration
+                ReplayTiming::Accelerated(factor) => Some(Duration::from_secs_f64(
+                    (record.timestamp.as_secs_f64() / factor).min(f64::from(u32::MAX)),
+                )),
+                ReplayTiming::Immediate => None,
+            };
+            if let Some(due) = due {
+                if let Some(wait_time) = due.checked_sub(start.elapsed()) {
+                    std::thread::sleep(wait_time);
+                }
+            }
+            writer.write_all(&record.bytes)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+    /// This starts a thread acting as the recorded peer.
+    /// It listens on an ephemeral port of 127.0.0.1 (the returned address) and waits for one connection.
+    /// Then the incoming bytes are replayed with the given timing (see replay_incoming) and the connection is closed.
+    /// Everything sent to the peer is ignored.
+    /// An invalid timing (see ReplayTiming::Accelerated) is rejected before the thread is started.
+    pub fn spawn_peer(
+        &self,
+        timing: ReplayTiming,
+    ) -> std::io::Result<(
+        std::net::SocketAddr,
+        std::thread::JoinHandle<std::io::Result<()>>,
+    )> {
+        check_timing(timing)?;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let replay = self.clone();
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept()?;
+            stream.set_nodelay(true)?;
+            replay.replay_incoming(&mut stream, timing)?;
+            stream.shutdown(std::net::Shutdown::Write)?;
+            // drain until the client closes the connection, so no reset is sent
+            std::io::copy(&mut stream, &mut std::io::sink())?;
+            Ok(())
+        });
+        Ok((address, handle))
+    }
+}
+
+fn check_timing(timing: ReplayTiming) -> std::io::Result<()> {
+    match timing {
+        ReplayTiming::Accelerated(factor) if factor.is_nan() || factor <= 0.0 => {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the acceleration factor has to be positive",
+            ))
+        }
+        _ => Ok(()),
+    }
+}
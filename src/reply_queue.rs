@@ -0,0 +1,119 @@
+use super::recording::*;
+use super::tcp_ipc::{BlockingWriter, TransferHooks};
+use log::*;
+use mio::net::TcpStream;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[derive(Debug)]
+struct PendingReply {
+    bytes: Vec<u8>,
+    // the count of bytes which are already written
+    written: usize,
+    // the connection has to be dropped after this reply (see FaultPlan::disconnect_after)
+    disconnect: bool,
+}
+
+// the immediate replies of the read thread which are not yet written
+// the read thread never waits for the socket: it queues its replies & writes them only as far as the socket accepts them without blocking
+// every other writer first writes the queued replies (while holding the write lock), so frames never interleave
+#[derive(Debug, Clone)]
+pub(crate) struct ReplyQueue {
+    replies: Arc<Mutex<VecDeque<PendingReply>>>,
+}
+impl ReplyQueue {
+    pub(crate) fn new() -> Self {
+        Self {
+            replies: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+    fn lock(&self) -> MutexGuard<'_, VecDeque<PendingReply>> {
+        match self.replies.lock() {
+            Ok(replies) => replies,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+    // queues a frame, injecting the outgoing faults (if any)
+    pub(crate) fn push(&self, frame: Vec<u8>, hooks: &TransferHooks) {
+        #[cfg(feature = "testing")]
+        {
+            if let Some(output) = hooks.faults.outgoing(&frame) {
+                for chunk in output.chunks {
+                    if let Some(delay) = output.delay {
+                        std::thread::sleep(delay);
+                    }
+                    hooks.recorder.record(Direction::Outgoing, &chunk);
+                    self.push_reply(chunk, false);
+                }
+                if output.disconnect {
+                    self.push_reply(Vec::new(), true);
+                }
+                return;
+            }
+        }
+        hooks.recorder.record(Direction::Outgoing, &frame);
+        self.push_reply(frame, false);
+    }
+    fn push_reply(&self, bytes: Vec<u8>, disconnect: bool) {
+        self.lock().push_back(PendingReply {
+            bytes,
+            written: 0,
+            disconnect,
+        });
+    }
+    pub(crate) fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+    // writes the queued replies as far as the socket accepts them, returns true if all are written
+    // the caller has to hold the write lock
+    pub(crate) fn write_without_blocking(&self, stream: &mut TcpStream) -> std::io::Result<bool> {
+        self.write(stream, false)
+    }
+    // writes all queued replies, waiting until the socket accepts them
+    // the caller has to hold the write lock
+    pub(crate) fn write_all(&self, stream: &mut TcpStream) -> std::io::Result<()> {
+        self.write(stream, true).map(|_| ())
+    }
+    fn write(&self, stream: &mut TcpStream, blocking: bool) -> std::io::Result<bool> {
+        loop {
+            // the queue is not locked while writing, so the read thread can queue further replies meanwhile
+            let mut reply = match self.lock().pop_front() {
+                Some(reply) => reply,
+                None => return Ok(true),
+            };
+            while reply.written < reply.bytes.len() {
+                let remaining = &reply.bytes[reply.written..];
+                let result = if blocking {
+                    BlockingWriter(stream).write(remaining)
+                } else {
+                    stream.write(remaining)
+                };
+                match result {
+                    Ok(0) => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::WriteZero,
+                            "failed to write the whole frame",
+                        ))
+                    }
+                    Ok(written) => reply.written += written,
+                    Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                        // the rest of the reply is written before any other frame
+                        self.lock().push_front(reply);
+                        return Ok(false);
+                    }
+                    Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            if reply.disconnect {
+                info!("Connection dropped by fault injection");
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionAborted,
+                    "connection dropped by fault injection",
+                ));
+            }
+        }
+    }
+}
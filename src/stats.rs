@@ -0,0 +1,277 @@
+use super::protocol::*;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+
+/// The message counters of a single command (see ConnectionStats).
+/// Bytes are counted as payload bytes, so the message headers are excluded.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CommandStats {
+    /// The number of messages sent.
+    pub messages_sent: u64,
+    /// The number of payload bytes sent.
+    pub bytes_sent: u64,
+    /// The number of messages received.
+    pub messages_received: u64,
+    /// The number of payload bytes received.
+    pub bytes_received: u64,
+}
+
+/// A snapshot of the statistics of a connection, see TcpIpc::stats.
+///
+/// Sent messages include the replies of the immediate route.
+/// Received messages are counted as received from the peer, so before any interceptor is applied.
+#[derive(Debug)]
+pub struct ConnectionStats<P: Protocol> {
+    /// The message counters per command, in order of first appearance.
+    pub per_command: Vec<(P::Commands, CommandStats)>,
+    /// The number of messages answered via the immediate route.
+    pub immediate_replies_sent: u64,
+    /// The number of headers which could not be parsed.
+    pub parse_errors: u64,
+    /// The number of received messages which are waiting in the message queue (see TcpIpc::get_message).
+    pub queue_depth: usize,
+    /// The number of received messages which were dropped, since the message queue was full (see TcpIpc::set_incoming_queue_limit).
+    pub messages_dropped: u64,
+    /// The time the last message was sent.
+    pub last_sent: Option<SystemTime>,
+    /// The time the last message was received.
+    pub last_received: Option<SystemTime>,
+    /// The round trip time of the last answered heartbeat (see TcpIpc::send_heartbeat).
+    pub heartbeat_round_trip_time: Option<Duration>,
+}
+impl<P: Protocol> Clone for ConnectionStats<P> {
+    fn clone(&self) -> Self {
+        Self {
+            per_command: self.per_command.clone(),
+            immediate_replies_sent: self.immediate_replies_sent,
+            parse_errors: self.parse_errors,
+            queue_depth: self.queue_depth,
+            messages_dropped: self.messages_dropped,
+            last_sent: self.last_sent,
+            last_received: self.last_received,
+            heartbeat_round_trip_time: self.heartbeat_round_trip_time,
+        }
+    }
+}
+impl<P: Protocol> Default for ConnectionStats<P> {
+    fn default() -> Self {
+        Self {
+            per_command: Vec::new(),
+            immediate_replies_sent: 0,
+            parse_errors: 0,
+            queue_depth: 0,
+            messages_dropped: 0,
+            last_sent: None,
+            last_received: None,
+            heartbeat_round_trip_time: None,
+        }
+    }
+}
+impl<P: Protocol> ConnectionStats<P> {
+    /// This returns the counters summed over all commands.
+    pub fn total(&self) -> CommandStats {
+        self.per_command
+            .iter()
+            .fold(CommandStats::default(), |total, (_, stats)| CommandStats {
+                messages_sent: total.messages_sent + stats.messages_sent,
+                bytes_sent: total.bytes_sent + stats.bytes_sent,
+                messages_received: total.messages_received + stats.messages_received,
+                bytes_received: total.bytes_received + stats.bytes_received,
+            })
+    }
+    fn command_stats(&mut self, command: P::Commands) -> &mut CommandStats {
+        let index = match self.per_command.iter().position(|(c, _)| *c == command) {
+            Some(index) => index,
+            None => {
+                self.per_command.push((command, CommandStats::default()));
+                self.per_command.len() - 1
+            }
+        };
+        &mut self.per_command[index].1
+    }
+    /// This renders the statistics in the Prometheus text exposition format.
+    /// All metrics are prefixed with "rust_tcp_ipc_" and carry the label connection="'connection'".
+    /// Commands are labeled via their Debug representation.
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let mut client = client();
+    /// let text = client.stats().to_prometheus("device_1");
+    /// ```
+    pub fn to_prometheus(&self, connection: &str) -> String {
+        use std::fmt::Write;
+        let connection = escape_label_value(connection);
+        let mut text = String::new();
+        let per_command_metrics: [CommandMetric; 4] = [
+            ("messages_sent_total", "Number of messages sent.", |s| {
+                s.messages_sent
+            }),
+            ("bytes_sent_total", "Number of payload bytes sent.", |s| {
+                s.bytes_sent
+            }),
+            (
+                "messages_received_total",
+                "Number of messages received.",
+                |s| s.messages_received,
+            ),
+            (
+                "bytes_received_total",
+                "Number of payload bytes received.",
+                |s| s.bytes_received,
+            ),
+        ];
+        // writing into a String cannot fail
+        for (name, help, value) in per_command_metrics.iter() {
+            let _ = writeln!(text, "# HELP rust_tcp_ipc_{} {}", name, help);
+            let _ = writeln!(text, "# TYPE rust_tcp_ipc_{} counter", name);
+            for (command, stats) in self.per_command.iter() {
+                let _ = writeln!(
+                    text,
+                    "rust_tcp_ipc_{}{{connection=\"{}\",command=\"{}\"}} {}",
+                    name,
+                    connection,
+                    escape_label_value(&format!("{:?}", command)),
+                    value(stats)
+                );
+            }
+        }
+        let since_epoch = |time: Option<SystemTime>| {
+            time.and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs_f64())
+        };
+        let metrics = [
+            (
+                "immediate_replies_sent_total",
+                "counter",
+                "Number of messages answered via the immediate route.",
+                Some(self.immediate_replies_sent as f64),
+            ),
+            (
+                "parse_errors_total",
+                "counter",
+                "Number of headers which could not be parsed.",
+                Some(self.parse_errors as f64),
+            ),
+            (
+                "queue_depth",
+                "gauge",
+                "Number of received messages waiting in the message queue.",
+                Some(self.queue_depth as f64),
+            ),
+            (
+                "messages_dropped_total",
+                "counter",
+                "Number of received messages dropped since the message queue was full.",
+                Some(self.messages_dropped as f64),
+            ),
+            (
+                "last_sent_timestamp_seconds",
+                "gauge",
+                "Time the last message was sent.",
+                since_epoch(self.last_sent),
+            ),
+            (
+                "last_received_timestamp_seconds",
+                "gauge",
+                "Time the last message was received.",
+                since_epoch(self.last_received),
+            ),
+            (
+                "heartbeat_round_trip_time_seconds",
+                "gauge",
+                "Round trip time of the last answered heartbeat.",
+                self.heartbeat_round_trip_time
+                    .map(|duration| duration.as_secs_f64()),
+            ),
+        ];
+        for (name, kind, help, value) in metrics.iter() {
+            if let Some(value) = value {
+                let _ = writeln!(text, "# HELP rust_tcp_ipc_{} {}", name, help);
+                let _ = writeln!(text, "# TYPE rust_tcp_ipc_{} {}", name, kind);
+                let _ = writeln!(
+                    text,
+                    "rust_tcp_ipc_{}{{connection=\"{}\"}} {}",
+                    name, connection, value
+                );
+            }
+        }
+        text
+    }
+}
+
+// name, help text & accessor of a per-command metric
+type CommandMetric = (&'static str, &'static str, fn(&CommandStats) -> u64);
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+struct StatsInner<P: Protocol> {
+    stats: ConnectionStats<P>,
+    heartbeat_sent: Option<Instant>,
+}
+
+// the statistics of a connection, shared between the main thread & the read thread
+pub(crate) struct StatsRecorder<P: Protocol> {
+    inner: Arc<Mutex<StatsInner<P>>>,
+}
+impl<P: Protocol> Clone for StatsRecorder<P> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+impl<P: Protocol> StatsRecorder<P> {
+    pub(crate) fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(StatsInner {
+                stats: ConnectionStats::default(),
+                heartbeat_sent: None,
+            })),
+        }
+    }
+    // the statistics are only counters, so they stay usable even if the lock is poisoned
+    fn lock(&self) -> MutexGuard<'_, StatsInner<P>> {
+        match self.inner.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+    pub(crate) fn snapshot(&self) -> ConnectionStats<P> {
+        self.lock().stats.clone()
+    }
+    pub(crate) fn message_sent(&self, command: P::Commands, payload_length: usize) {
+        let mut inner = self.lock();
+        inner.stats.last_sent = Some(SystemTime::now());
+        let command_stats = inner.stats.command_stats(command);
+        command_stats.messages_sent += 1;
+        command_stats.bytes_sent += payload_length as u64;
+    }
+    pub(crate) fn immediate_reply_sent(&self, command: P::Commands, payload_length: usize) {
+        self.message_sent(command, payload_length);
+        self.lock().stats.immediate_replies_sent += 1;
+    }
+    pub(crate) fn message_received(&self, command: P::Commands, message: &[u8]) {
+        let mut inner = self.lock();
+        inner.stats.last_received = Some(SystemTime::now());
+        let command_stats = inner.stats.command_stats(command);
+        command_stats.messages_received += 1;
+        command_stats.bytes_received += message.len() as u64;
+        if P::is_heartbeat_reply(&command, message) {
+            if let Some(heartbeat_sent) = inner.heartbeat_sent.take() {
+                inner.stats.heartbeat_round_trip_time = Some(heartbeat_sent.elapsed());
+            }
+        }
+    }
+    pub(crate) fn parse_error(&self) {
+        self.lock().stats.parse_errors += 1;
+    }
+    pub(crate) fn heartbeat_sent(&self) {
+        self.lock().heartbeat_sent = Some(Instant::now());
+    }
+}
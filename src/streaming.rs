@@ -0,0 +1,195 @@
+use super::protocol::*;
+use super::protocol_buffer::DecodeEvent;
+use log::*;
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+
+// the count of received, but not yet read chunks of a streamed message
+const STREAM_CHUNK_QUEUE_SIZE: usize = 16;
+// the time the read thread waits for a full chunk queue to be read, before the streamed message is aborted
+const STREAM_CHUNK_WAIT_TIME: std::time::Duration = std::time::Duration::from_secs(1);
+// time between two attempts to queue a chunk
+const STREAM_CHUNK_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_millis(1);
+
+/// A received message whose payload is streamed, i.e. delivered in chunks as they arrive (see TcpIpc::set_streaming_threshold).
+/// The payload is read via std::io::Read: reading blocks until the next chunk arrives and returns the end of the stream after the complete payload.
+/// If the connection is lost before the payload is complete, reading fails with UnexpectedEof.
+/// Only a few chunks are buffered: while the payload is not read, the read thread waits (and TCP slows down the peer), so meanwhile no other messages are received.
+/// If the buffered chunks are not read within a second, the stream is aborted, so that the connection keeps working:
+/// the remaining payload is discarded and reading fails with TimedOut.
+/// Dropping the stream discards the remaining payload.
+/// # Example
+/// ```no_run
+/// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+/// # use doc_setup::*;
+/// # let mut client = client();
+/// client.set_streaming_threshold(Some(1 << 20));
+/// if let Some(mut stream) = client.await_stream(std::time::Duration::from_secs(10)) {
+///     let mut file = std::fs::File::create("measurement.bin").expect("creating failed");
+///     std::io::copy(&mut stream, &mut file).expect("receiving failed");
+/// }
+/// ```
+#[derive(Debug)]
+pub struct IncomingStream<P: Protocol> {
+    command: P::Commands,
+    length: usize,
+    received: usize,
+    chunk: Vec<u8>,
+    position: usize,
+    chunk_receiver: Receiver<Vec<u8>>,
+    aborted: Arc<AtomicBool>,
+}
+impl<P: Protocol> IncomingStream<P> {
+    /// This returns the command of the message.
+    pub fn command(&self) -> P::Commands {
+        self.command
+    }
+    /// This returns the declared payload length.
+    pub fn length(&self) -> usize {
+        self.length
+    }
+    /// This returns the count of payload bytes which are not read yet.
+    pub fn remaining(&self) -> usize {
+        self.length - self.received + (self.chunk.len() - self.position)
+    }
+}
+impl<P: Protocol> Read for IncomingStream<P> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position == self.chunk.len() {
+            if self.received == self.length {
+                return Ok(0);
+            }
+            self.chunk = self.chunk_receiver.recv().map_err(|_| {
+                if self.aborted.load(Ordering::SeqCst) {
+                    std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "the payload was not read in time, the stream was aborted",
+                    )
+                } else {
+                    std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "the connection was lost before the payload was complete",
+                    )
+                }
+            })?;
+            self.position = 0;
+            self.received += self.chunk.len();
+        }
+        let length = buf.len().min(self.chunk.len() - self.position);
+        buf[..length].copy_from_slice(&self.chunk[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+// the streaming threshold, shared between the main thread & the read thread
+#[derive(Debug, Clone)]
+pub(crate) struct StreamingSlot {
+    threshold: Arc<Mutex<Option<usize>>>,
+}
+impl StreamingSlot {
+    pub(crate) fn new() -> Self {
+        Self {
+            threshold: Arc::new(Mutex::new(None)),
+        }
+    }
+    pub(crate) fn set(&self, threshold: Option<usize>) {
+        match self.threshold.lock() {
+            Ok(mut slot) => *slot = threshold,
+            Err(poisoned) => *poisoned.into_inner() = threshold,
+        }
+    }
+    pub(crate) fn threshold(&self) -> Option<usize> {
+        match self.threshold.lock() {
+            Ok(slot) => *slot,
+            Err(poisoned) => *poisoned.into_inner(),
+        }
+    }
+}
+
+// hands the streamed messages from the read thread to the main thread
+pub(crate) struct StreamDispatcher<P: Protocol> {
+    stream_sender: Sender<IncomingStream<P>>,
+    chunk_sender: Option<(SyncSender<Vec<u8>>, Arc<AtomicBool>)>,
+}
+impl<P: Protocol> StreamDispatcher<P> {
+    pub(crate) fn new() -> (Self, Receiver<IncomingStream<P>>) {
+        let (stream_sender, stream_receiver) = channel();
+        (
+            Self {
+                stream_sender,
+                chunk_sender: None,
+            },
+            stream_receiver,
+        )
+    }
+    // a message event is not handled here
+    pub(crate) fn handle(&mut self, event: DecodeEvent<P>) {
+        match event {
+            DecodeEvent::Message(_) => {}
+            DecodeEvent::StreamStart((command, length)) => {
+                debug!("Streamed message started: {:?}, length {}", command, length);
+                let (chunk_sender, chunk_receiver) = sync_channel(STREAM_CHUNK_QUEUE_SIZE);
+                let aborted = Arc::new(AtomicBool::new(false));
+                let stream = IncomingStream {
+                    command,
+                    length,
+                    received: 0,
+                    chunk: Vec::new(),
+                    position: 0,
+                    chunk_receiver,
+                    aborted: aborted.clone(),
+                };
+                // if sending fails, the main thread is gone anyway
+                self.chunk_sender = self
+                    .stream_sender
+                    .send(stream)
+                    .ok()
+                    .map(|_| (chunk_sender, aborted));
+            }
+            DecodeEvent::StreamChunk(chunk) => {
+                if let Some((chunk_sender, aborted)) = &self.chunk_sender {
+                    match queue_chunk(chunk_sender, chunk) {
+                        Ok(()) => {}
+                        Err(TrySendError::Disconnected(_)) => {
+                            debug!(
+                                "Streamed message was dropped, the remaining payload is discarded"
+                            );
+                            self.chunk_sender = None;
+                        }
+                        Err(TrySendError::Full(_)) => {
+                            warn!("Streamed message was not read in time, the remaining payload is discarded");
+                            aborted.store(true, Ordering::SeqCst);
+                            self.chunk_sender = None;
+                        }
+                    }
+                }
+            }
+            DecodeEvent::StreamEnd => {
+                debug!("Streamed message completed");
+                self.chunk_sender = None;
+            }
+        }
+    }
+}
+
+// waits while the queue is full (which lets TCP slow down the peer), but at most STREAM_CHUNK_WAIT_TIME
+fn queue_chunk(
+    chunk_sender: &SyncSender<Vec<u8>>,
+    mut chunk: Vec<u8>,
+) -> Result<(), TrySendError<Vec<u8>>> {
+    let started = std::time::Instant::now();
+    loop {
+        match chunk_sender.try_send(chunk) {
+            Err(TrySendError::Full(returned_chunk))
+                if started.elapsed() < STREAM_CHUNK_WAIT_TIME =>
+            {
+                chunk = returned_chunk;
+                std::thread::sleep(STREAM_CHUNK_RETRY_INTERVAL);
+            }
+            result => return result,
+        }
+    }
+}
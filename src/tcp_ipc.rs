@@ -1,6 +1,19 @@
 use super::protocol_buffer::*;
 
-pub use super::protocol_buffer::{ParseHeaderError, Protocol};
+use super::busy_state::*;
+#[cfg(feature = "testing")]
+use super::fault_injection::*;
+use super::immediate_route::*;
+use super::incoming_queue::*;
+use super::interceptor::*;
+use super::logging::*;
+use super::outgoing_queue::*;
+pub use super::protocol_buffer::{Message, ParseHeaderError, Protocol};
+use super::recording::*;
+use super::reply_queue::*;
+use super::stats::*;
+use super::streaming::*;
+use iovec::IoVec;
 use log::*;
 use mio::net::{TcpListener, TcpStream};
 use std::io::{Read, Write};
@@ -8,17 +21,30 @@ use std::net::ToSocketAddrs;
 use std::sync::mpsc::TryRecvError;
 
 const BUFFER_SIZE: usize = 128;
+// the size of the chunks in which a payload is read from a reader and sent
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+// the time to wait before retrying a write, if the send buffer is full
+const WRITE_RETRY_WAIT_TIME: std::time::Duration = std::time::Duration::from_micros(50);
+// the maximal count of buffers passed to one vectored write
+const MAXIMAL_IO_VECS: usize = 64;
+// maximal number of connection events which are queued if nobody takes them
+const EVENT_QUEUE_SIZE: usize = 1024;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 /// This bundles the time-settings for the protocol
 /// A 'None' value means that there will no time spend waiting.
+///
+/// The field payload_logging was added in version 0.4, which breaks struct literals written for earlier versions: they have to set it, too.
+/// TcpIpcConfig::new is not affected by such additions, since it uses the defaults for all further settings.
 /// # Example
 /// ```
+/// # use rust_tcp_ipc::{PayloadLogging, TcpIpcConfig};
 /// let config = TcpIpcConfig {
 ///     after_connect_wait_time: Some(std::time::Duration::from_micros(5_000)),
 ///     read_iteration_wait_time: Some(std::time::Duration::from_micros(1)),
 ///     shutdown_wait_time: Some(std::time::Duration::from_micros(5_000_000)),
 ///     check_count: 1,
+///     payload_logging: PayloadLogging::LengthOnly,
 /// };
 /// ```
 pub struct TcpIpcConfig {
@@ -30,17 +56,48 @@ pub struct TcpIpcConfig {
     /// Very small values can yield high CPU-usage.
     pub read_iteration_wait_time: Option<std::time::Duration>,
     /// This is the time the client waits for the server to accept a shutdown request.
+    /// The queued messages (see TcpIpc::set_background_writer) are written within this time, too.
     pub shutdown_wait_time: Option<std::time::Duration>,
-    /// This is the number of iterations inside the read thread after which the busy_update_state will be checked
+    /// This is the number of iterations inside the read thread after which shutdown requests and immediate route handler updates will be checked
     /// A good default value is 1 (check after each iteration)
     pub check_count: u32,
+    /// This configures how payloads of sent and received messages are logged.
+    pub payload_logging: PayloadLogging,
+}
+impl TcpIpcConfig {
+    /// This creates a config with the given time-settings, all further settings have their default values (for example PayloadLogging::default()).
+    /// # Example
+    /// ```
+    /// # use rust_tcp_ipc::{PayloadLogging, TcpIpcConfig};
+    /// let mut config = TcpIpcConfig::new(None, Some(std::time::Duration::from_micros(1)), None, 1);
+    /// config.payload_logging = PayloadLogging::HexPreview(16);
+    /// ```
+    pub fn new(
+        after_connect_wait_time: Option<std::time::Duration>,
+        read_iteration_wait_time: Option<std::time::Duration>,
+        shutdown_wait_time: Option<std::time::Duration>,
+        check_count: u32,
+    ) -> Self {
+        Self {
+            after_connect_wait_time,
+            read_iteration_wait_time,
+            shutdown_wait_time,
+            check_count,
+            payload_logging: PayloadLogging::default(),
+        }
+    }
 }
 
 #[derive(Debug)]
-enum ReadThreadErrorsInternal<P: Protocol> {
+#[allow(clippy::enum_variant_names)]
+pub(crate) enum ReadThreadErrorsInternal<P: Protocol> {
     WriteError(std::io::Error),
     ReadError(std::io::Error),
     ImmediateMessageConstructError((P::Commands, Vec<u8>)),
+    TruncatedMessage((Option<P::Commands>, Vec<u8>)),
+    PeerClosed,
+    ParseHeaderFailed((ParseHeaderError, Vec<u8>)),
+    QueueOverflow(usize),
 }
 #[derive(Debug)]
 /// The error type for operations in the asynchronous read thread
@@ -54,6 +111,20 @@ pub enum ReadThreadErrors<P: Protocol> {
     ImmediateMessageConstructError((P::Commands, Vec<u8>)),
     /// This happens if the read-thread is disconnected from the server.
     Disconnected,
+    /// This happens if the peer closed the connection while a message was only partially received.
+    /// The command is None if not even the header was received completely.
+    /// The byte-vector contains the partially received payload (or header, respectively).
+    TruncatedMessage((Option<P::Commands>, Vec<u8>)),
+    /// This happens if the peer closed the connection (orderly).
+    /// It is returned after all completely received messages were returned.
+    PeerClosed,
+    /// This happens if a received header could not be parsed (the header bytes are included).
+    /// Since the message boundaries are lost, the read-thread stops afterwards.
+    /// This typically indicates that the protocol implementation is incomplete.
+    ParseHeaderFailed((ParseHeaderError, Vec<u8>)),
+    /// This happens if received messages were dropped, since the message queue was full (see IncomingQueuePolicy::ReportOverflow).
+    /// The count of dropped messages is included. The messages received afterwards are returned as usual.
+    QueueOverflow(usize),
 }
 /// The error type for the connect-function.
 #[derive(Debug)]
@@ -81,23 +152,93 @@ pub enum ConnectErrors {
     SetSendBufferSizeError(std::io::Error),
     /// This error indicates that the given wait time was exceeded
     WaitTimeExceeded,
+    /// This error indicates that waiting for a client was aborted via a CancelHandle
+    Cancelled,
+    /// The protocol cannot be used yet (see Protocol::is_ready), so no connection was attempted.
+    ProtocolNotReady,
+}
+/// A handle to abort a server waiting for a client to connect.
+/// It can be cloned and moved to another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle {
+    cancelled: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+impl CancelHandle {
+    /// This constructs a new (not cancelled) handle.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// This requests the cancellation. All clones of this handle are affected.
+    pub fn cancel(&self) {
+        self.cancelled
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+    /// This checks if the cancellation was requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
+/// The connection lifecycle events, which are reported via TcpIpc::take_event_receiver.
+#[derive(Debug)]
+pub enum ConnectionEvent<P: Protocol> {
+    /// The connection to the given peer was established.
+    Connected(std::net::SocketAddr),
+    /// The peer closed the connection (a read returned zero bytes).
+    PeerClosed,
+    /// Reading from the Tcp-Stream failed.
+    ReadError(std::io::ErrorKind),
+    /// Writing to the Tcp-Stream failed.
+    WriteError(std::io::ErrorKind),
+    /// The read thread answered a message via the immediate route, using the given command.
+    ImmediateReplySent(P::Commands),
+    /// The busy state changed to the given one.
+    BusyStateChanged(P::BusyStates),
+    /// The connection was shut down. The result is the same as the one of TcpIpc::shutdown.
+    ShutdownComplete(Result<(), ShutdownError>),
 }
 /// This is the main type of the library.
 /// Here all the logic is bundle.
 /// It can be used to easily send and receive messages via TCP, allowing for many different protcols to be used.
 pub struct TcpIpc<P: Protocol> {
-    busy_state_sender: std::sync::mpsc::Sender<P::BusyStates>,
-    message_receiver: std::sync::mpsc::Receiver<Result<Message<P>, ReadThreadErrorsInternal<P>>>,
+    busy_state: SharedBusyState<P>,
+    message_receiver: IncomingReceiver<P>,
     stream: TcpStream,
     shutdown_sender: std::sync::mpsc::Sender<()>,
     shutdown_wait_time: Option<std::time::Duration>,
-    busy_state_query_sender: std::sync::mpsc::Sender<()>,
-    busy_state_queried_receiver: std::sync::mpsc::Receiver<P::BusyStates>,
+    event_sender: std::sync::mpsc::SyncSender<ConnectionEvent<P>>,
+    event_receiver: Option<std::sync::mpsc::Receiver<ConnectionEvent<P>>>,
+    peer_closed: bool,
+    immediate_route_sender: std::sync::mpsc::Sender<ImmediateRouteUpdate<P>>,
+    interceptors: InterceptorChain<P>,
+    stats: StatsRecorder<P>,
+    connection_span: ConnectionSpan,
+    payload_logging: PayloadLogging,
+    hooks: TransferHooks,
+    streaming: StreamingSlot,
+    stream_receiver: std::sync::mpsc::Receiver<IncomingStream<P>>,
+    // frames are written by the main thread & the read thread (immediate replies), which must not interleave
+    write_lock: std::sync::Arc<std::sync::Mutex<()>>,
+    replies: ReplyQueue,
+    // the size from which on batched frames are written (see set_write_batching)
+    write_batching: Option<usize>,
+    pending_batch: Vec<u8>,
+    writer: Option<BackgroundWriter>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 /// The error type for a BusyState update
 pub enum BusyStateUpdateResult {
+    /// Update succesful
+    Success,
+    /// This is no longer returned: the busy_state is shared with the read thread, so it can be updated even if the connection is closed.
+    /// The variant is kept for compatibility.
+    Disconnected,
+    /// The busy_state state machine does not allow this transition (see TcpIpc::set_busy_state_machine).
+    TransitionNotAllowed,
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The error type for an update of the immediate route handler
+pub enum ImmediateRouteHandlerUpdateResult {
     /// Update succesful
     Success,
     /// The only posibility for fail is that the connection is already (disgracefully) closed.
@@ -105,8 +246,11 @@ pub enum BusyStateUpdateResult {
 }
 #[derive(Debug, Clone, Copy, PartialEq)]
 /// The error type for a BusyState query
+///
+/// Since the busy_state is shared with the read thread, a query no longer fails.
+/// This type (and the Result returned by TcpIpc::get_busy_state) is kept for compatibility.
 pub enum BusyStateQueryResult {
-    /// The only posibility for fail is that the connection is already (disgracefully) closed.
+    /// This is no longer returned, even if the connection is already (disgracefully) closed.
     Disconnected,
 }
 #[derive(Debug)]
@@ -117,13 +261,17 @@ pub enum WriteMessageErrors {
     /// Failed to send message.
     /// This indicates typically a run-time problem.
     MessageSendFailed(std::io::Error),
+    /// The queue of the background writer is full (see OutgoingQueuePolicy::Fail), the message was not sent.
+    QueueFull,
+    /// The queued messages were not written within the given time (see TcpIpc::flush).
+    FlushTimedOut,
 }
 impl<P: Protocol> TcpIpc<P> {
     /// This connects a client to a server, allowing to send and receive commands.
     /// The input variable 'connect_wait_time' is the time the client waits for the Server to accept a TCP-connection.
     /// A 'None' value yields an infinite waiting period.
     /// # Example
-    /// ```
+    /// ```ignore
     /// let config = TcpIpcConfig {
     ///     connect_wait_time_ms: 5_000,
     ///     read_iteration_wait_time_ns: 1_000,
@@ -137,6 +285,7 @@ impl<P: Protocol> TcpIpc<P> {
         config: TcpIpcConfig,
         connect_wait_time: Option<std::time::Duration>,
     ) -> Result<TcpIpc<P>, ConnectErrors> {
+        check_protocol::<P>()?;
         // connect
         let client = {
             let mut error = self::ConnectErrors::SocketListIsEmpty;
@@ -181,19 +330,50 @@ impl<P: Protocol> TcpIpc<P> {
     }
     /// This sets up a server waiting for a client to connect to it.
     /// Afterwards it can be used to send and receive commands.
+    /// The input variable 'accept_wait_time' is the time the server waits for a client to connect.
+    /// A 'None' value yields an infinite waiting period.
     /// # Example
-    /// ```
-    /// let config = TcpIpcConfig {
-    ///     read_iteration_wait_time_ns: 1_000,
-    ///     shutdown_wait_time_in_ns: 1_000_000,
-    /// };
+    /// ```ignore
     /// let mut server =
-    ///     TcpIpc::<ProtocolExample>::server("127.0.0.1:6666", config).expect("connecting failed");
+    ///     TcpIpc::<ProtocolExample>::server("127.0.0.1:6666", config, Some(std::time::Duration::from_secs(5)))
+    ///         .expect("connecting failed");
     /// ```
     pub fn server<T: ToSocketAddrs>(
         socket_addresses: T,
         config: TcpIpcConfig,
+        accept_wait_time: Option<std::time::Duration>,
     ) -> Result<TcpIpc<P>, ConnectErrors> {
+        Self::server_cancellable(
+            socket_addresses,
+            config,
+            accept_wait_time,
+            &CancelHandle::new(),
+        )
+    }
+    /// This is the same as 'server', but the waiting for a client can be aborted from another thread via the given handle.
+    /// In this case, ConnectErrors::Cancelled is returned.
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let config = config();
+    /// let cancel_handle = CancelHandle::new();
+    /// let cancel_handle_clone = cancel_handle.clone();
+    /// std::thread::spawn(move || {
+    ///     std::thread::sleep(std::time::Duration::from_secs(1));
+    ///     cancel_handle_clone.cancel();
+    /// });
+    /// let result =
+    ///     TcpIpc::<ProtocolExample>::server_cancellable("127.0.0.1:6666", config, None, &cancel_handle);
+    /// ```
+    pub fn server_cancellable<T: ToSocketAddrs>(
+        socket_addresses: T,
+        config: TcpIpcConfig,
+        accept_wait_time: Option<std::time::Duration>,
+        cancel_handle: &CancelHandle,
+    ) -> Result<TcpIpc<P>, ConnectErrors> {
+        check_protocol::<P>()?;
+        let now = std::time::Instant::now();
         // connect
         let server = {
             let mut error = self::ConnectErrors::SocketListIsEmpty;
@@ -205,15 +385,16 @@ impl<P: Protocol> TcpIpc<P> {
                     debug!("trying to connect to {:?}", socket_address);
                     let listener =
                         TcpListener::bind(&socket_address).map_err(ConnectErrors::BindError)?;
-                    match loop {
-                        match listener.accept() {
-                            Ok(stream) => break Ok(stream),
-                            Err(error) => match error.kind() {
-                                std::io::ErrorKind::WouldBlock => continue,
-                                _ => break Err(error),
-                            },
-                        }
-                    } {
+                    let poll = register_listener(&listener).map_err(ConnectErrors::BindError)?;
+                    let remaining_wait_time = match accept_wait_time {
+                        Some(accept_wait_time) => match accept_wait_time.checked_sub(now.elapsed())
+                        {
+                            Some(remaining_wait_time) => Some(remaining_wait_time),
+                            None => return Err(self::ConnectErrors::WaitTimeExceeded),
+                        },
+                        None => None,
+                    };
+                    match accept(&listener, &poll, remaining_wait_time, cancel_handle)? {
                         Ok((stream, socket_address)) => {
                             info!("connected to {:?}", socket_address);
                             break stream;
@@ -239,28 +420,61 @@ impl<P: Protocol> TcpIpc<P> {
             .set_nodelay(true)
             .map_err(self::ConnectErrors::SetNodelayError)?;
         tcp_stream
-            .set_send_buffer_size(
-                std::mem::size_of::<<P as Protocol>::HeaderAsArray>() / std::mem::size_of::<u8>(),
-            )
+            .set_send_buffer_size(header_size::<P>())
             .map_err(self::ConnectErrors::SetSendBufferSizeError)?;
         tcp_stream
-            .set_recv_buffer_size(
-                std::mem::size_of::<<P as Protocol>::HeaderAsArray>() / std::mem::size_of::<u8>(),
-            )
+            .set_recv_buffer_size(header_size::<P>())
             .map_err(self::ConnectErrors::SetReceiveBufferSizeError)?;
 
         // start read thread
         let mut tcp_stream_read = tcp_stream
             .try_clone()
             .map_err(ConnectErrors::TryCloneError)?;
-        let (message_sender, message_receiver) = std::sync::mpsc::channel();
-        let (busy_state_sender, busy_state_receiver) = std::sync::mpsc::channel();
-        let (busy_state_query_sender, busy_state_query_receiver) = std::sync::mpsc::channel();
-        let (busy_state_queried_sender, busy_state_queried_receiver) = std::sync::mpsc::channel();
+        let (message_sender, message_receiver) = incoming_queue();
+        let busy_state = SharedBusyState::<P>::new();
+        let busy_state_read = busy_state.clone();
         let (shutdown_sender, shutdown_receiver) = std::sync::mpsc::channel();
+        let (immediate_route_sender, immediate_route_receiver) = std::sync::mpsc::channel();
+        let (event_sender, event_receiver) = std::sync::mpsc::sync_channel(EVENT_QUEUE_SIZE);
+        if let Ok(peer_address) = tcp_stream.peer_addr() {
+            send_event(&event_sender, ConnectionEvent::Connected(peer_address));
+        }
+        let event_sender_read = event_sender.clone();
+        let event_sender_busy_state = event_sender.clone();
+        busy_state.add_change_callback(move |_, new_busy_state| {
+            send_event(
+                &event_sender_busy_state,
+                ConnectionEvent::BusyStateChanged(new_busy_state),
+            )
+        });
+        let interceptors = InterceptorChain::<P>::new();
+        let interceptors_read = interceptors.clone();
+        let stats = StatsRecorder::<P>::new();
+        let stats_read = stats.clone();
+        let connection_span = ConnectionSpan::new(tcp_stream.peer_addr().ok());
+        let connection_span_read = connection_span.clone();
+        let hooks = TransferHooks::new();
+        let hooks_read = hooks.clone();
+        let streaming = StreamingSlot::new();
+        let streaming_read = streaming.clone();
+        let (mut stream_dispatcher, stream_receiver) = StreamDispatcher::<P>::new();
+        let write_lock = std::sync::Arc::new(std::sync::Mutex::new(()));
+        let write_lock_read = write_lock.clone();
+        let replies = ReplyQueue::new();
+        let reply_queue_read = replies.clone();
         std::thread::spawn(move || {
+            let event_sender = event_sender_read;
+            let interceptors = interceptors_read;
+            let stats = stats_read;
+            let connection_span = connection_span_read;
+            let hooks = hooks_read;
+            let streaming = streaming_read;
+            let write_lock = write_lock_read;
+            let reply_queue = reply_queue_read;
+            let busy_state = busy_state_read;
             let mut protocol = ProtocolBuffer::<P>::new();
-            let mut incoming_buffer = [0; BUFFER_SIZE];
+            let mut immediate_route = ImmediateRoute::new(busy_state);
+            let mut incoming_buffer = vec![0; BUFFER_SIZE];
             info!("Read thread started");
             let mut counter = 0;
             'read_loop: loop {
@@ -276,26 +490,9 @@ impl<P: Protocol> TcpIpc<P> {
                             break 'read_loop;
                         }
                     }
-                    match busy_state_query_receiver.try_recv() {
-                        Ok(()) => match busy_state_queried_sender.send(protocol.get_busy_state()) {
-                            Ok(()) => {}
-                            Err(std::sync::mpsc::SendError(_)) => {
-                                debug!("Read thread seems to be disconnected from main thread. Will be shut down.");
-                                break 'read_loop;
-                            }
-                        },
-                        Err(std::sync::mpsc::TryRecvError::Empty) => {
-                            // nothing to do
-                        }
-                        Err(std::sync::mpsc::TryRecvError::Disconnected) => {
-                            debug!("Read thread seems to be disconnected from main thread. Will be shut down.");
-                            break 'read_loop;
-                        }
-                    }
-
                     loop {
-                        match busy_state_receiver.try_recv() {
-                            Ok(busy_state) => protocol.update_busy_state(busy_state),
+                        match immediate_route_receiver.try_recv() {
+                            Ok(update) => immediate_route.update(update),
                             Err(std::sync::mpsc::TryRecvError::Empty) => break,
                             Err(std::sync::mpsc::TryRecvError::Disconnected) => {
                                 debug!("Read thread seems to be disconnected from main thread. Will be shut down.");
@@ -306,66 +503,204 @@ impl<P: Protocol> TcpIpc<P> {
                 } else {
                     counter += 1;
                 }
+                if !write_replies(
+                    &reply_queue,
+                    &write_lock,
+                    &mut tcp_stream_read,
+                    &event_sender,
+                    &message_sender,
+                ) {
+                    break 'read_loop; //disconnected
+                }
+                if message_sender.is_full() {
+                    // not reading lets TCP slow down the peer (see IncomingQueuePolicy::StopReading)
+                    if let Some(read_iteration_wait_time) = config.read_iteration_wait_time {
+                        std::thread::sleep(read_iteration_wait_time);
+                    }
+                    continue 'read_loop;
+                }
+                // streamed payloads are read in large chunks
+                let buffer_size = match streaming.threshold() {
+                    Some(_) => STREAM_CHUNK_SIZE,
+                    None => BUFFER_SIZE,
+                };
+                incoming_buffer.resize(buffer_size, 0);
+                let mut buffer_filled = false;
                 match tcp_stream_read.read(&mut incoming_buffer) {
                     Ok(message_length) => {
-                        if message_length == 0 {
-                            // nothing to do
+                        buffer_filled = message_length == incoming_buffer.len();
+                        // message_length == 0 means end of stream, the peer closed the connection
+                        let received = &incoming_buffer[0..message_length];
+                        #[cfg(feature = "testing")]
+                        let faulty_output = if message_length == 0 {
+                            None
+                        } else {
+                            hooks.faults.incoming(received)
+                        };
+                        #[cfg(feature = "testing")]
+                        let (chunks, end_of_stream) = match &faulty_output {
+                            Some(output) => (
+                                output.chunks.iter().map(Vec::as_slice).collect(),
+                                output.disconnect,
+                            ),
+                            None if message_length == 0 => (Vec::new(), true),
+                            None => (vec![received], false),
+                        };
+                        #[cfg(not(feature = "testing"))]
+                        let (chunks, end_of_stream) = if message_length == 0 {
+                            (Vec::new(), true)
                         } else {
-                            let mut buffer = &incoming_buffer[0..message_length];
-                            debug!("New incoming buffer: {:?}", buffer);
-                            while let Some((command, message)) = protocol.process_new_buffer(buffer)
+                            (vec![received], false)
+                        };
+                        for chunk in chunks {
+                            #[cfg(feature = "testing")]
                             {
-                                buffer = &[];
-                                if let Some((command, message)) =
-                                    P::message_is_answered_via_immediate_route(
-                                        &command,
-                                        &message,
-                                        &protocol.get_busy_state(),
-                                    ) {
-                                    if let Some(message) = P::construct_message(command, &message) {
-                                        if let Err(err) = tcp_stream_read.write(&message) {
-                                            if message_sender
-                                                .send(Err(ReadThreadErrorsInternal::WriteError(
-                                                    err,
-                                                )))
+                                if let Some(delay) =
+                                    faulty_output.as_ref().and_then(|output| output.delay)
+                                {
+                                    std::thread::sleep(delay);
+                                }
+                            }
+                            let buffer = chunk;
+                            hooks.recorder.record(Direction::Incoming, buffer);
+                            debug!(
+                                "New incoming buffer: {}",
+                                PayloadDisplay::new(buffer, config.payload_logging)
+                            );
+                            protocol.push(buffer);
+                            let streaming_threshold = streaming.threshold();
+                            loop {
+                                let received = match protocol.next_event(streaming_threshold) {
+                                    Ok(Some(DecodeEvent::Message(received))) => received,
+                                    Ok(Some(event)) => {
+                                        stream_dispatcher.handle(event);
+                                        continue;
+                                    }
+                                    Ok(None) => break,
+                                    Err(parse_error) => {
+                                        stats.parse_error();
+                                        // if sending fails, the main thread is gone anyway
+                                        let _ = message_sender.send(Err(
+                                            ReadThreadErrorsInternal::ParseHeaderFailed(
+                                                parse_error,
+                                            ),
+                                        ));
+                                        break 'read_loop;
+                                    }
+                                };
+                                stats.message_received(received.0, &received.1);
+                                connection_span.message(
+                                    "received",
+                                    &received.0,
+                                    &received.1,
+                                    config.payload_logging,
+                                );
+                                for (command, message) in interceptors.incoming(received) {
+                                    let replies = match immediate_route.handle(&command, &message) {
+                                        Some(replies) => replies,
+                                        None => {
+                                            if !wait_for_queue_room(
+                                                &message_sender,
+                                                &shutdown_receiver,
+                                                config.read_iteration_wait_time,
+                                            ) || message_sender
+                                                .send(Ok((command, message)))
                                                 .is_err()
                                             {
-                                                info!("Read thread seems to be disconnected from main thread. Will be shut down.");
+                                                debug!("Read thread seems to be disconnected from main thread. Will be shut down.");
                                                 break 'read_loop; //disconnected
                                             }
+                                            continue;
                                         }
-                                    } else if message_sender
-                                        .send(Err(
-                                            ReadThreadErrorsInternal::ImmediateMessageConstructError((
-                                                command, message,
-                                            )),
-                                        ))
-                                        .is_err()
+                                    };
+                                    for (command, message) in replies
+                                        .into_iter()
+                                        .flat_map(|reply| interceptors.outgoing(reply))
                                     {
-                                        debug!("Read thread seems to be disconnected from main thread. Will be shut down.");
-                                        break 'read_loop; //disconnected
+                                        if let Some(frame) = P::construct_message(command, &message)
+                                        {
+                                            // written below, without waiting for the socket
+                                            reply_queue.push(frame, &hooks);
+                                            stats.immediate_reply_sent(command, message.len());
+                                            connection_span.message(
+                                                "sent",
+                                                &command,
+                                                &message,
+                                                config.payload_logging,
+                                            );
+                                            send_event(
+                                                &event_sender,
+                                                ConnectionEvent::ImmediateReplySent(command),
+                                            );
+                                        } else if message_sender
+                                            .send(Err(
+                                                ReadThreadErrorsInternal::ImmediateMessageConstructError((
+                                                    command, message,
+                                                )),
+                                            ))
+                                            .is_err()
+                                        {
+                                            debug!("Read thread seems to be disconnected from main thread. Will be shut down.");
+                                            break 'read_loop; //disconnected
+                                        }
                                     }
-                                } else if message_sender.send(Ok((command, message))).is_err() {
-                                    debug!("Read thread seems to be disconnected from main thread. Will be shut down.");
-                                    break 'read_loop; //disconnected
                                 }
                             }
                         }
+                        if end_of_stream {
+                            if message_length > 0 {
+                                info!("Connection dropped by fault injection");
+                                let _ = tcp_stream_read.shutdown(std::net::Shutdown::Both);
+                            } else {
+                                info!("Peer closed the connection");
+                            }
+                            if let Some(truncated_message) = protocol.take_truncated_message() {
+                                warn!(
+                                    "Truncated message: {:?} {}",
+                                    truncated_message.0,
+                                    PayloadDisplay::new(
+                                        &truncated_message.1,
+                                        config.payload_logging
+                                    )
+                                );
+                                // if sending fails, the main thread is gone anyway
+                                let _ = message_sender.send(Err(
+                                    ReadThreadErrorsInternal::TruncatedMessage(truncated_message),
+                                ));
+                            }
+                            let _ = message_sender.send(Err(ReadThreadErrorsInternal::PeerClosed));
+                            send_event(&event_sender, ConnectionEvent::PeerClosed);
+                            break 'read_loop;
+                        }
                     }
                     Err(err) => {
                         if err.kind() == std::io::ErrorKind::WouldBlock {
                             // nothing to do, this is interpreted as "no message available"
-                        } else if message_sender
-                            .send(Err(ReadThreadErrorsInternal::ReadError(err)))
-                            .is_err()
-                        {
-                            break 'read_loop; //disconnected
+                        } else {
+                            send_event(&event_sender, ConnectionEvent::ReadError(err.kind()));
+                            if message_sender
+                                .send(Err(ReadThreadErrorsInternal::ReadError(err)))
+                                .is_err()
+                            {
+                                break 'read_loop; //disconnected
+                            }
                         }
                     }
                 }
-                // wait between loops
+                if !write_replies(
+                    &reply_queue,
+                    &write_lock,
+                    &mut tcp_stream_read,
+                    &event_sender,
+                    &message_sender,
+                ) {
+                    break 'read_loop; //disconnected
+                }
+                // wait between loops, unless more bytes are likely available already
                 if let Some(read_iteration_wait_time) = config.read_iteration_wait_time {
-                    std::thread::sleep(read_iteration_wait_time);
+                    if !buffer_filled {
+                        std::thread::sleep(read_iteration_wait_time);
+                    }
                 }
             }
             info!("Read thread finished");
@@ -375,44 +710,135 @@ impl<P: Protocol> TcpIpc<P> {
         }
         Ok(TcpIpc {
             shutdown_sender,
-            busy_state_sender,
+            busy_state,
             message_receiver,
             stream: tcp_stream,
             shutdown_wait_time: config.shutdown_wait_time,
-            busy_state_query_sender,
-            busy_state_queried_receiver,
+            event_sender,
+            event_receiver: Some(event_receiver),
+            peer_closed: false,
+            interceptors,
+            immediate_route_sender,
+            stats,
+            connection_span,
+            payload_logging: config.payload_logging,
+            hooks,
+            streaming,
+            stream_receiver,
+            write_lock,
+            replies,
+            write_batching: None,
+            pending_batch: Vec::new(),
+            writer: None,
         })
     }
+    /// This hands out the receiving end of the connection event channel.
+    /// Since there is only one receiving end, this returns None if it was already taken.
+    /// The receiver can be moved to another thread, for example a supervisor which blocks on it.
+    /// If the events are not taken, at most a fixed number of events is queued and later events are dropped.
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let mut client = client();
+    /// let events = client.take_event_receiver().expect("event receiver already taken");
+    /// std::thread::spawn(move || {
+    ///     for event in events {
+    ///         println!("{:?}", event);
+    ///     }
+    /// });
+    /// ```
+    pub fn take_event_receiver(&mut self) -> Option<std::sync::mpsc::Receiver<ConnectionEvent<P>>> {
+        self.event_receiver.take()
+    }
 
     /// This updates the busy_state.
+    /// The new busy_state is immediately visible to the read thread.
+    /// It fails only if an installed state machine does not allow the transition (see set_busy_state_machine), a closed connection is no failure.
     /// # Example
-    /// ```
+    /// ```ignore
     /// client.update_busy_state(BusyStatesExample::Working);
     /// ```
     pub fn update_busy_state(&mut self, new_busy_state: P::BusyStates) -> BusyStateUpdateResult {
-        match self.busy_state_sender.send(new_busy_state) {
-            Ok(()) => BusyStateUpdateResult::Success,
-            Err(_) => BusyStateUpdateResult::Disconnected,
+        match self.busy_state.set(new_busy_state) {
+            Some(_) => BusyStateUpdateResult::Success,
+            None => BusyStateUpdateResult::TransitionNotAllowed,
         }
     }
-    /// This queries the current busy_state.
+    /// This replaces the handler for the immediate route (see ImmediateRouteHandler).
+    /// The default handler uses Protocol::message_is_answered_via_immediate_route.
+    /// The new handler is used by the read thread after its next check (see TcpIpcConfig::check_count).
+    /// An installed state machine is kept (see set_busy_state_machine): the handler then only receives the commands without an action in the current busy_state.
     /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let mut client = client();
+    /// client.set_immediate_route_handler(ProtocolImmediateRoute);
     /// ```
+    pub fn set_immediate_route_handler<H: ImmediateRouteHandler<P>>(
+        &mut self,
+        handler: H,
+    ) -> ImmediateRouteHandlerUpdateResult {
+        self.update_immediate_route(ImmediateRouteUpdate::Handler(Box::new(handler)))
+    }
+    fn update_immediate_route(
+        &mut self,
+        update: ImmediateRouteUpdate<P>,
+    ) -> ImmediateRouteHandlerUpdateResult {
+        match self.immediate_route_sender.send(update) {
+            Ok(()) => ImmediateRouteHandlerUpdateResult::Success,
+            Err(_) => ImmediateRouteHandlerUpdateResult::Disconnected,
+        }
+    }
+    /// This installs a state machine for the busy_state (see BusyStateMachine).
+    /// The read thread then answers, rejects or forwards messages according to the current busy_state and performs the declared transitions.
+    /// Moreover, update_busy_state and the busy_state handle (see busy_state_handle) refuse transitions which are not allowed.
+    /// The handler for the immediate route is kept and receives the commands without an action in the current busy_state (see set_immediate_route_handler).
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let mut client = client();
+    /// # let state_machine = BusyStateMachine::<ProtocolExample>::new();
+    /// client.set_busy_state_machine(state_machine);
+    /// ```
+    pub fn set_busy_state_machine(
+        &mut self,
+        state_machine: BusyStateMachine<P>,
+    ) -> ImmediateRouteHandlerUpdateResult {
+        // the read thread gets its own copy, so the busy_states need not be Sync
+        self.busy_state.set_state_machine(state_machine.clone());
+        self.update_immediate_route(ImmediateRouteUpdate::StateMachine(state_machine))
+    }
+    /// This queries the current busy_state.
+    /// The query never fails, the Result is kept for compatibility (see BusyStateQueryResult).
+    /// # Example
+    /// ```ignore
     /// let current_busy_state = client.get_busy_state();
     /// ```
     pub fn get_busy_state(&mut self) -> Result<P::BusyStates, BusyStateQueryResult> {
-        match self.busy_state_query_sender.send(()) {
-            Ok(()) => match self.busy_state_queried_receiver.recv() {
-                Ok(busy_state) => Ok(busy_state),
-                Err(std::sync::mpsc::RecvError) => Err(BusyStateQueryResult::Disconnected),
-            },
-            Err(std::sync::mpsc::SendError(())) => Err(BusyStateQueryResult::Disconnected),
-        }
+        Ok(self.busy_state.get())
+    }
+    /// This returns a handle to the busy_state, which can be moved to another thread.
+    /// It can be used to query or update the busy_state, or to register callbacks which are called on every transition.
+    /// Transitions are also reported as ConnectionEvent::BusyStateChanged.
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let mut client = client();
+    /// let busy_state = client.busy_state_handle();
+    /// busy_state.add_change_callback(|old, new| println!("busy_state: {:?} -> {:?}", old, new));
+    /// ```
+    pub fn busy_state_handle(&self) -> SharedBusyState<P> {
+        self.busy_state.clone()
     }
     /// This function check if a message was received and returns it, if so.
     /// If no message is available (or if a message is only partial available and more data is neceesary), Ok(None) is return.
+    /// If the peer closed the connection, Err(ReadThreadErrors::PeerClosed) is returned once all received messages are returned.
     /// # Example
-    /// ```
+    /// ```ignore
     /// let message = client.get_message();
     /// ```
     pub fn get_message(&mut self) -> Result<Option<Message<P>>, ReadThreadErrors<P>> {
@@ -424,7 +850,21 @@ impl<P: Protocol> TcpIpc<P> {
                 ReadThreadErrorsInternal::ImmediateMessageConstructError(x) => {
                     ReadThreadErrors::ImmediateMessageConstructError(x)
                 }
+                ReadThreadErrorsInternal::TruncatedMessage(x) => {
+                    ReadThreadErrors::TruncatedMessage(x)
+                }
+                ReadThreadErrorsInternal::ParseHeaderFailed(x) => {
+                    ReadThreadErrors::ParseHeaderFailed(x)
+                }
+                ReadThreadErrorsInternal::PeerClosed => {
+                    self.peer_closed = true;
+                    ReadThreadErrors::PeerClosed
+                }
+                ReadThreadErrorsInternal::QueueOverflow(x) => ReadThreadErrors::QueueOverflow(x),
             }),
+            Err(TryRecvError::Disconnected) if self.peer_closed => {
+                Err(ReadThreadErrors::PeerClosed)
+            }
             Err(TryRecvError::Disconnected) => Err(ReadThreadErrors::Disconnected),
             Err(TryRecvError::Empty) => Ok(None),
         }
@@ -433,7 +873,7 @@ impl<P: Protocol> TcpIpc<P> {
     /// To do this, it waits a given duration.
     /// Then it calls get_message until no message is received, or an error is received (which is returned in turn).
     /// # Example
-    /// ```
+    /// ```ignore
     /// let result = client.clear_message_queue(std::time::Duration::from_micros(10_000));
     /// ```
     pub fn clear_message_queue(
@@ -456,7 +896,7 @@ impl<P: Protocol> TcpIpc<P> {
     /// If some message is received, Ok(Some((command, payload))) is returned.
     /// If an error happens, Err(x) is returned.
     /// # Example
-    /// ```
+    /// ```ignore
     /// let message = client.await_message(std::time::Duration::from_micros(10_000), std::time::Duration::from_nanos(2_000));
     /// ```
     pub fn await_message(
@@ -482,8 +922,11 @@ impl<P: Protocol> TcpIpc<P> {
     /// Then the message header is added and send via TCP, including the message.
     /// If an error occurs, Err(x) is returned.
     /// If the message is writen successfully, Ok(()) is returned.
+    /// The message passes the interceptors first (see add_interceptor), so possibly several or no messages are sent.
+    /// Header & payload are written together (via a vectored write), without copying the payload.
+    /// If write batching is enabled (see set_write_batching), the message is only queued.
     /// # Example
-    /// ```
+    /// ```ignore
     /// let message = client.write_message(ProtocolExampleCommands::Start, "ok".as_bytes());
     /// ```
     pub fn write_message(
@@ -491,18 +934,508 @@ impl<P: Protocol> TcpIpc<P> {
         command: P::Commands,
         message_: &[u8],
     ) -> Result<(), WriteMessageErrors> {
-        let message = P::construct_message(command, message_)
+        self.write_batch(&[(command, message_)])
+    }
+    /// This function writes/sends several messages at once, with as few writes as possible.
+    /// This is useful for high message rates, for example for telemetry.
+    /// Each message passes the interceptors first (see add_interceptor).
+    /// If a message cannot be constructed, nothing is sent.
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let mut client = client();
+    /// client.write_batch(&[(CommandsExample::Start, b"1"), (CommandsExample::Start, b"2")]).expect("sending failed");
+    /// ```
+    pub fn write_batch(
+        &mut self,
+        messages: &[(P::Commands, &[u8])],
+    ) -> Result<(), WriteMessageErrors> {
+        if self.interceptors.is_empty() {
+            return self.write_messages(messages);
+        }
+        let intercepted = messages
+            .iter()
+            .flat_map(|(command, message)| self.interceptors.outgoing((*command, message.to_vec())))
+            .collect::<Vec<_>>();
+        let intercepted = intercepted
+            .iter()
+            .map(|(command, message)| (*command, message.as_slice()))
+            .collect::<Vec<_>>();
+        self.write_messages(&intercepted)
+    }
+    /// This enables (or, if None is given, disables) write batching: messages are queued and written together, as soon as the given count of bytes is queued or flush_batch is called.
+    /// This coalesces many small messages into few writes. Queued messages are sent after immediate replies which are sent meanwhile.
+    /// Disabling write batching flushes the queued messages.
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let mut client = client();
+    /// # let samples: Vec<Vec<u8>> = Vec::new();
+    /// client.set_write_batching(Some(64 * 1024)).expect("flushing failed");
+    /// for sample in samples {
+    ///     client.write_message(CommandsExample::Funny, &sample).expect("queueing failed");
+    /// }
+    /// client.flush_batch().expect("sending failed");
+    /// ```
+    pub fn set_write_batching(
+        &mut self,
+        batch_size: Option<usize>,
+    ) -> Result<(), WriteMessageErrors> {
+        self.write_batching = batch_size;
+        if batch_size.is_none() {
+            self.flush_batch()?;
+        }
+        Ok(())
+    }
+    /// This writes all messages which are queued due to write batching (see set_write_batching).
+    /// If the background writer is used, they are handed to it as one frame.
+    pub fn flush_batch(&mut self) -> Result<(), WriteMessageErrors> {
+        if self.pending_batch.is_empty() {
+            return Ok(());
+        }
+        let pending_batch = std::mem::take(&mut self.pending_batch);
+        if let Some(writer) = &self.writer {
+            return writer.queue().push(vec![pending_batch]);
+        }
+        let written = match lock_writes(&self.write_lock, &self.replies, &mut self.stream) {
+            Ok(_write_guard) => write_frame(&mut self.stream, &pending_batch, &self.hooks),
+            Err(err) => Err(err),
+        };
+        written.map_err(|err| {
+            send_event(&self.event_sender, ConnectionEvent::WriteError(err.kind()));
+            WriteMessageErrors::MessageSendFailed(err)
+        })
+    }
+    // writes (or queues, if write batching is enabled) the messages, which have already passed the interceptors
+    fn write_messages(
+        &mut self,
+        messages: &[(P::Commands, &[u8])],
+    ) -> Result<(), WriteMessageErrors> {
+        let headers = messages
+            .iter()
+            .map(|(command, message)| P::construct_message_header(*command, message))
+            .collect::<Option<Vec<_>>>()
             .ok_or(WriteMessageErrors::MessageConstructionFailed)?;
-        let result = self
-            .stream
-            .write_all(&message)
-            .map_err(WriteMessageErrors::MessageSendFailed);
-        info!("Message send succesfully:{:?}", (command, message_));
-        result
+        if let Some(batch_size) = self.write_batching {
+            for (header, (_, message)) in headers.iter().zip(messages) {
+                self.pending_batch.extend_from_slice(header);
+                self.pending_batch.extend_from_slice(message);
+            }
+            self.messages_sent(messages);
+            if self.pending_batch.len() >= batch_size {
+                self.flush_batch()?;
+            }
+            return Ok(());
+        }
+        if let Some(writer) = &self.writer {
+            let frames = headers
+                .iter()
+                .zip(messages)
+                .map(|(header, (_, message))| [header.as_slice(), message].concat())
+                .collect();
+            writer.queue().push(frames)?;
+            self.messages_sent(messages);
+            return Ok(());
+        }
+        let parts = headers
+            .iter()
+            .zip(messages)
+            .flat_map(|(header, (_, message))| vec![header.as_slice(), message])
+            .collect::<Vec<_>>();
+        let written = match lock_writes(&self.write_lock, &self.replies, &mut self.stream) {
+            Ok(_write_guard) => write_parts(&mut self.stream, &parts, &self.hooks),
+            Err(err) => Err(err),
+        };
+        written.map_err(|err| {
+            send_event(&self.event_sender, ConnectionEvent::WriteError(err.kind()));
+            WriteMessageErrors::MessageSendFailed(err)
+        })?;
+        self.messages_sent(messages);
+        Ok(())
+    }
+    /// This starts (or, if None is given, stops) a background writer thread: afterwards, written messages are only queued and the writer thread writes them to the Tcp-Stream.
+    /// Writing then does not wait for a slow peer, unless the queue is full (see OutgoingQueuePolicy).
+    /// Each frame is written as a whole, so frames never interleave, even with immediate replies of the read thread.
+    /// If a write fails, the remaining queued frames are dropped and further writing fails.
+    /// Before a background writer is replaced or stopped, all queued frames are written.
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let mut client = client();
+    /// client
+    ///     .set_background_writer(Some(BackgroundWriterConfig {
+    ///         queue_capacity: 1024,
+    ///         full_policy: OutgoingQueuePolicy::DropOldest,
+    ///     }))
+    ///     .expect("starting the writer failed");
+    /// ```
+    pub fn set_background_writer(
+        &mut self,
+        config: Option<BackgroundWriterConfig>,
+    ) -> Result<(), WriteMessageErrors> {
+        if let Some(writer) = self.writer.take() {
+            writer.stop()?;
+        }
+        if let Some(config) = config {
+            let stream = self
+                .stream
+                .try_clone()
+                .map_err(WriteMessageErrors::MessageSendFailed)?;
+            self.writer = Some(self.start_writer_thread(config, stream));
+        }
+        Ok(())
+    }
+    fn start_writer_thread(
+        &self,
+        config: BackgroundWriterConfig,
+        mut stream: TcpStream,
+    ) -> BackgroundWriter {
+        let queue = OutgoingQueue::new(config);
+        let queue_write = queue.clone();
+        let hooks = self.hooks.clone();
+        let write_lock = self.write_lock.clone();
+        let replies = self.replies.clone();
+        let event_sender = self.event_sender.clone();
+        let thread = std::thread::spawn(move || {
+            info!("Writer thread started");
+            while let Some(frame) = queue_write.pop() {
+                let written = match lock_writes(&write_lock, &replies, &mut stream) {
+                    Ok(_write_guard) => write_frame(&mut stream, &frame, &hooks),
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = &written {
+                    send_event(&event_sender, ConnectionEvent::WriteError(err.kind()));
+                }
+                queue_write.written(written.map_err(|err| err.kind()));
+            }
+            info!("Writer thread finished");
+        });
+        BackgroundWriter::new(queue, thread)
+    }
+    /// This writes all queued messages (see set_write_batching) and waits at most the given time until the background writer (if any) has written them to the Tcp-Stream.
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let mut client = client();
+    /// client.flush(std::time::Duration::from_secs(1)).expect("flushing failed");
+    /// ```
+    pub fn flush(
+        &mut self,
+        maximal_wait_time: std::time::Duration,
+    ) -> Result<(), WriteMessageErrors> {
+        self.flush_batch()?;
+        match &self.writer {
+            Some(writer) => writer.queue().wait_until_written(Some(maximal_wait_time)),
+            None => Ok(()),
+        }
+    }
+    /// This returns the count of frames which the background writer has not yet written completely (zero without a background writer).
+    pub fn outgoing_queue_len(&self) -> usize {
+        self.writer
+            .as_ref()
+            .map_or(0, |writer| writer.queue().len())
+    }
+    fn messages_sent(&self, messages: &[(P::Commands, &[u8])]) {
+        for (command, message) in messages {
+            self.stats.message_sent(*command, message.len());
+            self.connection_span
+                .message("sent", command, message, self.payload_logging);
+        }
+    }
+    /// This function writes/sends a message whose payload is read from the reader, so that it is not in memory as a whole.
+    /// The header is constructed for the given payload length (see Protocol::construct_header_for_length), which fails with MessageConstructionFailed unless the protocol implements Protocol::length_to_array.
+    /// Then exactly this count of bytes is read & sent in chunks. The interceptors are not applied.
+    /// No other message (not even an immediate reply) is sent meanwhile, and the send buffer of the socket is enlarged.
+    /// If the reader ends early, the peer still waits for the rest of the payload, so the connection is unusable: an UnexpectedEof error is returned.
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let mut client = client();
+    /// let mut file = std::fs::File::open("measurement.bin").expect("opening failed");
+    /// let length = file.metadata().expect("metadata failed").len() as usize;
+    /// client.write_message_from_reader(CommandsExample::Start, length, &mut file).expect("sending failed");
+    /// ```
+    pub fn write_message_from_reader<R: Read>(
+        &mut self,
+        command: P::Commands,
+        length: usize,
+        reader: &mut R,
+    ) -> Result<(), WriteMessageErrors> {
+        let header = P::construct_header_for_length(command, length)
+            .ok_or(WriteMessageErrors::MessageConstructionFailed)?;
+        // the queued frames are sent first
+        if let Some(writer) = &self.writer {
+            writer.queue().wait_until_written(None)?;
+        }
+        let write_guard =
+            lock_writes(&self.write_lock, &self.replies, &mut self.stream).map_err(|err| {
+                send_event(&self.event_sender, ConnectionEvent::WriteError(err.kind()));
+                WriteMessageErrors::MessageSendFailed(err)
+            })?;
+        // the small default send buffer would throttle the transfer
+        if let Err(err) = self.stream.set_send_buffer_size(STREAM_CHUNK_SIZE) {
+            warn!("Enlarging the send buffer failed: {:?}", err);
+        }
+        let mut chunk = vec![0; STREAM_CHUNK_SIZE.min(length)];
+        let mut remaining = length;
+        let mut written = write_frame(&mut self.stream, &header, &self.hooks);
+        while written.is_ok() && remaining > 0 {
+            let chunk_length = chunk.len().min(remaining);
+            written = match reader.read(&mut chunk[..chunk_length]) {
+                Ok(0) => Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "the reader ended before the declared payload length",
+                )),
+                Ok(read) => {
+                    remaining -= read;
+                    write_frame(&mut self.stream, &chunk[..read], &self.hooks)
+                }
+                Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => Ok(()),
+                Err(err) => Err(err),
+            };
+        }
+        let _ = self.stream.set_send_buffer_size(header_size::<P>());
+        drop(write_guard);
+        written.map_err(|err| {
+            send_event(&self.event_sender, ConnectionEvent::WriteError(err.kind()));
+            WriteMessageErrors::MessageSendFailed(err)
+        })?;
+        self.stats.message_sent(command, length);
+        debug!("Streamed message sent: {:?}, length {}", command, length);
+        Ok(())
+    }
+    /// This sets the payload length from which on received messages are streamed (None disables streaming, which is the default).
+    /// A streamed message is not collected in memory, but handed out as soon as its header arrives (see get_stream & IncomingStream).
+    /// Its payload is then delivered in chunks as they arrive.
+    /// Streamed messages do not pass the interceptors and the immediate route, and their order relative to other messages is lost.
+    /// While streaming is enabled, the receive buffer of the socket is enlarged.
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let mut client = client();
+    /// client.set_streaming_threshold(Some(1 << 20));
+    /// ```
+    pub fn set_streaming_threshold(&mut self, threshold: Option<usize>) {
+        self.streaming.set(threshold);
+        // the small default receive buffer would throttle large transfers
+        let buffer_size = match threshold {
+            Some(_) => STREAM_CHUNK_SIZE,
+            None => header_size::<P>(),
+        };
+        if let Err(err) = self.stream.set_recv_buffer_size(buffer_size) {
+            warn!("Setting the receive buffer size failed: {:?}", err);
+        }
+    }
+    /// This returns a streamed message (see set_streaming_threshold), if one was started.
+    /// Since each message is only returned once, its payload has to be read from the returned stream.
+    pub fn get_stream(&mut self) -> Option<IncomingStream<P>> {
+        self.stream_receiver.try_recv().ok()
+    }
+    /// This waits at most the given time for a streamed message (see get_stream).
+    pub fn await_stream(
+        &mut self,
+        maximal_wait_time: std::time::Duration,
+    ) -> Option<IncomingStream<P>> {
+        self.stream_receiver.recv_timeout(maximal_wait_time).ok()
+    }
+    /// This sends a heartbeat message and starts the round trip time measurement.
+    /// The measurement stops as soon as the read thread receives a message for which Protocol::is_heartbeat_reply is true.
+    /// The round trip time is reported via stats().
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let mut client = client();
+    /// client.send_heartbeat(CommandsExample::Funny, &[]);
+    /// ```
+    pub fn send_heartbeat(
+        &mut self,
+        command: P::Commands,
+        message: &[u8],
+    ) -> Result<(), WriteMessageErrors> {
+        self.stats.heartbeat_sent();
+        self.write_message(command, message)
+    }
+    /// This returns a snapshot of the statistics of this connection (see ConnectionStats).
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let mut client = client();
+    /// let stats = client.stats();
+    /// println!("{}", stats.to_prometheus("client"));
+    /// ```
+    pub fn stats(&self) -> ConnectionStats<P> {
+        let mut stats = self.stats.snapshot();
+        stats.queue_depth = self.message_receiver.len();
+        stats.messages_dropped = self.message_receiver.dropped();
+        stats
+    }
+    /// This bounds (or, if None is given, unbounds) the queue of received messages, which are waiting to be taken via get_message.
+    /// By default, the queue is unbounded, so a slow consumer lets the memory grow while the peer keeps sending.
+    /// The limit applies to messages received afterwards. Dropped messages are counted in stats().
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let mut client = client();
+    /// client.set_incoming_queue_limit(Some(IncomingQueueLimit {
+    ///     capacity: 1024,
+    ///     full_policy: IncomingQueuePolicy::StopReading,
+    /// }));
+    /// ```
+    pub fn set_incoming_queue_limit(&mut self, limit: Option<IncomingQueueLimit>) {
+        self.message_receiver.set_limit(limit);
+    }
+    /// This returns the count of received messages which are waiting to be taken via get_message.
+    pub fn incoming_queue_len(&self) -> usize {
+        self.message_receiver.len()
+    }
+    /// This starts (or, if None is given, stops) recording all bytes sent and received (see SessionRecorder).
+    /// The recorder is used immediately by both the main thread and the read thread.
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let mut client = client();
+    /// client.set_recorder(Some(SessionRecorder::create("session.rec").expect("unable to create recording")));
+    /// ```
+    pub fn set_recorder(&mut self, recorder: Option<SessionRecorder>) {
+        self.hooks.recorder.set(recorder);
+    }
+    /// This injects the given faults (or, if None is given, no faults) into the bytes received, before they are decoded (see FaultPlan).
+    /// This allows to test the reassembly of messages & the error handling deterministically.
+    /// A disconnect is handled like the peer closing the connection.
+    /// This is only available with the feature "testing".
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let mut client = client();
+    /// // deliver every received byte separately & drop the connection after 20 bytes
+    /// client.set_incoming_faults(Some(FaultPlan::new().fragment(vec![1]).disconnect_after(20)));
+    /// ```
+    #[cfg(feature = "testing")]
+    pub fn set_incoming_faults(&mut self, plan: Option<FaultPlan>) {
+        self.hooks.faults.set_incoming(plan);
+    }
+    /// This injects the given faults (or, if None is given, no faults) into the bytes sent, including immediate replies (see FaultPlan).
+    /// After a disconnect, the connection is shut down and writing fails.
+    /// This is only available with the feature "testing".
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let mut client = client();
+    /// // send frames in chunks of 3 bytes, each delayed by 1ms
+    /// client.set_outgoing_faults(Some(FaultPlan::new().fragment(vec![3]).delay(std::time::Duration::from_millis(1))));
+    /// ```
+    #[cfg(feature = "testing")]
+    pub fn set_outgoing_faults(&mut self, plan: Option<FaultPlan>) {
+        self.hooks.faults.set_outgoing(plan);
+    }
+    /// This appends an interceptor to the chain of interceptors (see Interceptor).
+    /// Outgoing messages pass the interceptors in the order they were added, incoming messages in reverse order.
+    /// The interceptor is used immediately by both the main thread and the read thread.
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let mut client = client();
+    /// # struct PayloadLogger;
+    /// # impl Interceptor<ProtocolExample> for PayloadLogger {}
+    /// client.add_interceptor(PayloadLogger);
+    /// ```
+    pub fn add_interceptor<I: Interceptor<P>>(&mut self, interceptor: I) {
+        self.interceptors.push(Box::new(interceptor));
     }
     /// Attemps to close the TCP-connection
     /// Since the receiving side might not implement any shutdown functionality, this is optionally (and not included in Drop).
+    /// Queued messages (see set_write_batching & set_background_writer) are written first, but the shutdown wait time is not exceeded for them:
+    /// the messages which are not written within the wait time (or immediately, if it is None) are dropped.
     pub fn shutdown(self) -> Result<(), ShutdownError> {
+        let shutdown_wait_time = self.shutdown_wait_time;
+        self.shutdown_internal(shutdown_wait_time)
+    }
+    /// Attemps to close the TCP-connection via the graceful shutdown handshake of the protocol (see Protocol::shutdown_request).
+    /// First, the shutdown request is sent.
+    /// Then all incoming messages are drained until the peer acknowledges the request (or the wait time is exceeded).
+    /// Finally, the TCP-connection is closed.
+    /// If successful, the drained messages (received after the request was sent, excluding the acknowledgement) are returned.
+    /// Otherwise, the error indicates which stage failed.
+    /// # Example
+    /// ```no_run
+    /// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+    /// # use doc_setup::*;
+    /// # let mut client = client();
+    /// let drained_messages = client
+    ///     .graceful_shutdown(std::time::Duration::from_secs(1), None)
+    ///     .expect("Graceful shutdown failed.");
+    /// ```
+    pub fn graceful_shutdown(
+        mut self,
+        acknowledgement_wait_time: std::time::Duration,
+        iteration_wait_time: Option<std::time::Duration>,
+    ) -> Result<Vec<Message<P>>, GracefulShutdownError<P>> {
+        let (command, message) =
+            P::shutdown_request().ok_or(GracefulShutdownError::NotSupportedByProtocol)?;
+        self.write_message(command, &message)
+            .and_then(|_| self.flush(acknowledgement_wait_time))
+            .map_err(GracefulShutdownError::RequestSendFailed)?;
+        debug!("Shutdown request send successfully.");
+        let mut drained_messages = Vec::new();
+        let instant = std::time::Instant::now();
+        loop {
+            match self.get_message() {
+                Ok(Some((command, message))) => {
+                    if P::is_shutdown_acknowledgement(&command, &message) {
+                        debug!("Shutdown request acknowledged.");
+                        break;
+                    }
+                    drained_messages.push((command, message));
+                }
+                Ok(None) => {
+                    if instant.elapsed() >= acknowledgement_wait_time {
+                        warn!("Shutdown request was not acknowledged.");
+                        return Err(GracefulShutdownError::AcknowledgementWaitTimeExceeded(
+                            drained_messages,
+                        ));
+                    }
+                    if let Some(iteration_wait_time) = iteration_wait_time {
+                        std::thread::sleep(iteration_wait_time)
+                    }
+                }
+                Err(err) => {
+                    warn!("Draining messages failed.");
+                    return Err(GracefulShutdownError::DrainFailed((err, drained_messages)));
+                }
+            }
+        }
+        // the read thread might already be finished (if the peer closed the connection), which is fine here
+        match self.shutdown_internal(None) {
+            Err(err) if !err.shutdown_succesfully => Err(GracefulShutdownError::ShutdownFailed((
+                err,
+                drained_messages,
+            ))),
+            _ => Ok(drained_messages),
+        }
+    }
+    fn shutdown_internal(
+        mut self,
+        shutdown_wait_time: Option<std::time::Duration>,
+    ) -> Result<(), ShutdownError> {
+        let shutdown_wait_time = shutdown_wait_time.unwrap_or_default();
+        // writing the queued messages & waiting for the read thread share the wait time
+        let deadline = std::time::Instant::now() + shutdown_wait_time;
+        if let Err(err) = self.flush(shutdown_wait_time) {
+            warn!("Flushing the queued messages failed: {:?}", err);
+        }
         let shutdown_requested_succesfully = match self.shutdown_sender.send(()) {
             Ok(()) => {
                 debug!("Shutdown send successfully.");
@@ -514,8 +1447,9 @@ impl<P: Protocol> TcpIpc<P> {
             }
         };
 
-        if let Some(shutdown_wait_time) = self.shutdown_wait_time {
-            std::thread::sleep(shutdown_wait_time);
+        let remaining_wait_time = deadline.saturating_duration_since(std::time::Instant::now());
+        if remaining_wait_time > std::time::Duration::from_secs(0) {
+            std::thread::sleep(remaining_wait_time);
         }
         let shutdown_succesfully = match self.stream.shutdown(std::net::Shutdown::Both) {
             Ok(()) => {
@@ -527,14 +1461,19 @@ impl<P: Protocol> TcpIpc<P> {
                 false
             }
         };
-        if !shutdown_requested_succesfully || !shutdown_succesfully {
+        let result = if !shutdown_requested_succesfully || !shutdown_succesfully {
             Err(ShutdownError {
                 shutdown_succesfully,
                 shutdown_requested_succesfully,
             })
         } else {
             Ok(())
-        }
+        };
+        send_event(
+            &self.event_sender,
+            ConnectionEvent::ShutdownComplete(result),
+        );
+        result
     }
     /// Attemps to change the Tcp-Stream "NoDelay"-Option
     pub fn set_nodelay(&mut self, no_delay: bool) -> Result<(), std::io::Error> {
@@ -544,6 +1483,96 @@ impl<P: Protocol> TcpIpc<P> {
     pub fn get_nodelay(&self) -> Result<bool, std::io::Error> {
         self.stream.nodelay()
     }
+    /// Attemps to get the local socket address of the Tcp-Stream
+    pub fn local_addr(&self) -> Result<std::net::SocketAddr, std::io::Error> {
+        self.stream.local_addr()
+    }
+    /// Attemps to get the socket address of the remote peer of the Tcp-Stream
+    pub fn peer_addr(&self) -> Result<std::net::SocketAddr, std::io::Error> {
+        self.stream.peer_addr()
+    }
+}
+/// This is a bound, but not yet connected server.
+/// In contrast to TcpIpc::server, binding & waiting for a client are separated.
+/// This allows to bind to port 0 and to query the port chosen by the operating system before a client connects.
+/// # Example
+/// ```no_run
+/// # mod doc_setup { include!("../benches/doc_setup.rs"); }
+/// # use doc_setup::*;
+/// # let config = config();
+/// let listener = TcpIpcListener::bind("127.0.0.1:0").expect("binding failed");
+/// let port = listener.local_addr().expect("no local address").port();
+/// // ... tell the client the port ...
+/// let mut server = listener
+///     .accept::<ProtocolExample>(config, Some(std::time::Duration::from_secs(5)))
+///     .expect("connecting failed");
+/// ```
+#[derive(Debug)]
+pub struct TcpIpcListener {
+    listener: TcpListener,
+    // the listener is registered once, since mio refuses to register it with a second Poll
+    poll: mio::Poll,
+}
+impl TcpIpcListener {
+    /// This binds to the first socket address of the given list which can be bound.
+    pub fn bind<T: ToSocketAddrs>(socket_addresses: T) -> Result<TcpIpcListener, ConnectErrors> {
+        let mut error = self::ConnectErrors::SocketListIsEmpty;
+        let socket_addresses = socket_addresses
+            .to_socket_addrs()
+            .map_err(ConnectErrors::SocketListParseError)?;
+        for socket_address in socket_addresses {
+            debug!("trying to bind {:?}", socket_address);
+            match TcpListener::bind(&socket_address).and_then(|listener| {
+                let poll = register_listener(&listener)?;
+                Ok((listener, poll))
+            }) {
+                Ok((listener, poll)) => {
+                    info!("bound to {:?}", socket_address);
+                    return Ok(TcpIpcListener { listener, poll });
+                }
+                Err(err) => {
+                    info!("Received error: {:?}", err);
+                    error = ConnectErrors::BindError(err);
+                }
+            }
+        }
+        Err(error)
+    }
+    /// Attemps to get the local socket address the listener is bound to.
+    /// If bound to port 0, this yields the port chosen by the operating system.
+    pub fn local_addr(&self) -> Result<std::net::SocketAddr, std::io::Error> {
+        self.listener.local_addr()
+    }
+    /// This waits for a client to connect. Afterwards it can be used to send and receive commands.
+    /// The input variable 'accept_wait_time' is the time the server waits for a client to connect.
+    /// A 'None' value yields an infinite waiting period.
+    pub fn accept<P: Protocol>(
+        &self,
+        config: TcpIpcConfig,
+        accept_wait_time: Option<std::time::Duration>,
+    ) -> Result<TcpIpc<P>, ConnectErrors> {
+        self.accept_cancellable(config, accept_wait_time, &CancelHandle::new())
+    }
+    /// This is the same as 'accept', but the waiting for a client can be aborted from another thread via the given handle.
+    /// In this case, ConnectErrors::Cancelled is returned.
+    pub fn accept_cancellable<P: Protocol>(
+        &self,
+        config: TcpIpcConfig,
+        accept_wait_time: Option<std::time::Duration>,
+        cancel_handle: &CancelHandle,
+    ) -> Result<TcpIpc<P>, ConnectErrors> {
+        check_protocol::<P>()?;
+        match accept(&self.listener, &self.poll, accept_wait_time, cancel_handle)? {
+            Ok((stream, socket_address)) => {
+                info!("connected to {:?}", socket_address);
+                TcpIpc::start_read_thread(stream, config)
+            }
+            Err(err) => {
+                info!("Received error: {:?}", err);
+                Err(ConnectErrors::ConnectionError(err))
+            }
+        }
+    }
 }
 /// The error type for a shutdown attemp.
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -553,3 +1582,268 @@ pub struct ShutdownError {
     /// Indicates if the shutdown was successful.
     pub shutdown_succesfully: bool,
 }
+/// The error type for a graceful shutdown attemp.
+/// Each variant corresponds to the stage which failed.
+/// If available, the messages drained so far are included.
+#[derive(Debug)]
+pub enum GracefulShutdownError<P: Protocol> {
+    /// The protocol does not define a shutdown request (see Protocol::shutdown_request).
+    NotSupportedByProtocol,
+    /// Sending the shutdown request failed.
+    RequestSendFailed(WriteMessageErrors),
+    /// Receiving messages failed while waiting for the acknowledgement.
+    DrainFailed((ReadThreadErrors<P>, Vec<Message<P>>)),
+    /// The peer did not acknowledge the shutdown request in time.
+    AcknowledgementWaitTimeExceeded(Vec<Message<P>>),
+    /// The shutdown request was acknowledged, but closing the TCP-connection failed.
+    ShutdownFailed((ShutdownError, Vec<Message<P>>)),
+}
+
+fn check_protocol<P: Protocol>() -> Result<(), ConnectErrors> {
+    if P::is_ready() {
+        Ok(())
+    } else {
+        warn!("The protocol is not ready, for example its description is not installed");
+        Err(ConnectErrors::ProtocolNotReady)
+    }
+}
+// the header size is the length of the shortest input which contains a complete header
+// (the header may be unsized, see DynamicProtocol)
+fn header_size<P: Protocol>() -> usize {
+    const MAXIMAL_HEADER_SIZE: usize = 256;
+    let input = [0u8; MAXIMAL_HEADER_SIZE];
+    (0..=MAXIMAL_HEADER_SIZE)
+        .find(|&size| P::message_slice_to_header_array(&input[..size]).is_some())
+        .unwrap_or(MAXIMAL_HEADER_SIZE)
+}
+// the write lock is not poisoned by a panic, since it protects no data
+// the queued immediate replies are written first, so they are not overtaken
+fn lock_writes<'a>(
+    write_lock: &'a std::sync::Mutex<()>,
+    replies: &ReplyQueue,
+    stream: &mut TcpStream,
+) -> std::io::Result<std::sync::MutexGuard<'a, ()>> {
+    let guard = match write_lock.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    replies.write_all(stream)?;
+    Ok(guard)
+}
+// writes the queued immediate replies of the read thread, unless another thread is writing (which then writes them)
+// the read thread never waits for the socket (or the write lock), since a writer may wait for the peer, which may wait for the read thread
+// returns false if the main thread is gone
+fn write_replies<P: Protocol>(
+    replies: &ReplyQueue,
+    write_lock: &std::sync::Mutex<()>,
+    stream: &mut TcpStream,
+    event_sender: &std::sync::mpsc::SyncSender<ConnectionEvent<P>>,
+    message_sender: &IncomingSender<P>,
+) -> bool {
+    if replies.is_empty() {
+        return true;
+    }
+    let _write_guard = match write_lock.try_lock() {
+        Ok(guard) => guard,
+        Err(std::sync::TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        Err(std::sync::TryLockError::WouldBlock) => return true,
+    };
+    match replies.write_without_blocking(stream) {
+        Ok(_) => true,
+        Err(err) => {
+            send_event(event_sender, ConnectionEvent::WriteError(err.kind()));
+            message_sender
+                .send(Err(ReadThreadErrorsInternal::WriteError(err)))
+                .is_ok()
+        }
+    }
+}
+// the stream is non-blocking, so writes are retried until the send buffer has room again
+pub(crate) struct BlockingWriter<'a>(pub(crate) &'a mut TcpStream);
+impl<'a> Write for BlockingWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        loop {
+            match self.0.write(buf) {
+                Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(WRITE_RETRY_WAIT_TIME)
+                }
+                result => return result,
+            }
+        }
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+// the hooks applied to the transferred bytes, shared between the main thread, the read thread & the writer thread
+// faults are only injected with the feature "testing", so otherwise no lock is taken for them
+#[derive(Debug, Clone)]
+pub(crate) struct TransferHooks {
+    pub(crate) recorder: RecorderSlot,
+    #[cfg(feature = "testing")]
+    pub(crate) faults: FaultSlot,
+}
+impl TransferHooks {
+    fn new() -> Self {
+        Self {
+            recorder: RecorderSlot::new(),
+            #[cfg(feature = "testing")]
+            faults: FaultSlot::new(),
+        }
+    }
+}
+// writes the concatenated parts (for example header & payload) with vectored writes, injecting the outgoing faults (if any)
+fn write_parts(
+    stream: &mut TcpStream,
+    parts: &[&[u8]],
+    hooks: &TransferHooks,
+) -> std::io::Result<()> {
+    #[cfg(feature = "testing")]
+    {
+        if hooks.faults.has_outgoing() {
+            return write_frame(stream, &parts.concat(), hooks);
+        }
+    }
+    hooks.recorder.record_parts(Direction::Outgoing, parts);
+    // empty parts cannot be written vectored
+    let mut parts = parts
+        .iter()
+        .copied()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>();
+    let mut index = 0;
+    while index < parts.len() {
+        let io_vecs = parts[index..]
+            .iter()
+            .take(MAXIMAL_IO_VECS)
+            .filter_map(|part| IoVec::from_bytes(part))
+            .collect::<Vec<_>>();
+        match stream.write_bufs(&io_vecs) {
+            Ok(0) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::WriteZero,
+                    "failed to write the whole frame",
+                ))
+            }
+            Ok(mut written) => {
+                // skip the written parts, the last one possibly partially
+                while written > 0 {
+                    if written >= parts[index].len() {
+                        written -= parts[index].len();
+                        index += 1;
+                    } else {
+                        parts[index] = &parts[index][written..];
+                        written = 0;
+                    }
+                }
+            }
+            Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(WRITE_RETRY_WAIT_TIME)
+            }
+            Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+// waits until the message queue has room (see IncomingQueuePolicy::StopReading), since a single read can yield many messages
+// false is returned if the read thread is to be shut down meanwhile
+fn wait_for_queue_room<P: Protocol>(
+    message_sender: &IncomingSender<P>,
+    shutdown_receiver: &std::sync::mpsc::Receiver<()>,
+    read_iteration_wait_time: Option<std::time::Duration>,
+) -> bool {
+    while message_sender.is_full() {
+        if shutdown_receiver.try_recv() != Err(std::sync::mpsc::TryRecvError::Empty) {
+            return false;
+        }
+        if let Some(read_iteration_wait_time) = read_iteration_wait_time {
+            std::thread::sleep(read_iteration_wait_time);
+        }
+    }
+    true
+}
+// writes a frame, injecting the outgoing faults (if any)
+fn write_frame(stream: &mut TcpStream, frame: &[u8], hooks: &TransferHooks) -> std::io::Result<()> {
+    #[cfg(feature = "testing")]
+    {
+        if let Some(output) = hooks.faults.outgoing(frame) {
+            for chunk in &output.chunks {
+                hooks.recorder.record(Direction::Outgoing, chunk);
+            }
+            if output.write_to(&mut BlockingWriter(stream))? {
+                info!("Connection dropped by fault injection");
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionAborted,
+                    "connection dropped by fault injection",
+                ));
+            }
+            return Ok(());
+        }
+    }
+    hooks.recorder.record(Direction::Outgoing, frame);
+    BlockingWriter(stream).write_all(frame)
+}
+// queues an event, dropping it if the event queue is full (or nobody listens anymore)
+fn send_event<P: Protocol>(
+    event_sender: &std::sync::mpsc::SyncSender<ConnectionEvent<P>>,
+    event: ConnectionEvent<P>,
+) {
+    match event_sender.try_send(event) {
+        Ok(()) => {}
+        Err(std::sync::mpsc::TrySendError::Full(_)) => debug!("Event queue is full, event dropped"),
+        Err(std::sync::mpsc::TrySendError::Disconnected(_)) => {}
+    }
+}
+
+// time between two checks of the cancel handle while waiting for a client
+const ACCEPT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(10);
+
+// registers the listener with a new Poll, which reports clients waiting to be accepted
+fn register_listener(listener: &TcpListener) -> std::io::Result<mio::Poll> {
+    let poll = mio::Poll::new()?;
+    poll.register(
+        listener,
+        mio::Token(0),
+        mio::Ready::readable(),
+        mio::PollOpt::level(),
+    )?;
+    Ok(poll)
+}
+// waits (without busy-looping) until a client connects, the wait time is exceeded or the handle is cancelled
+// the listener has to be registered with the given poll (see register_listener)
+fn accept(
+    listener: &TcpListener,
+    poll: &mio::Poll,
+    accept_wait_time: Option<std::time::Duration>,
+    cancel_handle: &CancelHandle,
+) -> Result<std::io::Result<(TcpStream, std::net::SocketAddr)>, ConnectErrors> {
+    let mut events = mio::Events::with_capacity(1);
+    let now = std::time::Instant::now();
+    loop {
+        match listener.accept() {
+            Ok(stream) => return Ok(Ok(stream)),
+            Err(error) => match error.kind() {
+                std::io::ErrorKind::WouldBlock => {}
+                _ => return Ok(Err(error)),
+            },
+        }
+        if cancel_handle.is_cancelled() {
+            info!("Waiting for a client was cancelled");
+            return Err(ConnectErrors::Cancelled);
+        }
+        let poll_wait_time = match accept_wait_time {
+            Some(accept_wait_time) => match accept_wait_time.checked_sub(now.elapsed()) {
+                Some(remaining_wait_time) => remaining_wait_time.min(ACCEPT_POLL_INTERVAL),
+                None => return Err(ConnectErrors::WaitTimeExceeded),
+            },
+            None => ACCEPT_POLL_INTERVAL,
+        };
+        if let Err(err) = poll.poll(&mut events, Some(poll_wait_time)) {
+            if err.kind() != std::io::ErrorKind::Interrupted {
+                return Ok(Err(err));
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{
+    CancelHandle, ConnectErrors, PayloadLogging, TcpIpc, TcpIpcConfig, TcpIpcListener,
+};
+use std::time::{Duration, Instant};
+
+fn config() -> TcpIpcConfig {
+    TcpIpcConfig {
+        after_connect_wait_time: None,
+        read_iteration_wait_time: Some(Duration::from_micros(100)),
+        shutdown_wait_time: Some(Duration::from_millis(100)),
+        check_count: 1,
+        payload_logging: PayloadLogging::LengthOnly,
+    }
+}
+
+#[test]
+fn accept_wait_time_is_respected() {
+    let started = Instant::now();
+    match TcpIpc::<ProtocolExample>::server(
+        "127.0.0.1:0",
+        config(),
+        Some(Duration::from_millis(200)),
+    ) {
+        Err(ConnectErrors::WaitTimeExceeded) => {}
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+    let elapsed = started.elapsed();
+    assert!(
+        elapsed >= Duration::from_millis(200),
+        "returned after {:?}",
+        elapsed
+    );
+    assert!(
+        elapsed < Duration::from_secs(2),
+        "returned after {:?}",
+        elapsed
+    );
+}
+
+#[test]
+fn client_is_accepted_within_the_wait_time() {
+    let listener = TcpIpcListener::bind("127.0.0.1:0").expect("binding failed");
+    let address = listener.local_addr().expect("no local address");
+    let client = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        TcpIpc::<ProtocolExample>::client(address, config(), None).expect("connecting failed")
+    });
+    let server = listener
+        .accept::<ProtocolExample>(config(), Some(Duration::from_secs(5)))
+        .expect("accepting failed");
+    let client = client.join().expect("the client panicked");
+    assert_eq!(
+        server.peer_addr().expect("no peer address"),
+        client.local_addr().expect("no local address")
+    );
+}
+
+#[test]
+fn waiting_server_is_cancelled() {
+    let cancel_handle = CancelHandle::new();
+    let cancel_handle_clone = cancel_handle.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        cancel_handle_clone.cancel();
+    });
+    let started = Instant::now();
+    match TcpIpc::<ProtocolExample>::server_cancellable(
+        "127.0.0.1:0",
+        config(),
+        None,
+        &cancel_handle,
+    ) {
+        Err(ConnectErrors::Cancelled) => {}
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert!(cancel_handle.is_cancelled());
+}
+
+#[test]
+fn cancelled_handle_stops_the_server_immediately() {
+    let cancel_handle = CancelHandle::new();
+    cancel_handle.cancel();
+    match TcpIpc::<ProtocolExample>::server_cancellable(
+        "127.0.0.1:0",
+        config(),
+        Some(Duration::from_secs(5)),
+        &cancel_handle,
+    ) {
+        Err(ConnectErrors::Cancelled) => {}
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn listener_accepts_repeatedly() {
+    let listener = TcpIpcListener::bind("127.0.0.1:0").expect("binding failed");
+    let address = listener.local_addr().expect("no local address");
+    match listener.accept::<ProtocolExample>(config(), Some(Duration::from_millis(50))) {
+        Err(ConnectErrors::WaitTimeExceeded) => {}
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+    // a retry & a second client use the same listener
+    for _ in 0..2 {
+        let client = std::thread::spawn(move || {
+            TcpIpc::<ProtocolExample>::client(address, config(), None).expect("connecting failed")
+        });
+        let server = listener
+            .accept::<ProtocolExample>(config(), Some(Duration::from_secs(5)))
+            .expect("accepting failed");
+        let client = client.join().expect("the client panicked");
+        assert_eq!(
+            server.peer_addr().expect("no peer address"),
+            client.local_addr().expect("no local address")
+        );
+    }
+}
@@ -0,0 +1,269 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{
+    BackgroundWriterConfig, FaultPlan, MockPeer, OutgoingQueuePolicy, PayloadLogging, Protocol,
+    TcpIpc, TcpIpcConfig, WriteMessageErrors,
+};
+use std::io::Read;
+use std::net::TcpListener;
+use std::time::{Duration, Instant};
+
+fn config() -> TcpIpcConfig {
+    TcpIpcConfig {
+        after_connect_wait_time: None,
+        read_iteration_wait_time: Some(Duration::from_micros(100)),
+        shutdown_wait_time: Some(Duration::from_millis(100)),
+        check_count: 1,
+        payload_logging: PayloadLogging::LengthOnly,
+    }
+}
+
+fn writer_config(
+    queue_capacity: usize,
+    full_policy: OutgoingQueuePolicy,
+) -> BackgroundWriterConfig {
+    BackgroundWriterConfig {
+        queue_capacity,
+        full_policy,
+    }
+}
+
+// a client whose background writer needs the given time per frame
+fn slow_client(
+    address: std::net::SocketAddr,
+    config: TcpIpcConfig,
+    writer_config: BackgroundWriterConfig,
+    time_per_frame: Duration,
+) -> TcpIpc<ProtocolExample> {
+    let mut client =
+        TcpIpc::<ProtocolExample>::client(address, config, None).expect("connecting failed");
+    client.set_outgoing_faults(Some(FaultPlan::new().delay(time_per_frame)));
+    client
+        .set_background_writer(Some(writer_config))
+        .expect("starting the writer failed");
+    client
+}
+
+// a raw peer, which returns the count of received bytes once the connection is closed
+fn counting_peer() -> (std::net::SocketAddr, std::thread::JoinHandle<usize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("binding failed");
+    let address = listener.local_addr().expect("no local address");
+    let peer = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accepting failed");
+        let mut received = Vec::new();
+        let _ = stream.read_to_end(&mut received);
+        received.len()
+    });
+    (address, peer)
+}
+
+fn frame_length(payload: &[u8]) -> usize {
+    ProtocolExample::construct_message(CommandsExample::Funny, payload)
+        .expect("constructing failed")
+        .len()
+}
+
+#[test]
+fn background_writer_sends_queued_messages() {
+    let peer = (0..100u8)
+        .fold(MockPeer::<ProtocolExample>::new(), |peer, index| {
+            peer.expect_payload(CommandsExample::Funny, vec![index; 500])
+        })
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    client
+        .set_background_writer(Some(writer_config(4, OutgoingQueuePolicy::Block)))
+        .expect("starting the writer failed");
+    for index in 0..100u8 {
+        client
+            .write_message(CommandsExample::Funny, &[index; 500])
+            .expect("queueing failed");
+    }
+    client
+        .flush(Duration::from_secs(5))
+        .expect("flushing failed");
+    assert_eq!(client.outgoing_queue_len(), 0);
+    client
+        .set_background_writer(None)
+        .expect("stopping the writer failed");
+    peer.assert_finished();
+}
+
+#[test]
+fn flush_waits_at_most_the_given_time() {
+    let (address, peer) = counting_peer();
+    let mut client = slow_client(
+        address,
+        config(),
+        writer_config(10, OutgoingQueuePolicy::Block),
+        Duration::from_millis(100),
+    );
+    for index in 0..10u8 {
+        client
+            .write_message(CommandsExample::Funny, &[index])
+            .expect("queueing failed");
+    }
+    match client.flush(Duration::from_millis(50)) {
+        Err(WriteMessageErrors::FlushTimedOut) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    assert!(client.outgoing_queue_len() > 0);
+    drop(client);
+    peer.join().expect("the peer panicked");
+}
+
+#[test]
+fn full_queue_fails_the_write() {
+    let (address, peer) = counting_peer();
+    let mut client = slow_client(
+        address,
+        config(),
+        writer_config(1, OutgoingQueuePolicy::Fail),
+        Duration::from_millis(200),
+    );
+    // at most one frame is written & one is queued meanwhile
+    let results: Vec<_> = (0..5u8)
+        .map(|index| client.write_message(CommandsExample::Funny, &[index]))
+        .collect();
+    assert!(results.iter().filter(|result| result.is_ok()).count() <= 2);
+    for result in results {
+        match result {
+            Ok(()) | Err(WriteMessageErrors::QueueFull) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+    drop(client);
+    peer.join().expect("the peer panicked");
+}
+
+#[test]
+fn full_queue_drops_the_oldest_frames() {
+    let (address, peer) = counting_peer();
+    let mut client = slow_client(
+        address,
+        config(),
+        writer_config(2, OutgoingQueuePolicy::DropOldest),
+        Duration::from_millis(200),
+    );
+    for index in 0..10u8 {
+        client
+            .write_message(CommandsExample::Funny, &[index])
+            .expect("queueing failed");
+    }
+    // the frame being written & the queued ones
+    assert!(client.outgoing_queue_len() <= 3);
+    drop(client);
+    peer.join().expect("the peer panicked");
+}
+
+#[test]
+fn failed_write_is_reported() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    client.set_outgoing_faults(Some(FaultPlan::new().disconnect_after(3)));
+    client
+        .set_background_writer(Some(writer_config(4, OutgoingQueuePolicy::Block)))
+        .expect("starting the writer failed");
+    client
+        .write_message(CommandsExample::Funny, b"lost")
+        .expect("queueing failed");
+    match client.flush(Duration::from_secs(5)) {
+        Err(WriteMessageErrors::MessageSendFailed(_)) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    // nothing is written anymore
+    match client.write_message(CommandsExample::Funny, b"later") {
+        Err(WriteMessageErrors::MessageSendFailed(_)) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    peer.assert_finished();
+}
+
+#[test]
+fn shutdown_writes_queued_messages_within_the_wait_time() {
+    let (address, peer) = counting_peer();
+    let config = TcpIpcConfig {
+        shutdown_wait_time: Some(Duration::from_millis(300)),
+        ..config()
+    };
+    let mut client = slow_client(
+        address,
+        config,
+        writer_config(10, OutgoingQueuePolicy::Block),
+        Duration::from_millis(20),
+    );
+    for index in 0..5u8 {
+        client
+            .write_message(CommandsExample::Funny, &[index])
+            .expect("queueing failed");
+    }
+    let started = Instant::now();
+    client.shutdown().expect("shutdown failed");
+    let elapsed = started.elapsed();
+    // the flush & the wait for the read thread share the wait time
+    assert!(elapsed >= Duration::from_millis(300));
+    assert!(elapsed < Duration::from_millis(550), "{:?}", elapsed);
+    assert_eq!(
+        peer.join().expect("the peer panicked"),
+        5 * frame_length(&[0])
+    );
+}
+
+#[test]
+fn shutdown_does_not_exceed_the_wait_time() {
+    let (address, peer) = counting_peer();
+    let config = TcpIpcConfig {
+        shutdown_wait_time: Some(Duration::from_millis(300)),
+        ..config()
+    };
+    let mut client = slow_client(
+        address,
+        config,
+        writer_config(100, OutgoingQueuePolicy::Block),
+        Duration::from_millis(100),
+    );
+    for index in 0..20u8 {
+        client
+            .write_message(CommandsExample::Funny, &[index])
+            .expect("queueing failed");
+    }
+    let started = Instant::now();
+    // the remaining queued messages are dropped
+    let _ = client.shutdown();
+    let elapsed = started.elapsed();
+    assert!(elapsed < Duration::from_millis(500), "{:?}", elapsed);
+    assert!(peer.join().expect("the peer panicked") < 20 * frame_length(&[0]));
+}
+
+#[test]
+fn shutdown_without_wait_time_does_not_wait() {
+    let (address, peer) = counting_peer();
+    let config = TcpIpcConfig {
+        shutdown_wait_time: None,
+        ..config()
+    };
+    let mut client = slow_client(
+        address,
+        config,
+        writer_config(100, OutgoingQueuePolicy::Block),
+        Duration::from_millis(100),
+    );
+    for index in 0..20u8 {
+        client
+            .write_message(CommandsExample::Funny, &[index])
+            .expect("queueing failed");
+    }
+    let started = Instant::now();
+    let _ = client.shutdown();
+    let elapsed = started.elapsed();
+    assert!(elapsed < Duration::from_millis(250), "{:?}", elapsed);
+    assert!(peer.join().expect("the peer panicked") < 20 * frame_length(&[0]));
+}
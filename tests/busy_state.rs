@@ -0,0 +1,99 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{
+    BusyStateMachine, BusyStateUpdateResult, CommandAction, MockPeer, PayloadLogging,
+    ReadThreadErrors, TcpIpc, TcpIpcConfig,
+};
+use std::time::Duration;
+
+fn config() -> TcpIpcConfig {
+    TcpIpcConfig {
+        after_connect_wait_time: None,
+        read_iteration_wait_time: Some(Duration::from_micros(100)),
+        shutdown_wait_time: Some(Duration::from_millis(100)),
+        check_count: 1,
+        payload_logging: PayloadLogging::LengthOnly,
+    }
+}
+
+#[test]
+fn state_machine_and_handler_are_chained() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        // the handler & the state machine are installed meanwhile
+        .wait(Duration::from_millis(200))
+        .send(CommandsExample::Start, b"state?".to_vec())
+        .expect_payload(CommandsExample::Funny, b"idle".to_vec())
+        .send(CommandsExample::Funny, b"ping".to_vec())
+        .expect_payload(CommandsExample::Funny, b"handled".to_vec())
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    client.set_immediate_route_handler(
+        |command: &CommandsExample, _message: &[u8], _busy_state: &BusyStatesExample| {
+            if *command == CommandsExample::Funny {
+                Some(vec![(CommandsExample::Funny, b"handled".to_vec())])
+            } else {
+                None
+            }
+        },
+    );
+    client.set_busy_state_machine(BusyStateMachine::new().on_command(
+        BusyStatesExample::Idle,
+        CommandsExample::Start,
+        CommandAction::Answer(vec![(CommandsExample::Funny, b"idle".to_vec())]),
+    ));
+    peer.assert_finished();
+}
+
+#[test]
+fn busy_state_handle_respects_the_state_machine() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    let handle = client.busy_state_handle();
+    client.set_busy_state_machine(
+        BusyStateMachine::new()
+            .allow_transition(BusyStatesExample::Idle, BusyStatesExample::Working),
+    );
+    assert_eq!(
+        handle.set(BusyStatesExample::Working),
+        Some(BusyStatesExample::Idle)
+    );
+    assert_eq!(handle.set(BusyStatesExample::Idle), None);
+    assert!(!handle.compare_and_set(BusyStatesExample::Working, BusyStatesExample::Idle));
+    assert_eq!(
+        client.update_busy_state(BusyStatesExample::Idle),
+        BusyStateUpdateResult::TransitionNotAllowed
+    );
+    assert_eq!(handle.get(), BusyStatesExample::Working);
+    peer.assert_finished();
+}
+
+#[test]
+fn busy_state_is_kept_after_the_peer_closed() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        .close()
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    match client.await_message(Duration::from_secs(5), Some(Duration::from_millis(1))) {
+        Err(ReadThreadErrors::PeerClosed) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    peer.assert_finished();
+    assert_eq!(
+        client.update_busy_state(BusyStatesExample::Working),
+        BusyStateUpdateResult::Success
+    );
+    assert_eq!(
+        client.get_busy_state().expect("query failed"),
+        BusyStatesExample::Working
+    );
+}
@@ -0,0 +1,141 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{CodecErrors, DecodeEvent, Decoder, Encoder, FrameReader, ParseHeaderError};
+
+fn encode() -> Vec<u8> {
+    let encoder = Encoder::<ProtocolExample>::new();
+    let mut bytes = Vec::new();
+    encoder
+        .encode(CommandsExample::Start, b"go", &mut bytes)
+        .expect("encoding failed");
+    encoder
+        .encode(CommandsExample::Funny, &[7; 300], &mut bytes)
+        .expect("encoding failed");
+    bytes
+}
+
+#[test]
+fn frames_are_pulled_after_pushing_bytes() {
+    let bytes = encode();
+    let mut decoder = Decoder::<ProtocolExample>::new();
+    decoder.push(&bytes[..4]);
+    assert_eq!(decoder.next_frame().expect("decoding failed"), None);
+    decoder.push(&bytes[4..10]);
+    assert_eq!(
+        decoder.next_frame().expect("decoding failed"),
+        Some((CommandsExample::Start, b"go".to_vec()))
+    );
+    assert_eq!(decoder.next_frame().expect("decoding failed"), None);
+    assert_eq!(decoder.buffered_len(), 3);
+    decoder.push(&bytes[10..]);
+    assert_eq!(
+        decoder.next_frame().expect("decoding failed"),
+        Some((CommandsExample::Funny, vec![7; 300]))
+    );
+    assert_eq!(decoder.buffered_len(), 0);
+    decoder.finish().expect("the stream is complete");
+}
+
+#[test]
+fn all_complete_frames_are_decoded_at_once() {
+    let bytes = encode();
+    let mut decoder = Decoder::<ProtocolExample>::new();
+    let mut bytes = [bytes.as_slice(), b"\0\0\0zz"].concat();
+    bytes.truncate(bytes.len() - 1);
+    let frames = decoder.decode(&bytes).collect::<Vec<_>>();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(
+        frames
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("decoding failed"),
+        vec![
+            (CommandsExample::Start, b"go".to_vec()),
+            (CommandsExample::Funny, vec![7; 300])
+        ]
+    );
+    // the incomplete header is kept, so the parse error follows the completing byte
+    match decoder.decode(b"z").collect::<Vec<_>>().as_slice() {
+        [Err(CodecErrors::ParseHeaderFailed((ParseHeaderError::CommandParseFailed, _)))] => {}
+        frames => panic!("unexpected frames: {:?}", frames),
+    }
+}
+
+#[test]
+fn parse_errors_persist_until_reset() {
+    let mut decoder = Decoder::<ProtocolExample>::new();
+    decoder.push(b"\0\0\0zz");
+    for _ in 0..2 {
+        match decoder.next_frame() {
+            Err(CodecErrors::ParseHeaderFailed((ParseHeaderError::CommandParseFailed, header))) => {
+                assert_eq!(header, b"\0\0\0zz".to_vec())
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+    decoder.reset();
+    decoder.push(&encode());
+    assert!(decoder.next_frame().expect("decoding failed").is_some());
+}
+
+#[test]
+fn frame_reader_reports_truncated_streams() {
+    let bytes = encode();
+    let frames = FrameReader::<_, ProtocolExample>::new(bytes.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .expect("decoding failed");
+    assert_eq!(frames.len(), 2);
+    let mut reader = FrameReader::<_, ProtocolExample>::new(&bytes[..bytes.len() - 1]);
+    assert!(reader.read_frame().expect("decoding failed").is_some());
+    match reader.read_frame() {
+        Err(CodecErrors::TruncatedMessage((Some(CommandsExample::Funny), payload))) => {
+            assert_eq!(payload, vec![7; 299])
+        }
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn large_payloads_are_streamed_in_chunks() {
+    let bytes = encode();
+    let mut decoder = Decoder::<ProtocolExample>::new();
+    decoder.set_streaming_threshold(Some(100));
+    let mut events = Vec::new();
+    for chunk in bytes.chunks(200) {
+        decoder.push(chunk);
+        while let Some(event) = decoder.next_event().expect("decoding failed") {
+            events.push(event);
+        }
+    }
+    assert_eq!(
+        events,
+        vec![
+            DecodeEvent::Message((CommandsExample::Start, b"go".to_vec())),
+            DecodeEvent::StreamStart((CommandsExample::Funny, 300)),
+            DecodeEvent::StreamChunk(vec![7; 188]),
+            DecodeEvent::StreamChunk(vec![7; 112]),
+            DecodeEvent::StreamEnd,
+        ]
+    );
+    decoder.finish().expect("the stream is complete");
+}
+
+#[test]
+fn headers_are_encoded_from_the_length_only() {
+    let encoder = Encoder::<ProtocolExample>::new();
+    let mut frame = Vec::new();
+    encoder
+        .encode(CommandsExample::Funny, &[7; 300], &mut frame)
+        .expect("encoding failed");
+    let mut header = Vec::new();
+    encoder
+        .encode_header(CommandsExample::Funny, 300, &mut header)
+        .expect("encoding failed");
+    assert_eq!(header, frame[..5].to_vec());
+    assert!(encoder
+        .encode_header(CommandsExample::Funny, 1 << 24, &mut header)
+        .is_err());
+}
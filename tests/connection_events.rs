@@ -0,0 +1,90 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{
+    BusyStateUpdateResult, ConnectionEvent, MockPeer, PayloadLogging, ReadThreadErrors, TcpIpc,
+    TcpIpcConfig,
+};
+use std::time::Duration;
+
+fn config() -> TcpIpcConfig {
+    TcpIpcConfig {
+        after_connect_wait_time: None,
+        read_iteration_wait_time: Some(Duration::from_micros(100)),
+        shutdown_wait_time: Some(Duration::from_millis(100)),
+        check_count: 1,
+        payload_logging: PayloadLogging::LengthOnly,
+    }
+}
+
+#[test]
+fn events_are_delivered_in_order() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        // the immediate route handler is installed meanwhile
+        .wait(Duration::from_millis(200))
+        .send(CommandsExample::Start, b"ping".to_vec())
+        .expect_payload(CommandsExample::Funny, b"pong".to_vec())
+        .close()
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    let events = client
+        .take_event_receiver()
+        .expect("event receiver already taken");
+    assert!(client.take_event_receiver().is_none());
+    client.set_immediate_route_handler(
+        |command: &CommandsExample, _message: &[u8], _busy_state: &BusyStatesExample| {
+            if *command == CommandsExample::Start {
+                Some(vec![(CommandsExample::Funny, b"pong".to_vec())])
+            } else {
+                None
+            }
+        },
+    );
+    assert_eq!(
+        client.update_busy_state(BusyStatesExample::Working),
+        BusyStateUpdateResult::Success
+    );
+    match client.await_message(Duration::from_secs(5), Some(Duration::from_millis(1))) {
+        Err(ReadThreadErrors::PeerClosed) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    peer.assert_finished();
+    let result = client.shutdown();
+    let events: Vec<_> = events.try_iter().collect();
+    assert_eq!(events.len(), 5, "unexpected events: {:?}", events);
+    assert!(matches!(events[0], ConnectionEvent::Connected(_)));
+    assert!(matches!(
+        events[1],
+        ConnectionEvent::BusyStateChanged(BusyStatesExample::Working)
+    ));
+    assert!(matches!(
+        events[2],
+        ConnectionEvent::ImmediateReplySent(CommandsExample::Funny)
+    ));
+    assert!(matches!(events[3], ConnectionEvent::PeerClosed));
+    match events[4] {
+        ConnectionEvent::ShutdownComplete(shutdown_result) => assert_eq!(shutdown_result, result),
+        ref event => panic!("unexpected event: {:?}", event),
+    }
+}
+
+#[test]
+fn connected_event_names_the_peer() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    let events = client
+        .take_event_receiver()
+        .expect("event receiver already taken");
+    match events.try_recv() {
+        Ok(ConnectionEvent::Connected(address)) => assert_eq!(address, peer.address()),
+        result => panic!("unexpected event: {:?}", result),
+    }
+    peer.assert_finished();
+}
@@ -0,0 +1,175 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{
+    CancelHandle, DispatchErrors, Dispatcher, MockPeer, PayloadLogging, ReadThreadErrors,
+    ReplyResult, TcpIpc, TcpIpcConfig,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn config() -> TcpIpcConfig {
+    TcpIpcConfig {
+        after_connect_wait_time: None,
+        read_iteration_wait_time: Some(Duration::from_micros(100)),
+        shutdown_wait_time: Some(Duration::from_millis(100)),
+        check_count: 1,
+        payload_logging: PayloadLogging::LengthOnly,
+    }
+}
+
+#[test]
+fn handlers_run_on_the_caller_thread_without_workers() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        .send(CommandsExample::Start, b"ping".to_vec())
+        .expect_payload(CommandsExample::Funny, b"pong".to_vec())
+        .send(CommandsExample::Funny, b"stop".to_vec())
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    let caller_thread = std::thread::current().id();
+    let fallback_messages = Arc::new(Mutex::new(Vec::new()));
+    let fallback_messages_clone = fallback_messages.clone();
+    let stop_handle = CancelHandle::new();
+    let stop_handle_clone = stop_handle.clone();
+    let dispatcher = Dispatcher::<ProtocolExample>::new()
+        .on(
+            CommandsExample::Start,
+            move |_command, message, responder| {
+                assert_eq!(std::thread::current().id(), caller_thread);
+                assert_eq!(message, b"ping".to_vec());
+                assert_eq!(
+                    responder.reply(CommandsExample::Funny, b"pong".to_vec()),
+                    ReplyResult::Success
+                );
+            },
+        )
+        .fallback(move |command, message, _responder| {
+            fallback_messages_clone
+                .lock()
+                .expect("lock poisoned")
+                .push((command, message));
+            stop_handle_clone.cancel();
+        });
+    dispatcher
+        .run(&mut client, &stop_handle, Some(Duration::from_micros(100)))
+        .expect("dispatching failed");
+    assert_eq!(
+        *fallback_messages.lock().expect("lock poisoned"),
+        vec![(CommandsExample::Funny, b"stop".to_vec())]
+    );
+    peer.assert_finished();
+}
+
+#[test]
+fn first_matching_route_is_used() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        .send(CommandsExample::Start, Vec::new())
+        .expect_payload(CommandsExample::Funny, b"start".to_vec())
+        .send(CommandsExample::Funny, Vec::new())
+        .expect_payload(CommandsExample::Funny, b"any".to_vec())
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    let stop_handle = CancelHandle::new();
+    let stop_handle_clone = stop_handle.clone();
+    let dispatcher = Dispatcher::<ProtocolExample>::new()
+        .on(CommandsExample::Start, |_command, _message, responder| {
+            responder.reply(CommandsExample::Funny, b"start".to_vec());
+        })
+        .on_matching(
+            |_command| true,
+            move |_command, _message, responder| {
+                responder.reply(CommandsExample::Funny, b"any".to_vec());
+                stop_handle_clone.cancel();
+            },
+        )
+        .fallback(|command, _message, _responder| panic!("fallback called for {:?}", command));
+    // the replies queued before stopping are still sent
+    dispatcher
+        .run(&mut client, &stop_handle, Some(Duration::from_micros(100)))
+        .expect("dispatching failed");
+    peer.assert_finished();
+}
+
+#[test]
+fn workers_handle_messages_concurrently() {
+    const MESSAGES: u8 = 8;
+    let peer = (0..MESSAGES).fold(MockPeer::<ProtocolExample>::new(), |peer, index| {
+        peer.send(CommandsExample::Start, vec![index])
+    });
+    // the replies arrive in any order
+    let peer = (0..MESSAGES)
+        .fold(peer, |peer, _| {
+            peer.expect_matching(CommandsExample::Funny, "a reply", |payload| {
+                payload.len() == 1 && payload[0] < MESSAGES
+            })
+        })
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    let caller_thread = std::thread::current().id();
+    let running = Arc::new(AtomicUsize::new(0));
+    let maximal_running = Arc::new(AtomicUsize::new(0));
+    let handled = Arc::new(Mutex::new(Vec::new()));
+    let stop_handle = CancelHandle::new();
+    let dispatcher = {
+        let running = running.clone();
+        let maximal_running = maximal_running.clone();
+        let handled = handled.clone();
+        let stop_handle = stop_handle.clone();
+        Dispatcher::<ProtocolExample>::new()
+            .on(
+                CommandsExample::Start,
+                move |_command, message, responder| {
+                    assert_ne!(std::thread::current().id(), caller_thread);
+                    let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    maximal_running.fetch_max(now_running, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(50));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    let mut handled = handled.lock().expect("lock poisoned");
+                    handled.push(message[0]);
+                    responder.reply(CommandsExample::Funny, message);
+                    // the queued replies are sent after stopping
+                    if handled.len() == usize::from(MESSAGES) {
+                        stop_handle.cancel();
+                    }
+                },
+            )
+            .worker_threads(4)
+    };
+    dispatcher
+        .run(&mut client, &stop_handle, Some(Duration::from_micros(100)))
+        .expect("dispatching failed");
+    peer.assert_finished();
+    let mut handled = handled.lock().expect("lock poisoned").clone();
+    handled.sort();
+    assert_eq!(handled, (0..MESSAGES).collect::<Vec<_>>());
+    assert!(maximal_running.load(Ordering::SeqCst) > 1);
+}
+
+#[test]
+fn receive_errors_stop_the_dispatcher() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        .close()
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    let dispatcher = Dispatcher::<ProtocolExample>::new().worker_threads(2);
+    match dispatcher.run(
+        &mut client,
+        &CancelHandle::new(),
+        Some(Duration::from_micros(100)),
+    ) {
+        Err(DispatchErrors::ReceiveFailed(ReadThreadErrors::PeerClosed)) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    peer.assert_finished();
+}
@@ -0,0 +1,378 @@
+use rust_tcp_ipc::*;
+
+// a description can be installed only once per slot, so each test uses its own slot
+macro_rules! slot {
+    ($name:ident) => {
+        #[derive(Debug)]
+        enum $name {}
+        impl DescriptionSlot for $name {
+            fn cell() -> &'static DescriptionCell {
+                static CELL: DescriptionCell = DescriptionCell::new();
+                &CELL
+            }
+        }
+    };
+}
+
+fn description(
+    order: HeaderOrder,
+    length_encoding: LengthEncoding,
+    length_width: usize,
+) -> ProtocolDescription {
+    ProtocolDescription {
+        command_width: 2,
+        length_width,
+        order,
+        length_encoding,
+        commands: vec![
+            CommandDescription {
+                name: "Ping".to_string(),
+                value: b"00".to_vec(),
+            },
+            CommandDescription {
+                name: "Pong".to_string(),
+                value: b"01".to_vec(),
+            },
+        ],
+        accept_unknown_commands: false,
+        busy_states: Vec::new(),
+        immediate_replies: Vec::new(),
+    }
+}
+
+fn header<S: DescriptionSlot>(command: &str, length: usize) -> Option<Vec<u8>> {
+    let command = S::command_by_name(command).expect("unknown command");
+    DynamicProtocol::<S>::construct_header_for_length(command, length)
+}
+
+fn parse<S: DescriptionSlot>(header: &[u8]) -> Result<(String, usize), ParseHeaderError> {
+    let (header, _) = DynamicProtocol::<S>::message_slice_to_header_array(header)
+        .expect("the header is incomplete");
+    DynamicProtocol::<S>::parse_header(header)
+        .map(|(command, length)| (command.name().expect("unnamed command"), length))
+        .map_err(|(err, _)| err)
+}
+
+#[test]
+fn length_encodings_round_trip() {
+    slot!(BigEndian);
+    slot!(LittleEndian);
+    slot!(AsciiDecimal);
+    slot!(AsciiHex);
+    BigEndian::install(description(
+        HeaderOrder::CommandFirst,
+        LengthEncoding::BigEndian,
+        3,
+    ))
+    .expect("invalid description");
+    LittleEndian::install(description(
+        HeaderOrder::CommandFirst,
+        LengthEncoding::LittleEndian,
+        3,
+    ))
+    .expect("invalid description");
+    AsciiDecimal::install(description(
+        HeaderOrder::LengthFirst,
+        LengthEncoding::AsciiDecimal,
+        5,
+    ))
+    .expect("invalid description");
+    AsciiHex::install(description(
+        HeaderOrder::LengthFirst,
+        LengthEncoding::AsciiHex,
+        4,
+    ))
+    .expect("invalid description");
+
+    assert_eq!(
+        header::<BigEndian>("Pong", 0x010203),
+        Some(b"01\x01\x02\x03".to_vec())
+    );
+    assert_eq!(
+        header::<LittleEndian>("Pong", 0x010203),
+        Some(b"01\x03\x02\x01".to_vec())
+    );
+    assert_eq!(
+        header::<AsciiDecimal>("Ping", 42),
+        Some(b"0004200".to_vec())
+    );
+    assert_eq!(header::<AsciiHex>("Ping", 42), Some(b"002a00".to_vec()));
+
+    for &length in &[0, 1, 255, 256, 4242] {
+        let pong = ("Pong".to_string(), length);
+        let encoded = header::<BigEndian>("Pong", length).expect("encoding failed");
+        assert_eq!(parse::<BigEndian>(&encoded), Ok(pong.clone()));
+        let encoded = header::<LittleEndian>("Pong", length).expect("encoding failed");
+        assert_eq!(parse::<LittleEndian>(&encoded), Ok(pong.clone()));
+        let encoded = header::<AsciiDecimal>("Pong", length).expect("encoding failed");
+        assert_eq!(parse::<AsciiDecimal>(&encoded), Ok(pong.clone()));
+        let encoded = header::<AsciiHex>("Pong", length).expect("encoding failed");
+        assert_eq!(parse::<AsciiHex>(&encoded), Ok(pong));
+    }
+}
+
+#[test]
+fn lengths_beyond_the_width_are_rejected() {
+    slot!(Binary);
+    slot!(Decimal);
+    slot!(Hex);
+    Binary::install(description(
+        HeaderOrder::CommandFirst,
+        LengthEncoding::LittleEndian,
+        2,
+    ))
+    .expect("invalid description");
+    Decimal::install(description(
+        HeaderOrder::CommandFirst,
+        LengthEncoding::AsciiDecimal,
+        3,
+    ))
+    .expect("invalid description");
+    Hex::install(description(
+        HeaderOrder::CommandFirst,
+        LengthEncoding::AsciiHex,
+        2,
+    ))
+    .expect("invalid description");
+    assert_eq!(
+        header::<Binary>("Ping", 0xffff),
+        Some(b"00\xff\xff".to_vec())
+    );
+    assert_eq!(header::<Binary>("Ping", 0x10000), None);
+    assert_eq!(header::<Decimal>("Ping", 999), Some(b"00999".to_vec()));
+    assert_eq!(header::<Decimal>("Ping", 1000), None);
+    assert_eq!(header::<Hex>("Ping", 255), Some(b"00ff".to_vec()));
+    assert_eq!(header::<Hex>("Ping", 256), None);
+}
+
+#[test]
+fn invalid_ascii_lengths_are_rejected() {
+    slot!(Decimal);
+    slot!(Hex);
+    Decimal::install(description(
+        HeaderOrder::CommandFirst,
+        LengthEncoding::AsciiDecimal,
+        3,
+    ))
+    .expect("invalid description");
+    Hex::install(description(
+        HeaderOrder::CommandFirst,
+        LengthEncoding::AsciiHex,
+        3,
+    ))
+    .expect("invalid description");
+    assert_eq!(parse::<Decimal>(b"00 42"), Ok(("Ping".to_string(), 42)));
+    assert_eq!(parse::<Hex>(b"00 2a"), Ok(("Ping".to_string(), 42)));
+    for header in &[
+        &b"000x2"[..],
+        b"004a2",
+        b"00-42",
+        b"00+42",
+        b"004 2",
+        b"00   ",
+    ] {
+        assert_eq!(
+            parse::<Decimal>(header),
+            Err(ParseHeaderError::LengthParseFailed),
+            "{:?}",
+            header
+        );
+    }
+    for header in &[
+        &b"000x2"[..],
+        b"00zz1",
+        b"00-2a",
+        b"00+2a",
+        b"00\xff\xff\xff",
+    ] {
+        assert_eq!(
+            parse::<Hex>(header),
+            Err(ParseHeaderError::LengthParseFailed),
+            "{:?}",
+            header
+        );
+    }
+    assert_eq!(
+        parse::<Decimal>(b"99042"),
+        Err(ParseHeaderError::CommandParseFailed)
+    );
+}
+
+#[test]
+fn invalid_descriptions_are_rejected() {
+    let valid = description(HeaderOrder::CommandFirst, LengthEncoding::BigEndian, 3);
+    assert_eq!(valid.validate(), Ok(()));
+    let check = |change: &dyn Fn(&mut ProtocolDescription), expected: ProtocolDescriptionError| {
+        let mut description = valid.clone();
+        change(&mut description);
+        assert_eq!(description.validate(), Err(expected));
+    };
+    check(
+        &|d| d.command_width = 0,
+        ProtocolDescriptionError::InvalidCommandWidth(0),
+    );
+    check(
+        &|d| d.command_width = 9,
+        ProtocolDescriptionError::InvalidCommandWidth(9),
+    );
+    check(
+        &|d| d.length_width = 0,
+        ProtocolDescriptionError::InvalidLengthWidth(0),
+    );
+    check(
+        &|d| d.length_width = 9,
+        ProtocolDescriptionError::InvalidLengthWidth(9),
+    );
+    check(
+        &|d| {
+            d.length_encoding = LengthEncoding::AsciiDecimal;
+            d.length_width = 17
+        },
+        ProtocolDescriptionError::InvalidLengthWidth(17),
+    );
+    check(
+        &|d| d.commands[1].value = b"1".to_vec(),
+        ProtocolDescriptionError::CommandWidthMismatch("Pong".to_string()),
+    );
+    check(
+        &|d| d.commands[1].name = "Ping".to_string(),
+        ProtocolDescriptionError::DuplicateCommandName("Ping".to_string()),
+    );
+    check(
+        &|d| d.commands[1].value = b"00".to_vec(),
+        ProtocolDescriptionError::DuplicateCommandValue("Pong".to_string()),
+    );
+    check(
+        &|d| d.busy_states = vec!["Idle".to_string(), "Idle".to_string()],
+        ProtocolDescriptionError::DuplicateBusyStateName("Idle".to_string()),
+    );
+    let reply = ImmediateReplyDescription {
+        command: "Ping".to_string(),
+        payload: None,
+        busy_states: Vec::new(),
+        reply_command: "Pong".to_string(),
+        reply_payload: Vec::new(),
+    };
+    check(
+        &|d| {
+            d.immediate_replies = vec![ImmediateReplyDescription {
+                reply_command: "Unknown".to_string(),
+                ..reply.clone()
+            }]
+        },
+        ProtocolDescriptionError::UnknownCommand("Unknown".to_string()),
+    );
+    check(
+        &|d| {
+            d.immediate_replies = vec![ImmediateReplyDescription {
+                busy_states: vec!["Working".to_string()],
+                ..reply.clone()
+            }]
+        },
+        ProtocolDescriptionError::UnknownBusyState("Working".to_string()),
+    );
+}
+
+#[test]
+fn slots_are_independent() {
+    slot!(Narrow);
+    slot!(Wide);
+    slot!(Empty);
+    Narrow::install(description(
+        HeaderOrder::CommandFirst,
+        LengthEncoding::BigEndian,
+        1,
+    ))
+    .expect("invalid description");
+    Wide::install(description(
+        HeaderOrder::LengthFirst,
+        LengthEncoding::AsciiDecimal,
+        8,
+    ))
+    .expect("invalid description");
+    assert_eq!(
+        Narrow::install(description(
+            HeaderOrder::CommandFirst,
+            LengthEncoding::BigEndian,
+            2,
+        )),
+        Err(ProtocolDescriptionError::AlreadyInstalled)
+    );
+    assert_eq!(header::<Narrow>("Ping", 7), Some(b"00\x07".to_vec()));
+    assert_eq!(header::<Wide>("Ping", 7), Some(b"0000000700".to_vec()));
+
+    assert!(!DynamicProtocol::<Empty>::is_ready());
+    assert_eq!(Empty::command_by_name("Ping"), None);
+    let (header, _) = DynamicProtocol::<Empty>::message_slice_to_header_array(b"0007")
+        .expect("the bytes are handed to parse_header");
+    assert!(DynamicProtocol::<Empty>::parse_header(header).is_err());
+    let config = TcpIpcConfig {
+        after_connect_wait_time: None,
+        read_iteration_wait_time: None,
+        shutdown_wait_time: None,
+        check_count: 1,
+        payload_logging: PayloadLogging::Off,
+    };
+    match TcpIpc::<DynamicProtocol<Empty>>::client("127.0.0.1:1", config, None) {
+        Err(ConnectErrors::ProtocolNotReady) => {}
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+}
+
+#[cfg(feature = "protocol-files")]
+#[test]
+fn descriptions_are_loaded_from_toml_and_json() {
+    let from_toml = ProtocolDescription::from_toml(
+        r#"
+            command_width = 2
+            length_width = 3
+            order = "length_first"
+            length_encoding = "ascii_decimal"
+            busy_states = ["Idle", "Working"]
+
+            [[commands]]
+            name = "Ping"
+            value = "00"
+
+            [[commands]]
+            name = "Pong"
+            value = [48, 49]
+
+            [[immediate_replies]]
+            command = "Ping"
+            busy_states = ["Working"]
+            reply_command = "Pong"
+            reply_payload = "busy"
+        "#,
+    )
+    .expect("loading TOML failed");
+    let from_json = ProtocolDescription::from_json(
+        r#"{
+            "command_width": 2,
+            "length_width": 3,
+            "order": "length_first",
+            "length_encoding": "ascii_decimal",
+            "busy_states": ["Idle", "Working"],
+            "commands": [{"name": "Ping", "value": "00"}, {"name": "Pong", "value": "01"}],
+            "immediate_replies": [
+                {"command": "Ping", "busy_states": ["Working"], "reply_command": "Pong", "reply_payload": [98, 117, 115, 121]}
+            ]
+        }"#,
+    )
+    .expect("loading JSON failed");
+    assert_eq!(from_toml, from_json);
+    assert_eq!(from_toml.commands[1].value, b"01".to_vec());
+    assert_eq!(
+        from_toml.immediate_replies[0].reply_payload,
+        b"busy".to_vec()
+    );
+
+    match ProtocolDescription::from_toml("command_width = 2\nlength_width = 0\norder = \"length_first\"\nlength_encoding = \"big_endian\"") {
+        Err(ProtocolDescriptionLoadError::Invalid(ProtocolDescriptionError::InvalidLengthWidth(0))) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    match ProtocolDescription::from_json("{\"command_width\": 2}") {
+        Err(ProtocolDescriptionLoadError::Json(_)) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
@@ -0,0 +1,238 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{
+    FaultPlan, FaultyStream, MockPeer, PayloadLogging, ReadThreadErrors, TcpIpc, TcpIpcConfig,
+    WriteMessageErrors,
+};
+use std::io::{Cursor, Read, Write};
+use std::time::{Duration, Instant};
+
+fn config() -> TcpIpcConfig {
+    TcpIpcConfig {
+        after_connect_wait_time: None,
+        read_iteration_wait_time: Some(Duration::from_micros(100)),
+        shutdown_wait_time: Some(Duration::from_millis(100)),
+        check_count: 1,
+        payload_logging: PayloadLogging::LengthOnly,
+    }
+}
+
+// remembers each write separately
+#[derive(Default)]
+struct ChunkWriter(Vec<Vec<u8>>);
+impl Write for ChunkWriter {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.push(bytes.to_vec());
+        Ok(bytes.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn write_all(stream: &mut FaultyStream<ChunkWriter>, writes: &[&[u8]]) {
+    for bytes in writes {
+        stream.write_all(bytes).expect("writing failed");
+    }
+}
+
+// returns the bytes of each read until the end of the stream
+fn read_chunks<R: Read>(stream: &mut R) -> Vec<Vec<u8>> {
+    let mut chunks = Vec::new();
+    let mut buffer = [0; 64];
+    loop {
+        match stream.read(&mut buffer).expect("reading failed") {
+            0 => return chunks,
+            length => chunks.push(buffer[..length].to_vec()),
+        }
+    }
+}
+
+#[test]
+fn fragments_are_written_in_the_given_sizes() {
+    let mut stream = FaultyStream::new(ChunkWriter::default())
+        .write_faults(FaultPlan::new().fragment(vec![1, 0, 3]));
+    write_all(&mut stream, &[b"abcdefg", b"hi"]);
+    // the sizes are repeated, also across writes
+    assert_eq!(
+        stream.into_inner().0,
+        vec![
+            b"a".to_vec(),
+            b"bcd".to_vec(),
+            b"e".to_vec(),
+            b"fg".to_vec(),
+            b"h".to_vec(),
+            b"i".to_vec(),
+        ]
+    );
+}
+
+#[test]
+fn fragments_are_read_in_the_given_sizes() {
+    let mut stream = FaultyStream::new(Cursor::new(b"abcdefg".to_vec()))
+        .read_faults(FaultPlan::new().fragment(vec![2]));
+    assert_eq!(
+        read_chunks(&mut stream),
+        vec![
+            b"ab".to_vec(),
+            b"cd".to_vec(),
+            b"ef".to_vec(),
+            b"g".to_vec(),
+        ]
+    );
+}
+
+#[test]
+fn coalesced_writes_are_held_back() {
+    let mut stream =
+        FaultyStream::new(ChunkWriter::default()).write_faults(FaultPlan::new().coalesce(2));
+    write_all(&mut stream, &[b"ab", b"cd", b"ef"]);
+    // the last write waits for its partner
+    assert_eq!(stream.into_inner().0, vec![b"abcd".to_vec()]);
+}
+
+#[test]
+fn bits_are_flipped_at_the_stream_offsets() {
+    let mut stream = FaultyStream::new(ChunkWriter::default())
+        .write_faults(FaultPlan::new().flip_bit(1, 0).flip_bit(3, 9));
+    write_all(&mut stream, &[b"ab", b"cd", b"ef"]);
+    // bit 9 is bit 1
+    assert_eq!(
+        stream.into_inner().0,
+        vec![b"ac".to_vec(), b"cf".to_vec(), b"ef".to_vec()]
+    );
+    let mut stream = FaultyStream::new(Cursor::new(b"abc".to_vec()))
+        .read_faults(FaultPlan::new().flip_bit(0, 7));
+    assert_eq!(
+        read_chunks(&mut stream),
+        vec![vec![b'a' | 0x80, b'b', b'c']]
+    );
+}
+
+#[test]
+fn writing_fails_after_the_disconnect() {
+    let mut stream = FaultyStream::new(ChunkWriter::default())
+        .write_faults(FaultPlan::new().disconnect_after(5));
+    write_all(&mut stream, &[b"abc", b"defg"]);
+    let error = stream
+        .write(b"h")
+        .expect_err("writing after the disconnect");
+    assert_eq!(error.kind(), std::io::ErrorKind::BrokenPipe);
+    assert_eq!(stream.into_inner().0, vec![b"abc".to_vec(), b"de".to_vec()]);
+}
+
+#[test]
+fn reading_ends_at_the_disconnect() {
+    let mut stream = FaultyStream::new(Cursor::new(b"abcdefg".to_vec()))
+        .read_faults(FaultPlan::new().disconnect_after(4));
+    assert_eq!(read_chunks(&mut stream), vec![b"abcd".to_vec()]);
+}
+
+#[test]
+fn each_chunk_is_delayed() {
+    let mut stream = FaultyStream::new(ChunkWriter::default()).write_faults(
+        FaultPlan::new()
+            .fragment(vec![1])
+            .delay(Duration::from_millis(30)),
+    );
+    let started = Instant::now();
+    write_all(&mut stream, &[b"abc"]);
+    assert!(started.elapsed() >= Duration::from_millis(90));
+    assert_eq!(stream.into_inner().0.len(), 3);
+}
+
+#[test]
+fn outgoing_faults_are_injected_into_the_sent_bytes() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        // the flipped bit is in the first payload byte
+        .expect_payload(CommandsExample::Start, b"rplit".to_vec())
+        .expect_payload(CommandsExample::Funny, b"first".to_vec())
+        .expect_payload(CommandsExample::Funny, b"second".to_vec())
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    client.set_outgoing_faults(Some(
+        FaultPlan::new()
+            .fragment(vec![1, 4])
+            .delay(Duration::from_millis(1))
+            .flip_bit(5, 0),
+    ));
+    client
+        .write_message(CommandsExample::Start, b"split")
+        .expect("writing failed");
+    client.set_outgoing_faults(Some(FaultPlan::new().coalesce(2)));
+    client
+        .write_message(CommandsExample::Funny, b"first")
+        .expect("writing failed");
+    client
+        .write_message(CommandsExample::Funny, b"second")
+        .expect("writing failed");
+    peer.assert_finished();
+}
+
+#[test]
+fn outgoing_disconnect_fails_the_write() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    client.set_outgoing_faults(Some(FaultPlan::new().disconnect_after(3)));
+    match client.write_message(CommandsExample::Start, b"lost") {
+        Err(WriteMessageErrors::MessageSendFailed(_)) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    peer.assert_finished();
+}
+
+#[test]
+fn incoming_faults_are_injected_into_the_received_bytes() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        // the faults are set meanwhile
+        .wait(Duration::from_millis(200))
+        .send(CommandsExample::Funny, b"abc".to_vec())
+        .send(CommandsExample::Funny, b"abc".to_vec())
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    client.set_incoming_faults(Some(FaultPlan::new().fragment(vec![1]).flip_bit(5, 0)));
+    for payload in [&b"`bc"[..], b"abc"].iter() {
+        assert_eq!(
+            client
+                .await_message(Duration::from_secs(5), Some(Duration::from_millis(1)))
+                .expect("reading failed"),
+            Some((CommandsExample::Funny, payload.to_vec()))
+        );
+    }
+    peer.assert_finished();
+}
+
+#[test]
+fn incoming_disconnect_truncates_the_message() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        .wait(Duration::from_millis(200))
+        .send(CommandsExample::Funny, b"abcdef".to_vec())
+        // the connection is dropped by the client
+        .wait(Duration::from_secs(1))
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    client.set_incoming_faults(Some(FaultPlan::new().disconnect_after(8)));
+    match client.await_message(Duration::from_secs(5), Some(Duration::from_millis(1))) {
+        Err(ReadThreadErrors::TruncatedMessage((Some(CommandsExample::Funny), payload))) => {
+            assert_eq!(payload, b"abc".to_vec())
+        }
+        result => panic!("unexpected result: {:?}", result),
+    }
+    match client.await_message(Duration::from_secs(5), Some(Duration::from_millis(1))) {
+        Err(ReadThreadErrors::PeerClosed) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    peer.assert_finished();
+}
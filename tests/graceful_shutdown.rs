@@ -0,0 +1,223 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{
+    GracefulShutdownError, MockPeer, ParseHeaderError, PayloadLogging, Protocol, ReadThreadErrors,
+    TcpIpc, TcpIpcConfig,
+};
+use std::io::{Read, Write};
+use std::time::Duration;
+
+// the example protocol with a shutdown handshake: Start "bye" is acknowledged by Funny "bye"
+#[derive(Debug)]
+enum GoodbyeProtocol {}
+impl Protocol for GoodbyeProtocol {
+    type CommandAsArray = <ProtocolExample as Protocol>::CommandAsArray;
+    type HeaderAsArray = <ProtocolExample as Protocol>::HeaderAsArray;
+    type LengthAsArray = <ProtocolExample as Protocol>::LengthAsArray;
+    type Commands = CommandsExample;
+    type BusyStates = BusyStatesExample;
+    fn idle() -> Self::BusyStates {
+        ProtocolExample::idle()
+    }
+    fn message_is_answered_via_immediate_route(
+        command: &Self::Commands,
+        message: &[u8],
+        busy_state: &Self::BusyStates,
+    ) -> Option<(Self::Commands, Vec<u8>)> {
+        ProtocolExample::message_is_answered_via_immediate_route(command, message, busy_state)
+    }
+    fn message_slice_to_header_array(input: &[u8]) -> Option<(&Self::HeaderAsArray, &[u8])> {
+        ProtocolExample::message_slice_to_header_array(input)
+    }
+    fn parse_command(command: &Self::CommandAsArray) -> Option<Self::Commands> {
+        ProtocolExample::parse_command(command)
+    }
+    fn parse_length(length: &Self::LengthAsArray) -> Option<usize> {
+        ProtocolExample::parse_length(length)
+    }
+    fn split_header_array(
+        header: &Self::HeaderAsArray,
+    ) -> (&Self::CommandAsArray, &Self::LengthAsArray) {
+        ProtocolExample::split_header_array(header)
+    }
+    fn parse_header(
+        header: &Self::HeaderAsArray,
+    ) -> Result<(Self::Commands, usize), (ParseHeaderError, &Self::HeaderAsArray)> {
+        ProtocolExample::parse_header(header)
+    }
+    fn command_to_array(command: Self::Commands) -> Self::CommandAsArray {
+        ProtocolExample::command_to_array(command)
+    }
+    fn get_length_as_array(command: Self::Commands, message: &[u8]) -> Option<Self::LengthAsArray> {
+        ProtocolExample::get_length_as_array(command, message)
+    }
+    fn construct_header(command: Self::CommandAsArray, length: Self::LengthAsArray) -> Vec<u8> {
+        ProtocolExample::construct_header(command, length)
+    }
+    fn shutdown_request() -> Option<(Self::Commands, Vec<u8>)> {
+        Some((CommandsExample::Start, b"bye".to_vec()))
+    }
+    fn is_shutdown_acknowledgement(command: &Self::Commands, message: &[u8]) -> bool {
+        *command == CommandsExample::Funny && message == b"bye"
+    }
+}
+
+const SHUTDOWN_REQUEST: [u8; 8] = [0, 0, 3, b'0', b'0', b'b', b'y', b'e'];
+const SHUTDOWN_ACKNOWLEDGEMENT: [u8; 8] = [0, 0, 3, b'4', b'2', b'b', b'y', b'e'];
+
+fn config() -> TcpIpcConfig {
+    TcpIpcConfig {
+        after_connect_wait_time: None,
+        read_iteration_wait_time: Some(Duration::from_micros(100)),
+        shutdown_wait_time: Some(Duration::from_millis(100)),
+        check_count: 1,
+        payload_logging: PayloadLogging::LengthOnly,
+    }
+}
+
+// closes the stream with a reset (instead of an orderly close)
+fn reset(stream: std::net::TcpStream) {
+    let stream = mio::net::TcpStream::from_stream(stream).expect("converting failed");
+    stream
+        .set_linger(Some(Duration::from_secs(0)))
+        .expect("setting linger failed");
+}
+
+#[test]
+fn acknowledged_shutdown_returns_the_drained_messages() {
+    let peer = MockPeer::<GoodbyeProtocol>::new()
+        .expect_payload(CommandsExample::Start, b"bye".to_vec())
+        .send(CommandsExample::Funny, b"late".to_vec())
+        .send(CommandsExample::Funny, b"bye".to_vec())
+        .start()
+        .expect("starting the mock peer failed");
+    let client = TcpIpc::<GoodbyeProtocol>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    let drained_messages = client
+        .graceful_shutdown(Duration::from_secs(5), Some(Duration::from_millis(1)))
+        .expect("graceful shutdown failed");
+    assert_eq!(
+        drained_messages,
+        vec![(CommandsExample::Funny, b"late".to_vec())]
+    );
+    peer.assert_finished();
+}
+
+#[test]
+fn protocol_without_handshake_is_reported() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        .start()
+        .expect("starting the mock peer failed");
+    let client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    match client.graceful_shutdown(Duration::from_secs(1), None) {
+        Err(GracefulShutdownError::NotSupportedByProtocol) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    peer.assert_finished();
+}
+
+#[test]
+fn failed_request_is_reported() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("binding failed");
+    let address = listener.local_addr().expect("no local address");
+    let mut client =
+        TcpIpc::<GoodbyeProtocol>::client(address, config(), None).expect("connecting failed");
+    let (stream, _) = listener.accept().expect("accepting failed");
+    reset(stream);
+    // the read thread notices the reset
+    let started = std::time::Instant::now();
+    while let Ok(None) = client.get_message() {
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "reset not noticed"
+        );
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    match client.graceful_shutdown(Duration::from_secs(1), None) {
+        Err(GracefulShutdownError::RequestSendFailed(_)) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn closed_connection_during_drain_is_reported() {
+    let peer = MockPeer::<GoodbyeProtocol>::new()
+        .expect_payload(CommandsExample::Start, b"bye".to_vec())
+        .send(CommandsExample::Funny, b"late".to_vec())
+        .close()
+        .start()
+        .expect("starting the mock peer failed");
+    let client = TcpIpc::<GoodbyeProtocol>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    match client.graceful_shutdown(Duration::from_secs(5), Some(Duration::from_millis(1))) {
+        Err(GracefulShutdownError::DrainFailed((
+            ReadThreadErrors::PeerClosed,
+            drained_messages,
+        ))) => {
+            assert_eq!(
+                drained_messages,
+                vec![(CommandsExample::Funny, b"late".to_vec())]
+            )
+        }
+        result => panic!("unexpected result: {:?}", result),
+    }
+    peer.assert_finished();
+}
+
+#[test]
+fn missing_acknowledgement_is_reported() {
+    let peer = MockPeer::<GoodbyeProtocol>::new()
+        .expect_payload(CommandsExample::Start, b"bye".to_vec())
+        .send(CommandsExample::Funny, b"late".to_vec())
+        // the connection is kept open meanwhile
+        .wait(Duration::from_secs(1))
+        .start()
+        .expect("starting the mock peer failed");
+    let client = TcpIpc::<GoodbyeProtocol>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    match client.graceful_shutdown(Duration::from_millis(300), Some(Duration::from_millis(1))) {
+        Err(GracefulShutdownError::AcknowledgementWaitTimeExceeded(drained_messages)) => {
+            assert_eq!(
+                drained_messages,
+                vec![(CommandsExample::Funny, b"late".to_vec())]
+            )
+        }
+        result => panic!("unexpected result: {:?}", result),
+    }
+    peer.assert_finished();
+}
+
+#[test]
+fn failed_close_after_the_acknowledgement_is_reported() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("binding failed");
+    let address = listener.local_addr().expect("no local address");
+    let peer = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accepting failed");
+        let mut request = [0; 8];
+        stream.read_exact(&mut request).expect("reading failed");
+        assert_eq!(request, SHUTDOWN_REQUEST);
+        stream
+            .write_all(&SHUTDOWN_ACKNOWLEDGEMENT)
+            .expect("writing failed");
+        reset(stream);
+    });
+    // the read thread reads rarely, so the reset arrives before the acknowledgement is read
+    let config = TcpIpcConfig {
+        read_iteration_wait_time: Some(Duration::from_millis(200)),
+        ..config()
+    };
+    let client =
+        TcpIpc::<GoodbyeProtocol>::client(address, config, None).expect("connecting failed");
+    match client.graceful_shutdown(Duration::from_secs(5), Some(Duration::from_millis(1))) {
+        Err(GracefulShutdownError::ShutdownFailed((err, drained_messages))) => {
+            assert!(!err.shutdown_succesfully);
+            assert!(drained_messages.is_empty());
+        }
+        result => panic!("unexpected result: {:?}", result),
+    }
+    peer.join().expect("the peer panicked");
+}
@@ -0,0 +1,86 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{PayloadLogging, TcpIpc, TcpIpcConfig, TcpIpcListener};
+use std::time::Duration;
+
+const MESSAGES: usize = 10;
+// much more than the (small) socket buffers hold
+const PAYLOAD_LENGTH: usize = 8 * 1024;
+
+fn config() -> TcpIpcConfig {
+    TcpIpcConfig {
+        after_connect_wait_time: None,
+        read_iteration_wait_time: Some(Duration::from_micros(10)),
+        shutdown_wait_time: Some(Duration::from_millis(100)),
+        check_count: 1,
+        payload_logging: PayloadLogging::LengthOnly,
+    }
+}
+
+// each Start message is answered immediately, the replies are forwarded
+fn answer_start(connection: &mut TcpIpc<ProtocolExample>) {
+    connection.set_immediate_route_handler(
+        |command: &CommandsExample, _message: &[u8], _busy_state: &BusyStatesExample| {
+            if *command == CommandsExample::Start {
+                Some(vec![(CommandsExample::Funny, b"ok".to_vec())])
+            } else {
+                None
+            }
+        },
+    );
+}
+
+fn write_and_collect_replies(
+    mut connection: TcpIpc<ProtocolExample>,
+    done: std::sync::Arc<std::sync::Barrier>,
+) -> usize {
+    answer_start(&mut connection);
+    // the handler is installed by the read thread after its next check
+    std::thread::sleep(Duration::from_millis(100));
+    let payload = vec![7; PAYLOAD_LENGTH];
+    for _ in 0..MESSAGES {
+        connection
+            .write_message(CommandsExample::Start, &payload)
+            .expect("writing failed");
+    }
+    let mut replies = 0;
+    while replies < MESSAGES {
+        match connection.await_message(Duration::from_secs(10), Some(Duration::from_micros(100))) {
+            Ok(Some((CommandsExample::Funny, _))) => replies += 1,
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+    // the peer still waits for replies
+    done.wait();
+    replies
+}
+
+#[test]
+fn replies_do_not_block_concurrent_writes() {
+    let listener = TcpIpcListener::bind("127.0.0.1:0").expect("binding failed");
+    let address = listener.local_addr().expect("no local address");
+    let (result_sender, result_receiver) = std::sync::mpsc::channel();
+    let server_result_sender = result_sender.clone();
+    let done = std::sync::Arc::new(std::sync::Barrier::new(2));
+    let server_done = done.clone();
+    std::thread::spawn(move || {
+        let server = listener
+            .accept::<ProtocolExample>(config(), None)
+            .expect("accepting failed");
+        let _ = server_result_sender.send(write_and_collect_replies(server, server_done));
+    });
+    std::thread::spawn(move || {
+        let client =
+            TcpIpc::<ProtocolExample>::client(address, config(), None).expect("connecting failed");
+        let _ = result_sender.send(write_and_collect_replies(client, done));
+    });
+    for _ in 0..2 {
+        let replies = result_receiver
+            .recv_timeout(Duration::from_secs(30))
+            .expect("the connection is deadlocked");
+        assert_eq!(replies, MESSAGES);
+    }
+}
@@ -0,0 +1,136 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{
+    IncomingQueueLimit, IncomingQueuePolicy, PayloadLogging, Protocol, ReadThreadErrors, TcpIpc,
+    TcpIpcConfig,
+};
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+const MESSAGES: u8 = 10;
+
+fn config() -> TcpIpcConfig {
+    TcpIpcConfig {
+        after_connect_wait_time: None,
+        read_iteration_wait_time: Some(Duration::from_micros(100)),
+        shutdown_wait_time: Some(Duration::from_millis(100)),
+        check_count: 1,
+        payload_logging: PayloadLogging::LengthOnly,
+    }
+}
+
+// a client with the given limit & the raw peer it is connected to
+fn connect(
+    capacity: usize,
+    full_policy: IncomingQueuePolicy,
+) -> (TcpIpc<ProtocolExample>, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("binding failed");
+    let address = listener.local_addr().expect("no local address");
+    let mut client =
+        TcpIpc::<ProtocolExample>::client(address, config(), None).expect("connecting failed");
+    client.set_incoming_queue_limit(Some(IncomingQueueLimit {
+        capacity,
+        full_policy,
+    }));
+    let (peer, _) = listener.accept().expect("accepting failed");
+    (client, peer)
+}
+
+// all messages are sent at once, so they are received by a single read
+fn send_messages(peer: &mut TcpStream) {
+    let bytes: Vec<u8> = (0..MESSAGES)
+        .flat_map(|index| {
+            ProtocolExample::construct_message(CommandsExample::Funny, &[index])
+                .expect("constructing failed")
+        })
+        .collect();
+    peer.write_all(&bytes).expect("writing failed");
+}
+
+fn wait_until_received(client: &TcpIpc<ProtocolExample>) {
+    let started = Instant::now();
+    while client.stats().total().messages_received < u64::from(MESSAGES) {
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "messages missing"
+        );
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+fn assert_queued(client: &mut TcpIpc<ProtocolExample>, indices: std::ops::Range<u8>) {
+    for index in indices {
+        let message = client.get_message().expect("reading failed");
+        assert_eq!(message, Some((CommandsExample::Funny, vec![index])));
+    }
+}
+
+#[test]
+fn stop_reading_bounds_the_queue() {
+    let (mut client, mut peer) = connect(2, IncomingQueuePolicy::StopReading);
+    send_messages(&mut peer);
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(client.incoming_queue_len(), 2);
+    // nothing is dropped, the messages are received as soon as there is room
+    for index in 0..MESSAGES {
+        let message = client
+            .await_message(Duration::from_secs(5), Some(Duration::from_millis(1)))
+            .expect("reading failed");
+        assert_eq!(message, Some((CommandsExample::Funny, vec![index])));
+        assert!(client.incoming_queue_len() <= 2);
+    }
+    assert_eq!(client.stats().messages_dropped, 0);
+}
+
+#[test]
+fn drop_oldest_keeps_the_newest_messages() {
+    let (mut client, mut peer) = connect(3, IncomingQueuePolicy::DropOldest);
+    send_messages(&mut peer);
+    wait_until_received(&client);
+    assert_eq!(client.incoming_queue_len(), 3);
+    assert_eq!(client.stats().messages_dropped, 7);
+    assert_queued(&mut client, 7..MESSAGES);
+    assert_eq!(client.get_message().expect("reading failed"), None);
+    assert_eq!(client.incoming_queue_len(), 0);
+}
+
+#[test]
+fn drop_newest_keeps_the_oldest_messages() {
+    let (mut client, mut peer) = connect(3, IncomingQueuePolicy::DropNewest);
+    send_messages(&mut peer);
+    wait_until_received(&client);
+    assert_eq!(client.incoming_queue_len(), 3);
+    assert_eq!(client.stats().messages_dropped, 7);
+    assert_queued(&mut client, 0..3);
+    assert_eq!(client.get_message().expect("reading failed"), None);
+    assert_eq!(client.incoming_queue_len(), 0);
+}
+
+#[test]
+fn incoming_queue_overflow_is_reported() {
+    let (mut client, mut peer) = connect(2, IncomingQueuePolicy::ReportOverflow);
+    send_messages(&mut peer);
+    wait_until_received(&client);
+    assert_eq!(client.incoming_queue_len(), 2);
+    assert_eq!(client.stats().messages_dropped, 8);
+    assert_queued(&mut client, 0..2);
+    match client.get_message() {
+        Err(ReadThreadErrors::QueueOverflow(8)) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn unlimited_queue_keeps_all_messages() {
+    let (mut client, mut peer) = connect(1, IncomingQueuePolicy::DropNewest);
+    client.set_incoming_queue_limit(None);
+    send_messages(&mut peer);
+    wait_until_received(&client);
+    assert_eq!(client.incoming_queue_len(), usize::from(MESSAGES));
+    assert_queued(&mut client, 0..MESSAGES);
+    assert_eq!(client.incoming_queue_len(), 0);
+}
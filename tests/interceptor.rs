@@ -0,0 +1,139 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{
+    Interceptor, Message, MockPeer, PayloadLogging, ReadThreadErrors, TcpIpc, TcpIpcConfig,
+};
+use std::time::Duration;
+
+fn config() -> TcpIpcConfig {
+    TcpIpcConfig {
+        after_connect_wait_time: None,
+        read_iteration_wait_time: Some(Duration::from_micros(100)),
+        shutdown_wait_time: Some(Duration::from_millis(100)),
+        check_count: 1,
+        payload_logging: PayloadLogging::LengthOnly,
+    }
+}
+
+fn await_message(client: &mut TcpIpc<ProtocolExample>) -> Message<ProtocolExample> {
+    client
+        .await_message(Duration::from_secs(5), Some(Duration::from_millis(1)))
+        .expect("reading failed")
+        .expect("no message received")
+}
+
+// appends its marker to each payload
+struct Appender(u8);
+impl Interceptor<ProtocolExample> for Appender {
+    fn outgoing(
+        &mut self,
+        (command, mut message): Message<ProtocolExample>,
+    ) -> Vec<Message<ProtocolExample>> {
+        message.push(self.0);
+        vec![(command, message)]
+    }
+    fn incoming(
+        &mut self,
+        (command, mut message): Message<ProtocolExample>,
+    ) -> Vec<Message<ProtocolExample>> {
+        message.push(self.0);
+        vec![(command, message)]
+    }
+}
+
+// drops all Funny messages & duplicates all Start messages
+struct Rewriter;
+impl Interceptor<ProtocolExample> for Rewriter {
+    fn outgoing(&mut self, message: Message<ProtocolExample>) -> Vec<Message<ProtocolExample>> {
+        match message.0 {
+            CommandsExample::Funny => Vec::new(),
+            CommandsExample::Start => vec![message.clone(), message],
+        }
+    }
+    fn incoming(&mut self, message: Message<ProtocolExample>) -> Vec<Message<ProtocolExample>> {
+        self.outgoing(message)
+    }
+}
+
+#[test]
+fn interceptors_are_applied_in_order() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        // outgoing: in the order the interceptors were added
+        .expect_payload(CommandsExample::Start, b"xab".to_vec())
+        // incoming: in reverse order
+        .send(CommandsExample::Funny, b"y".to_vec())
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    client.add_interceptor(Appender(b'a'));
+    client.add_interceptor(Appender(b'b'));
+    client
+        .write_message(CommandsExample::Start, b"x")
+        .expect("writing failed");
+    assert_eq!(
+        await_message(&mut client),
+        (CommandsExample::Funny, b"yba".to_vec())
+    );
+    peer.assert_finished();
+}
+
+#[test]
+fn interceptors_drop_and_synthesise_messages() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        .expect_payload(CommandsExample::Start, b"twice".to_vec())
+        .expect_payload(CommandsExample::Start, b"twice".to_vec())
+        .send(CommandsExample::Funny, b"dropped".to_vec())
+        .send(CommandsExample::Start, b"kept".to_vec())
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    client.add_interceptor(Rewriter);
+    client
+        .write_message(CommandsExample::Funny, b"dropped")
+        .expect("writing failed");
+    client
+        .write_message(CommandsExample::Start, b"twice")
+        .expect("writing failed");
+    for _ in 0..2 {
+        assert_eq!(
+            await_message(&mut client),
+            (CommandsExample::Start, b"kept".to_vec())
+        );
+    }
+    peer.assert_finished();
+    // the dropped message is not received
+    match client.await_message(Duration::from_secs(5), Some(Duration::from_millis(1))) {
+        Err(ReadThreadErrors::PeerClosed) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn immediate_route_is_between_the_interceptors() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        // the handler is installed meanwhile
+        .wait(Duration::from_millis(200))
+        .send(CommandsExample::Start, b"ping".to_vec())
+        .expect_payload(CommandsExample::Funny, b"ping!!".to_vec())
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    client.add_interceptor(Appender(b'!'));
+    // the incoming message was already intercepted, the reply is intercepted again
+    client.set_immediate_route_handler(
+        |command: &CommandsExample, message: &[u8], _busy_state: &BusyStatesExample| {
+            if *command == CommandsExample::Start {
+                Some(vec![(CommandsExample::Funny, message.to_vec())])
+            } else {
+                None
+            }
+        },
+    );
+    peer.assert_finished();
+}
@@ -0,0 +1,69 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{MockPeer, MockPeerFailure, PayloadLogging, TcpIpc, TcpIpcConfig};
+use std::time::Duration;
+
+fn config() -> TcpIpcConfig {
+    TcpIpcConfig {
+        after_connect_wait_time: None,
+        read_iteration_wait_time: Some(Duration::from_micros(100)),
+        shutdown_wait_time: Some(Duration::from_millis(100)),
+        check_count: 1,
+        payload_logging: PayloadLogging::LengthOnly,
+    }
+}
+
+#[test]
+fn script_is_met() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        .expect_payload(CommandsExample::Start, b"go".to_vec())
+        .send(CommandsExample::Funny, b"ok".to_vec())
+        .expect_matching(CommandsExample::Funny, "three bytes", |payload| {
+            payload.len() == 3
+        })
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    client
+        .write_message(CommandsExample::Start, b"go")
+        .expect("writing failed");
+    let reply = client
+        .await_message(Duration::from_secs(5), Some(Duration::from_millis(1)))
+        .expect("reading failed");
+    assert_eq!(reply, Some((CommandsExample::Funny, b"ok".to_vec())));
+    client
+        .write_message(CommandsExample::Funny, b"abc")
+        .expect("writing failed");
+    peer.assert_finished();
+}
+
+#[test]
+fn failures_are_reported() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        .timeout(Duration::from_millis(500))
+        .expect_payload(CommandsExample::Start, b"go".to_vec())
+        .expect(CommandsExample::Funny)
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    client
+        .write_message(CommandsExample::Start, b"stop")
+        .expect("writing failed");
+    let failures = peer.finish().expect_err("the mock peer did not fail");
+    assert_eq!(failures.len(), 2);
+    match &failures[0] {
+        MockPeerFailure::Mismatch((0, _, message)) => {
+            assert_eq!(message, &(CommandsExample::Start, b"stop".to_vec()))
+        }
+        failure => panic!("unexpected failure: {}", failure),
+    }
+    match &failures[1] {
+        MockPeerFailure::Unmet((1, _)) => {}
+        failure => panic!("unexpected failure: {}", failure),
+    }
+}
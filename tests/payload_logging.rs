@@ -0,0 +1,87 @@
+// with the feature "tracing", messages are logged via tracing (see tests/tracing_spans.rs)
+#![cfg(not(feature = "tracing"))]
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{PayloadLogging, Protocol, TcpIpc, TcpIpcConfig};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// remembers all log records, since the read thread logs, too
+struct Capture(Mutex<Vec<String>>);
+impl log::Log for Capture {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+    fn log(&self, record: &log::Record) {
+        self.0
+            .lock()
+            .expect("lock poisoned")
+            .push(record.args().to_string());
+    }
+    fn flush(&self) {}
+}
+
+static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+
+// the messages sent & received with the given payload logging, as logged
+fn logged_messages(payload_logging: PayloadLogging) -> Vec<String> {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("binding failed");
+    let address = listener.local_addr().expect("no local address");
+    let mut config = TcpIpcConfig::new(None, Some(Duration::from_micros(100)), None, 1);
+    config.payload_logging = payload_logging;
+    let mut client =
+        TcpIpc::<ProtocolExample>::client(address, config, None).expect("connecting failed");
+    let (mut peer, _) = listener.accept().expect("accepting failed");
+    CAPTURE.0.lock().expect("lock poisoned").clear();
+    client
+        .write_message(CommandsExample::Start, b"hello")
+        .expect("writing failed");
+    let mut received = [0; 10];
+    peer.read_exact(&mut received).expect("reading failed");
+    let reply = ProtocolExample::construct_message(CommandsExample::Funny, &[1, 2, 255])
+        .expect("constructing failed");
+    peer.write_all(&reply).expect("writing failed");
+    client
+        .await_message(Duration::from_secs(5), Some(Duration::from_millis(1)))
+        .expect("reading failed")
+        .expect("no message received");
+    let records = CAPTURE.0.lock().expect("lock poisoned").clone();
+    records
+        .into_iter()
+        .filter(|record| record.starts_with("Message "))
+        .collect()
+}
+
+// a single test, since the captured records are shared
+#[test]
+fn payloads_are_logged_as_configured() {
+    log::set_logger(&CAPTURE).expect("logger already set");
+    log::set_max_level(log::LevelFilter::Debug);
+    assert_eq!(
+        logged_messages(PayloadLogging::Off),
+        vec!["Message sent: Start ", "Message received: Funny "]
+    );
+    assert_eq!(
+        logged_messages(PayloadLogging::LengthOnly),
+        vec![
+            "Message sent: Start 5 bytes",
+            "Message received: Funny 3 bytes"
+        ]
+    );
+    assert_eq!(
+        logged_messages(PayloadLogging::HexPreview(3)),
+        vec![
+            "Message sent: Start 5 bytes [68 65 6c ...]",
+            "Message received: Funny 3 bytes [01 02 ff]"
+        ]
+    );
+    // the raw buffers are logged in the same way
+    let records = CAPTURE.0.lock().expect("lock poisoned").clone();
+    assert!(records.contains(&"New incoming buffer: 8 bytes [00 00 03 ...]".to_string()));
+    assert!(!records.iter().any(|record| record.contains("hello")));
+}
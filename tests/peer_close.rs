@@ -0,0 +1,109 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{Message, PayloadLogging, ReadThreadErrors, TcpIpc, TcpIpcConfig};
+use std::io::Write;
+use std::time::Duration;
+
+fn config() -> TcpIpcConfig {
+    TcpIpcConfig {
+        after_connect_wait_time: None,
+        read_iteration_wait_time: Some(Duration::from_micros(100)),
+        shutdown_wait_time: Some(Duration::from_millis(100)),
+        check_count: 1,
+        payload_logging: PayloadLogging::LengthOnly,
+    }
+}
+
+// a peer which sends the given bytes & closes the connection afterwards
+fn connect_to_closing_peer(bytes: Vec<u8>) -> TcpIpc<ProtocolExample> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("binding failed");
+    let address = listener.local_addr().expect("no local address");
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accepting failed");
+        stream.write_all(&bytes).expect("writing failed");
+    });
+    TcpIpc::<ProtocolExample>::client(address, config(), None).expect("connecting failed")
+}
+
+// waits until a message or an error is available
+fn next_result(
+    client: &mut TcpIpc<ProtocolExample>,
+) -> Result<Message<ProtocolExample>, ReadThreadErrors<ProtocolExample>> {
+    let started = std::time::Instant::now();
+    loop {
+        match client.get_message() {
+            Ok(Some(message)) => return Ok(message),
+            Ok(None) => {
+                assert!(
+                    started.elapsed() < Duration::from_secs(5),
+                    "nothing received"
+                );
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[test]
+fn peer_close_is_reported_after_the_received_messages() {
+    let mut client =
+        connect_to_closing_peer(vec![0, 0, 2, b'4', b'2', b'o', b'k', 0, 0, 0, b'0', b'0']);
+    assert_eq!(
+        next_result(&mut client).expect("reading failed"),
+        (CommandsExample::Funny, b"ok".to_vec())
+    );
+    assert_eq!(
+        next_result(&mut client).expect("reading failed"),
+        (CommandsExample::Start, Vec::new())
+    );
+    match next_result(&mut client) {
+        Err(ReadThreadErrors::PeerClosed) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    // the state is kept
+    match client.get_message() {
+        Err(ReadThreadErrors::PeerClosed) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn truncated_payload_is_reported() {
+    // the second frame announces five bytes, but only two are sent
+    let mut client = connect_to_closing_peer(vec![
+        0, 0, 1, b'4', b'2', b'!', 0, 0, 5, b'0', b'0', b'a', b'b',
+    ]);
+    assert_eq!(
+        next_result(&mut client).expect("reading failed"),
+        (CommandsExample::Funny, b"!".to_vec())
+    );
+    match next_result(&mut client) {
+        Err(ReadThreadErrors::TruncatedMessage((Some(CommandsExample::Start), payload))) => {
+            assert_eq!(payload, b"ab".to_vec())
+        }
+        result => panic!("unexpected result: {:?}", result),
+    }
+    match next_result(&mut client) {
+        Err(ReadThreadErrors::PeerClosed) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn truncated_header_is_reported() {
+    let mut client = connect_to_closing_peer(vec![0, 0, 1]);
+    match next_result(&mut client) {
+        Err(ReadThreadErrors::TruncatedMessage((None, header))) => {
+            assert_eq!(header, vec![0, 0, 1])
+        }
+        result => panic!("unexpected result: {:?}", result),
+    }
+    match next_result(&mut client) {
+        Err(ReadThreadErrors::PeerClosed) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
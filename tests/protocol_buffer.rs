@@ -0,0 +1,130 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use proptest::prelude::*;
+use rust_tcp_ipc::{CodecErrors, Decoder, Message, Protocol};
+
+fn messages() -> impl Strategy<Value = Vec<Message<ProtocolExample>>> {
+    let command = prop_oneof![Just(CommandsExample::Start), Just(CommandsExample::Funny)];
+    // payloads are mostly short, but some exceed one byte of the length field
+    let payload = prop_oneof![
+        4 => proptest::collection::vec(any::<u8>(), 0..16),
+        1 => proptest::collection::vec(any::<u8>(), 0..1_000),
+    ];
+    proptest::collection::vec((command, payload), 0..20)
+}
+
+fn encode(messages: &[Message<ProtocolExample>]) -> Vec<u8> {
+    messages
+        .iter()
+        .flat_map(|(command, payload)| {
+            ProtocolExample::construct_message(*command, payload).expect("encoding failed")
+        })
+        .collect()
+}
+
+// splits the bytes at the given cut points (taken modulo the length)
+fn split(bytes: &[u8], cuts: &[usize]) -> Vec<Vec<u8>> {
+    let mut cuts = cuts
+        .iter()
+        .map(|cut| cut % (bytes.len() + 1))
+        .collect::<Vec<_>>();
+    cuts.push(0);
+    cuts.push(bytes.len());
+    cuts.sort_unstable();
+    cuts.windows(2)
+        .map(|window| bytes[window[0]..window[1]].to_vec())
+        .collect()
+}
+
+// feeds the chunks one by one, collecting every completed message
+fn decode(
+    buffer: &mut Decoder<ProtocolExample>,
+    chunks: &[Vec<u8>],
+) -> Vec<Message<ProtocolExample>> {
+    let mut decoded = Vec::new();
+    for chunk in chunks {
+        for message in buffer.decode(chunk) {
+            decoded.push(message.expect("parsing the header failed"));
+        }
+    }
+    decoded
+}
+
+// the partially received message (if any), which resets the decoder
+fn take_truncated_message(
+    buffer: &mut Decoder<ProtocolExample>,
+) -> Option<(Option<CommandsExample>, Vec<u8>)> {
+    match buffer.finish() {
+        Ok(()) => None,
+        Err(CodecErrors::TruncatedMessage(truncated_message)) => Some(truncated_message),
+        Err(err) => panic!("unexpected error: {:?}", err),
+    }
+}
+
+proptest! {
+    #[test]
+    fn split_messages_are_reassembled(messages in messages(), cuts in proptest::collection::vec(any::<usize>(), 0..40)) {
+        let chunks = split(&encode(&messages), &cuts);
+        let mut buffer = Decoder::<ProtocolExample>::new();
+        prop_assert_eq!(decode(&mut buffer, &chunks), messages);
+        prop_assert_eq!(take_truncated_message(&mut buffer), None);
+    }
+
+    #[test]
+    fn truncated_stream_yields_complete_messages_and_remainder(
+        messages in messages(),
+        cuts in proptest::collection::vec(any::<usize>(), 0..40),
+        end in any::<usize>(),
+    ) {
+        let bytes = encode(&messages);
+        let end = end % (bytes.len() + 1);
+        let chunks = split(&bytes[..end], &cuts);
+        let mut buffer = Decoder::<ProtocolExample>::new();
+        let decoded = decode(&mut buffer, &chunks);
+        prop_assert_eq!(&decoded[..], &messages[..decoded.len()]);
+        let consumed = encode(&decoded).len();
+        match take_truncated_message(&mut buffer) {
+            None => prop_assert_eq!(consumed, end),
+            Some((None, header)) => {
+                prop_assert!(header.len() < 5);
+                prop_assert_eq!(&header[..], &bytes[consumed..end]);
+            }
+            Some((Some(command), payload)) => {
+                let (expected_command, expected_payload) = &messages[decoded.len()];
+                prop_assert_eq!(command, *expected_command);
+                prop_assert!(payload.len() < expected_payload.len());
+                prop_assert_eq!(&payload[..], &expected_payload[..payload.len()]);
+                prop_assert_eq!(consumed + 5 + payload.len(), end);
+            }
+        }
+        // the buffer is reset afterwards
+        prop_assert_eq!(decode(&mut buffer, &[encode(&messages)]), messages);
+    }
+
+    #[test]
+    fn arbitrary_bytes_do_not_panic(bytes in proptest::collection::vec(any::<u8>(), 0..2_000), cuts in proptest::collection::vec(any::<usize>(), 0..40)) {
+        let mut buffer = Decoder::<ProtocolExample>::new();
+        'chunks: for chunk in split(&bytes, &cuts) {
+            for message in buffer.decode(&chunk) {
+                if message.is_err() {
+                    // the stream cannot be decoded any further
+                    break 'chunks;
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn all_messages_of_a_chunk_are_decoded_at_once() {
+    let messages = vec![(CommandsExample::Start, Vec::new()); 100_000];
+    let mut buffer = Decoder::<ProtocolExample>::new();
+    let decoded = buffer
+        .decode(&encode(&messages))
+        .collect::<Result<Vec<_>, _>>()
+        .expect("parsing a header failed");
+    assert_eq!(decoded, messages);
+}
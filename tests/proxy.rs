@@ -0,0 +1,188 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{CancelHandle, FrameAction, Protocol, Proxy, ProxyDirection};
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+type Observed = Arc<Mutex<Vec<(ProxyDirection, CommandsExample, Vec<u8>)>>>;
+
+fn frame(command: CommandsExample, payload: &[u8]) -> Vec<u8> {
+    ProtocolExample::construct_message(command, payload).expect("constructing failed")
+}
+
+// a client connected via the proxy to a server
+struct Setup {
+    client: TcpStream,
+    server: TcpStream,
+    cancel_handle: CancelHandle,
+    proxy: std::thread::JoinHandle<()>,
+}
+impl Setup {
+    fn new(proxy: Proxy<ProtocolExample>) -> Setup {
+        let server_listener = TcpListener::bind("127.0.0.1:0").expect("binding failed");
+        let server_address = server_listener.local_addr().expect("no local address");
+        let proxy_listener = TcpListener::bind("127.0.0.1:0").expect("binding failed");
+        let proxy_address = proxy_listener.local_addr().expect("no local address");
+        let cancel_handle = CancelHandle::new();
+        let proxy = {
+            let cancel_handle = cancel_handle.clone();
+            std::thread::spawn(move || {
+                proxy
+                    .serve(&proxy_listener, server_address, &cancel_handle)
+                    .expect("proxy failed")
+            })
+        };
+        let client = TcpStream::connect(proxy_address).expect("connecting failed");
+        client.set_nodelay(true).expect("setting nodelay failed");
+        let (server, _) = server_listener.accept().expect("accepting failed");
+        server.set_nodelay(true).expect("setting nodelay failed");
+        Setup {
+            client,
+            server,
+            cancel_handle,
+            proxy,
+        }
+    }
+    fn stop(self) {
+        self.cancel_handle.cancel();
+        self.proxy.join().expect("the proxy panicked");
+    }
+}
+
+// reads until the writing side is closed
+fn read_to_end(stream: &mut TcpStream) -> Vec<u8> {
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .expect("setting the timeout failed");
+    let mut bytes = Vec::new();
+    stream.read_to_end(&mut bytes).expect("reading failed");
+    bytes
+}
+
+fn observing_proxy(observed: &Observed) -> Proxy<ProtocolExample> {
+    let observed = observed.clone();
+    Proxy::new().on_message(move |direction, command, payload| {
+        observed
+            .lock()
+            .expect("lock poisoned")
+            .push((direction, *command, payload.to_vec()))
+    })
+}
+
+#[test]
+fn split_frames_are_forwarded_exactly() {
+    let observed = Observed::default();
+    let mut setup = Setup::new(observing_proxy(&observed));
+    let mut bytes = frame(CommandsExample::Start, b"first");
+    bytes.extend(frame(CommandsExample::Funny, &[]));
+    bytes.extend(frame(CommandsExample::Funny, &[9; 300]));
+    // split inside the header, inside the payload & between frames
+    for chunk in [
+        &bytes[0..2],
+        &bytes[2..7],
+        &bytes[7..11],
+        &bytes[11..20],
+        &bytes[20..],
+    ]
+    .iter()
+    {
+        setup.client.write_all(chunk).expect("writing failed");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    setup
+        .client
+        .shutdown(Shutdown::Write)
+        .expect("shutdown failed");
+    assert_eq!(read_to_end(&mut setup.server), bytes);
+    setup.stop();
+    assert_eq!(
+        *observed.lock().expect("lock poisoned"),
+        vec![
+            (
+                ProxyDirection::ClientToServer,
+                CommandsExample::Start,
+                b"first".to_vec()
+            ),
+            (
+                ProxyDirection::ClientToServer,
+                CommandsExample::Funny,
+                Vec::new()
+            ),
+            (
+                ProxyDirection::ClientToServer,
+                CommandsExample::Funny,
+                vec![9; 300]
+            ),
+        ]
+    );
+}
+
+#[test]
+fn frame_actions_are_applied() {
+    let proxy = Proxy::<ProtocolExample>::new().on_frame(|direction, command, payload| {
+        assert_eq!(direction, ProxyDirection::ServerToClient);
+        match (command, payload) {
+            (CommandsExample::Funny, b"drop") => FrameAction::Drop,
+            (CommandsExample::Funny, b"replace") => {
+                FrameAction::Replace(frame(CommandsExample::Start, b"replaced"))
+            }
+            (CommandsExample::Funny, b"delay") => FrameAction::Delay(Duration::from_millis(10)),
+            (CommandsExample::Funny, b"disconnect") => FrameAction::Disconnect,
+            _ => FrameAction::Forward,
+        }
+    });
+    let mut setup = Setup::new(proxy);
+    for payload in [
+        &b"forward"[..],
+        b"drop",
+        b"replace",
+        b"delay",
+        b"disconnect",
+        b"lost",
+    ]
+    .iter()
+    {
+        setup
+            .server
+            .write_all(&frame(CommandsExample::Funny, payload))
+            .expect("writing failed");
+    }
+    let mut expected = frame(CommandsExample::Funny, b"forward");
+    expected.extend(frame(CommandsExample::Start, b"replaced"));
+    expected.extend(frame(CommandsExample::Funny, b"delay"));
+    // the frames after the disconnect are not forwarded
+    assert_eq!(read_to_end(&mut setup.client), expected);
+    setup.stop();
+}
+
+#[test]
+fn undecodable_bytes_are_forwarded_unchanged() {
+    let observed = Observed::default();
+    let mut setup = Setup::new(observing_proxy(&observed));
+    let mut bytes = frame(CommandsExample::Start, b"valid");
+    // "zz" is no command
+    bytes.extend_from_slice(&[0, 0, 1, b'z', b'z', 1]);
+    bytes.extend(frame(CommandsExample::Funny, b"not decoded"));
+    setup.client.write_all(&bytes[..8]).expect("writing failed");
+    std::thread::sleep(Duration::from_millis(20));
+    setup.client.write_all(&bytes[8..]).expect("writing failed");
+    setup
+        .client
+        .shutdown(Shutdown::Write)
+        .expect("shutdown failed");
+    assert_eq!(read_to_end(&mut setup.server), bytes);
+    setup.stop();
+    assert_eq!(
+        *observed.lock().expect("lock poisoned"),
+        vec![(
+            ProxyDirection::ClientToServer,
+            CommandsExample::Start,
+            b"valid".to_vec()
+        )]
+    );
+}
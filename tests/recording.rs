@@ -0,0 +1,199 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{
+    Direction, MockPeer, PayloadLogging, Protocol, ReadThreadErrors, ReplayTiming, SessionRecord,
+    SessionRecorder, SessionReplay, TcpIpc, TcpIpcConfig,
+};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn config() -> TcpIpcConfig {
+    TcpIpcConfig {
+        after_connect_wait_time: None,
+        read_iteration_wait_time: Some(Duration::from_micros(100)),
+        shutdown_wait_time: Some(Duration::from_millis(100)),
+        check_count: 1,
+        payload_logging: PayloadLogging::LengthOnly,
+    }
+}
+
+// a recording target which can be read while the recorder still exists
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+impl Write for SharedBuffer {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .lock()
+            .expect("lock poisoned")
+            .extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn record_session() -> SessionReplay {
+    let peer = MockPeer::<ProtocolExample>::new()
+        .expect_payload(CommandsExample::Start, b"hello".to_vec())
+        .send(CommandsExample::Funny, b"first".to_vec())
+        .send(CommandsExample::Funny, vec![7; 1000])
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    let buffer = SharedBuffer::default();
+    let recorder = SessionRecorder::new(buffer.clone()).expect("unable to create recording");
+    client.set_recorder(Some(recorder));
+    client
+        .write_message(CommandsExample::Start, b"hello")
+        .expect("writing failed");
+    for _ in 0..2 {
+        client
+            .await_message(Duration::from_secs(5), Some(Duration::from_millis(1)))
+            .expect("reading failed")
+            .expect("no message received");
+    }
+    peer.assert_finished();
+    client.set_recorder(None);
+    let bytes = buffer.0.lock().expect("lock poisoned").clone();
+    SessionReplay::from_reader(&bytes[..]).expect("reading the recording failed")
+}
+
+#[test]
+fn recorded_session_is_decoded() {
+    let replay = record_session();
+    let outgoing: Vec<u8> = replay
+        .records()
+        .iter()
+        .filter(|record| record.direction == Direction::Outgoing)
+        .flat_map(|record| record.bytes.iter().cloned())
+        .collect();
+    assert_eq!(
+        Some(outgoing),
+        ProtocolExample::construct_message(CommandsExample::Start, b"hello")
+    );
+    assert!(replay
+        .records()
+        .windows(2)
+        .all(|records| records[0].timestamp <= records[1].timestamp));
+    let messages: Vec<_> = replay
+        .incoming_messages::<ProtocolExample>()
+        .expect("decoding failed")
+        .into_iter()
+        .map(|(_timestamp, message)| message)
+        .collect();
+    assert_eq!(
+        messages,
+        vec![
+            (CommandsExample::Funny, b"first".to_vec()),
+            (CommandsExample::Funny, vec![7; 1000]),
+        ]
+    );
+}
+
+#[test]
+fn replayed_peer_sends_the_recorded_messages() {
+    let replay = record_session();
+    let (address, peer) = replay
+        .spawn_peer(ReplayTiming::Accelerated(10.0))
+        .expect("unable to start peer");
+    let mut client =
+        TcpIpc::<ProtocolExample>::client(address, config(), None).expect("connecting failed");
+    let mut messages = Vec::new();
+    loop {
+        match client.await_message(Duration::from_secs(5), Some(Duration::from_millis(1))) {
+            Ok(Some(message)) => messages.push(message),
+            Err(ReadThreadErrors::PeerClosed) => break,
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+    assert_eq!(
+        messages,
+        vec![
+            (CommandsExample::Funny, b"first".to_vec()),
+            (CommandsExample::Funny, vec![7; 1000]),
+        ]
+    );
+    // the peer waits until the connection is closed
+    drop(client);
+    peer.join()
+        .expect("the peer panicked")
+        .expect("replaying failed");
+}
+
+#[test]
+fn recording_file_is_read_back() {
+    let path = std::env::temp_dir().join(format!("rust_tcp_ipc_{}.rec", std::process::id()));
+    let recorder = SessionRecorder::create(&path).expect("unable to create recording");
+    recorder
+        .record(Direction::Outgoing, b"out")
+        .expect("recording failed");
+    recorder
+        .record(Direction::Incoming, b"")
+        .expect("recording failed");
+    recorder
+        .record(Direction::Incoming, b"in")
+        .expect("recording failed");
+    recorder.flush().expect("flushing failed");
+    let replay = SessionReplay::open(&path).expect("reading the recording failed");
+    let _ = std::fs::remove_file(&path);
+    let records: Vec<_> = replay
+        .records()
+        .iter()
+        .map(|record| (record.direction, record.bytes.clone()))
+        .collect();
+    assert_eq!(
+        records,
+        vec![
+            (Direction::Outgoing, b"out".to_vec()),
+            (Direction::Incoming, Vec::new()),
+            (Direction::Incoming, b"in".to_vec()),
+        ]
+    );
+}
+
+#[test]
+fn invalid_recordings_are_rejected() {
+    let error = SessionReplay::from_reader(&b"not a recording"[..])
+        .expect_err("invalid recording accepted");
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    // a record announcing more bytes than available
+    let buffer = SharedBuffer::default();
+    let recorder = SessionRecorder::new(buffer.clone()).expect("unable to create recording");
+    recorder
+        .record(Direction::Incoming, b"complete")
+        .expect("recording failed");
+    let mut bytes = buffer.0.lock().expect("lock poisoned").clone();
+    bytes.pop();
+    let error = SessionReplay::from_reader(&bytes[..]).expect_err("truncated recording accepted");
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn invalid_acceleration_factors_are_rejected() {
+    let replay = SessionReplay::from_records(vec![SessionRecord {
+        timestamp: Duration::from_millis(10),
+        direction: Direction::Incoming,
+        bytes: b"in".to_vec(),
+    }]);
+    for &factor in [0.0, -2.0, f64::NAN].iter() {
+        let error = replay
+            .replay_incoming(&mut Vec::new(), ReplayTiming::Accelerated(factor))
+            .expect_err("invalid factor accepted");
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        let error = replay
+            .spawn_peer(ReplayTiming::Accelerated(factor))
+            .expect_err("invalid factor accepted");
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    }
+    let mut replayed = Vec::new();
+    replay
+        .replay_incoming(&mut replayed, ReplayTiming::Accelerated(1e300))
+        .expect("replaying failed");
+    assert_eq!(replayed, b"in".to_vec());
+}
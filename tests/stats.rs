@@ -0,0 +1,117 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{CommandStats, ConnectionStats};
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn prometheus_text_contains_all_metrics() {
+    let stats = ConnectionStats::<ProtocolExample> {
+        per_command: vec![
+            (
+                CommandsExample::Start,
+                CommandStats {
+                    messages_sent: 1,
+                    bytes_sent: 2,
+                    messages_received: 3,
+                    bytes_received: 4,
+                },
+            ),
+            (
+                CommandsExample::Funny,
+                CommandStats {
+                    messages_sent: 5,
+                    bytes_sent: 6,
+                    messages_received: 7,
+                    bytes_received: 8,
+                },
+            ),
+        ],
+        immediate_replies_sent: 9,
+        parse_errors: 10,
+        queue_depth: 11,
+        messages_dropped: 12,
+        last_sent: Some(SystemTime::UNIX_EPOCH + Duration::from_millis(1_500)),
+        last_received: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(2)),
+        heartbeat_round_trip_time: Some(Duration::from_millis(250)),
+    };
+    let expected = "\
+# HELP rust_tcp_ipc_messages_sent_total Number of messages sent.
+# TYPE rust_tcp_ipc_messages_sent_total counter
+rust_tcp_ipc_messages_sent_total{connection=\"device\",command=\"Start\"} 1
+rust_tcp_ipc_messages_sent_total{connection=\"device\",command=\"Funny\"} 5
+# HELP rust_tcp_ipc_bytes_sent_total Number of payload bytes sent.
+# TYPE rust_tcp_ipc_bytes_sent_total counter
+rust_tcp_ipc_bytes_sent_total{connection=\"device\",command=\"Start\"} 2
+rust_tcp_ipc_bytes_sent_total{connection=\"device\",command=\"Funny\"} 6
+# HELP rust_tcp_ipc_messages_received_total Number of messages received.
+# TYPE rust_tcp_ipc_messages_received_total counter
+rust_tcp_ipc_messages_received_total{connection=\"device\",command=\"Start\"} 3
+rust_tcp_ipc_messages_received_total{connection=\"device\",command=\"Funny\"} 7
+# HELP rust_tcp_ipc_bytes_received_total Number of payload bytes received.
+# TYPE rust_tcp_ipc_bytes_received_total counter
+rust_tcp_ipc_bytes_received_total{connection=\"device\",command=\"Start\"} 4
+rust_tcp_ipc_bytes_received_total{connection=\"device\",command=\"Funny\"} 8
+# HELP rust_tcp_ipc_immediate_replies_sent_total Number of messages answered via the immediate route.
+# TYPE rust_tcp_ipc_immediate_replies_sent_total counter
+rust_tcp_ipc_immediate_replies_sent_total{connection=\"device\"} 9
+# HELP rust_tcp_ipc_parse_errors_total Number of headers which could not be parsed.
+# TYPE rust_tcp_ipc_parse_errors_total counter
+rust_tcp_ipc_parse_errors_total{connection=\"device\"} 10
+# HELP rust_tcp_ipc_queue_depth Number of received messages waiting in the message queue.
+# TYPE rust_tcp_ipc_queue_depth gauge
+rust_tcp_ipc_queue_depth{connection=\"device\"} 11
+# HELP rust_tcp_ipc_messages_dropped_total Number of received messages dropped since the message queue was full.
+# TYPE rust_tcp_ipc_messages_dropped_total counter
+rust_tcp_ipc_messages_dropped_total{connection=\"device\"} 12
+# HELP rust_tcp_ipc_last_sent_timestamp_seconds Time the last message was sent.
+# TYPE rust_tcp_ipc_last_sent_timestamp_seconds gauge
+rust_tcp_ipc_last_sent_timestamp_seconds{connection=\"device\"} 1.5
+# HELP rust_tcp_ipc_last_received_timestamp_seconds Time the last message was received.
+# TYPE rust_tcp_ipc_last_received_timestamp_seconds gauge
+rust_tcp_ipc_last_received_timestamp_seconds{connection=\"device\"} 2
+# HELP rust_tcp_ipc_heartbeat_round_trip_time_seconds Round trip time of the last answered heartbeat.
+# TYPE rust_tcp_ipc_heartbeat_round_trip_time_seconds gauge
+rust_tcp_ipc_heartbeat_round_trip_time_seconds{connection=\"device\"} 0.25
+";
+    assert_eq!(stats.to_prometheus("device"), expected);
+}
+
+#[test]
+fn missing_values_are_omitted() {
+    let stats = ConnectionStats::<ProtocolExample>::default();
+    let expected = "\
+# HELP rust_tcp_ipc_messages_sent_total Number of messages sent.
+# TYPE rust_tcp_ipc_messages_sent_total counter
+# HELP rust_tcp_ipc_bytes_sent_total Number of payload bytes sent.
+# TYPE rust_tcp_ipc_bytes_sent_total counter
+# HELP rust_tcp_ipc_messages_received_total Number of messages received.
+# TYPE rust_tcp_ipc_messages_received_total counter
+# HELP rust_tcp_ipc_bytes_received_total Number of payload bytes received.
+# TYPE rust_tcp_ipc_bytes_received_total counter
+# HELP rust_tcp_ipc_immediate_replies_sent_total Number of messages answered via the immediate route.
+# TYPE rust_tcp_ipc_immediate_replies_sent_total counter
+rust_tcp_ipc_immediate_replies_sent_total{connection=\"\"} 0
+# HELP rust_tcp_ipc_parse_errors_total Number of headers which could not be parsed.
+# TYPE rust_tcp_ipc_parse_errors_total counter
+rust_tcp_ipc_parse_errors_total{connection=\"\"} 0
+# HELP rust_tcp_ipc_queue_depth Number of received messages waiting in the message queue.
+# TYPE rust_tcp_ipc_queue_depth gauge
+rust_tcp_ipc_queue_depth{connection=\"\"} 0
+# HELP rust_tcp_ipc_messages_dropped_total Number of received messages dropped since the message queue was full.
+# TYPE rust_tcp_ipc_messages_dropped_total counter
+rust_tcp_ipc_messages_dropped_total{connection=\"\"} 0
+";
+    assert_eq!(stats.to_prometheus(""), expected);
+}
+
+#[test]
+fn label_values_are_escaped() {
+    let stats = ConnectionStats::<ProtocolExample>::default();
+    let text = stats.to_prometheus("a \"quoted\" \\ name\nwith a line break");
+    assert!(text.contains(
+        "rust_tcp_ipc_queue_depth{connection=\"a \\\"quoted\\\" \\\\ name\\nwith a line break\"} 0\n"
+    ));
+}
@@ -0,0 +1,161 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{PayloadLogging, Protocol, TcpIpc, TcpIpcConfig, WriteMessageErrors};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+fn config() -> TcpIpcConfig {
+    TcpIpcConfig {
+        after_connect_wait_time: None,
+        read_iteration_wait_time: Some(Duration::from_micros(100)),
+        shutdown_wait_time: Some(Duration::from_millis(100)),
+        check_count: 1,
+        payload_logging: PayloadLogging::LengthOnly,
+    }
+}
+
+// a client & the raw peer it is connected to
+fn connect() -> (TcpIpc<ProtocolExample>, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("binding failed");
+    let address = listener.local_addr().expect("no local address");
+    let client =
+        TcpIpc::<ProtocolExample>::client(address, config(), None).expect("connecting failed");
+    let (peer, _) = listener.accept().expect("accepting failed");
+    peer.set_read_timeout(Some(Duration::from_secs(5)))
+        .expect("setting the timeout failed");
+    (client, peer)
+}
+
+fn payload(length: usize) -> Vec<u8> {
+    (0..length).map(|index| (index % 251) as u8).collect()
+}
+
+fn frame(command: CommandsExample, payload: &[u8]) -> Vec<u8> {
+    ProtocolExample::construct_message(command, payload).expect("constructing failed")
+}
+
+// writes the bytes from another thread, since the client may not read them immediately
+fn write_in_background(mut peer: TcpStream, bytes: Vec<u8>) -> std::thread::JoinHandle<TcpStream> {
+    std::thread::spawn(move || {
+        peer.write_all(&bytes).expect("writing failed");
+        peer
+    })
+}
+
+#[test]
+fn large_messages_are_streamed() {
+    let (mut client, peer) = connect();
+    client.set_streaming_threshold(Some(1000));
+    let large_payload = payload(300_000);
+    let mut bytes = frame(CommandsExample::Start, &large_payload);
+    bytes.extend(frame(CommandsExample::Funny, b"small"));
+    let peer = write_in_background(peer, bytes);
+    let mut stream = client
+        .await_stream(Duration::from_secs(5))
+        .expect("no stream started");
+    assert_eq!(stream.command(), CommandsExample::Start);
+    assert_eq!(stream.length(), large_payload.len());
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).expect("reading failed");
+    assert_eq!(received, large_payload);
+    assert_eq!(stream.remaining(), 0);
+    // messages below the threshold are received as usual
+    assert_eq!(
+        client
+            .await_message(Duration::from_secs(5), Some(Duration::from_millis(1)))
+            .expect("reading failed"),
+        Some((CommandsExample::Funny, b"small".to_vec()))
+    );
+    assert!(client.get_stream().is_none());
+    peer.join().expect("the peer panicked");
+}
+
+#[test]
+fn lost_connection_fails_the_stream() {
+    let (mut client, peer) = connect();
+    client.set_streaming_threshold(Some(1000));
+    let mut bytes = frame(CommandsExample::Start, &payload(5000));
+    bytes.truncate(3000);
+    // the peer closes the connection after writing
+    drop(write_in_background(peer, bytes).join());
+    let mut stream = client
+        .await_stream(Duration::from_secs(5))
+        .expect("no stream started");
+    let error = stream
+        .read_to_end(&mut Vec::new())
+        .expect_err("incomplete payload accepted");
+    assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn unread_stream_is_aborted() {
+    let (mut client, peer) = connect();
+    client.set_streaming_threshold(Some(1000));
+    let mut bytes = frame(CommandsExample::Start, &payload(4 << 20));
+    bytes.extend(frame(CommandsExample::Funny, b"after"));
+    let peer = write_in_background(peer, bytes);
+    let mut stream = client
+        .await_stream(Duration::from_secs(5))
+        .expect("no stream started");
+    // the stream is not read, so the read thread gives up on it
+    let started = Instant::now();
+    assert_eq!(
+        client
+            .await_message(Duration::from_secs(10), Some(Duration::from_millis(1)))
+            .expect("reading failed"),
+        Some((CommandsExample::Funny, b"after".to_vec()))
+    );
+    assert!(started.elapsed() >= Duration::from_millis(900));
+    let error = stream
+        .read_to_end(&mut Vec::new())
+        .expect_err("aborted stream completed");
+    assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+    peer.join().expect("the peer panicked");
+}
+
+#[test]
+fn payload_is_sent_from_a_reader() {
+    let (mut client, mut peer) = connect();
+    let large_payload = payload(300_000);
+    let mut expected = frame(CommandsExample::Funny, &large_payload);
+    expected.extend(frame(CommandsExample::Start, b"next"));
+    // the peer reads meanwhile, since the payload does not fit into the socket buffers
+    let expected_length = expected.len();
+    let peer = std::thread::spawn(move || {
+        let mut received = vec![0; expected_length];
+        peer.read_exact(&mut received).expect("reading failed");
+        received
+    });
+    client
+        .write_message_from_reader(
+            CommandsExample::Funny,
+            large_payload.len(),
+            &mut &large_payload[..],
+        )
+        .expect("sending failed");
+    client
+        .write_message(CommandsExample::Start, b"next")
+        .expect("writing failed");
+    assert_eq!(peer.join().expect("the peer panicked"), expected);
+}
+
+#[test]
+fn short_reader_is_reported() {
+    let (mut client, mut peer) = connect();
+    match client.write_message_from_reader(CommandsExample::Funny, 100, &mut &[1u8; 60][..]) {
+        Err(WriteMessageErrors::MessageSendFailed(err)) => {
+            assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof)
+        }
+        result => panic!("unexpected result: {:?}", result),
+    }
+    // the available part was sent nevertheless
+    let mut expected = frame(CommandsExample::Funny, &[1; 100]);
+    expected.truncate(expected.len() - 40);
+    let mut received = vec![0; expected.len()];
+    peer.read_exact(&mut received).expect("reading failed");
+    assert_eq!(received, expected);
+}
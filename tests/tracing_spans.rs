@@ -0,0 +1,177 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{Protocol, TcpIpc, TcpIpcConfig};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+// a span (or an event) with its formatted fields
+#[derive(Debug, Clone, Default)]
+struct Recorded {
+    name: String,
+    fields: HashMap<String, String>,
+    parent: Option<u64>,
+}
+
+impl Visit for Recorded {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.fields
+            .insert(field.name().to_string(), format!("{:?}", value));
+    }
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.fields
+            .insert(field.name().to_string(), value.to_string());
+    }
+}
+
+thread_local! {
+    // the spans entered by the current thread
+    static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+fn current_span() -> Option<u64> {
+    ENTERED.with(|entered| entered.borrow().last().copied())
+}
+
+#[derive(Default)]
+struct Capture {
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, Recorded>>,
+    events: Mutex<Vec<Recorded>>,
+}
+
+#[derive(Clone, Default)]
+struct CaptureSubscriber(Arc<Capture>);
+
+impl Subscriber for CaptureSubscriber {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+    fn new_span(&self, attributes: &Attributes) -> Id {
+        let id = self.0.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let parent = if attributes.is_root() {
+            None
+        } else if let Some(parent) = attributes.parent() {
+            Some(parent.into_u64())
+        } else {
+            current_span()
+        };
+        let mut span = Recorded {
+            name: attributes.metadata().name().to_string(),
+            parent,
+            ..Recorded::default()
+        };
+        attributes.record(&mut span);
+        self.0.spans.lock().expect("lock poisoned").insert(id, span);
+        Id::from_u64(id)
+    }
+    fn record(&self, span: &Id, values: &Record) {
+        if let Some(span) = self
+            .0
+            .spans
+            .lock()
+            .expect("lock poisoned")
+            .get_mut(&span.into_u64())
+        {
+            values.record(span);
+        }
+    }
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+    fn event(&self, event: &Event) {
+        let mut recorded = Recorded {
+            name: event.metadata().name().to_string(),
+            parent: event
+                .parent()
+                .map(Id::into_u64)
+                .or_else(|| current_span().filter(|_| event.is_contextual())),
+            ..Recorded::default()
+        };
+        event.record(&mut recorded);
+        self.0.events.lock().expect("lock poisoned").push(recorded);
+    }
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+    }
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(position) = entered.iter().rposition(|id| *id == span.into_u64()) {
+                entered.remove(position);
+            }
+        });
+    }
+}
+
+// a single test, since the subscriber is set globally (the read thread logs, too)
+#[test]
+fn messages_are_traced_in_the_connection_span() {
+    let subscriber = CaptureSubscriber::default();
+    let capture = subscriber.0.clone();
+    tracing::subscriber::set_global_default(subscriber).expect("subscriber already set");
+    let listener = TcpListener::bind("127.0.0.1:0").expect("binding failed");
+    let address = listener.local_addr().expect("no local address");
+    let mut client = TcpIpc::<ProtocolExample>::client(
+        address,
+        TcpIpcConfig::new(None, Some(Duration::from_micros(100)), None, 1),
+        None,
+    )
+    .expect("connecting failed");
+    let (mut peer, _) = listener.accept().expect("accepting failed");
+    client
+        .write_message(CommandsExample::Start, b"hello")
+        .expect("writing failed");
+    let mut received = [0; 10];
+    peer.read_exact(&mut received).expect("reading failed");
+    let reply = ProtocolExample::construct_message(CommandsExample::Funny, b"abc")
+        .expect("constructing failed");
+    peer.write_all(&reply).expect("writing failed");
+    client
+        .await_message(Duration::from_secs(5), Some(Duration::from_millis(1)))
+        .expect("reading failed")
+        .expect("no message received");
+
+    let spans = capture.spans.lock().expect("lock poisoned").clone();
+    let connections: Vec<_> = spans
+        .iter()
+        .filter(|(_, span)| span.name == "connection")
+        .collect();
+    assert_eq!(connections.len(), 1, "unexpected spans: {:?}", spans);
+    let (connection_id, connection) = connections[0];
+    assert_eq!(connection.fields["peer"], format!("Some({})", address));
+    assert_eq!(connection.parent, None);
+
+    let events = capture.events.lock().expect("lock poisoned").clone();
+    let message = |direction: &str| {
+        let (id, span) = spans
+            .iter()
+            .find(|(_, span)| span.name == "message" && span.fields["direction"] == direction)
+            .unwrap_or_else(|| panic!("no {} message span: {:?}", direction, spans));
+        assert_eq!(span.parent, Some(*connection_id));
+        let event = events
+            .iter()
+            .find(|event| event.parent == Some(*id))
+            .unwrap_or_else(|| panic!("no {} message event: {:?}", direction, events));
+        (span.fields.clone(), event.fields.clone())
+    };
+    let (sent, sent_event) = message("sent");
+    assert_eq!(sent["command"], "Start");
+    assert_eq!(sent["length"], "5");
+    assert_eq!(sent_event["payload"], "5 bytes");
+    assert_eq!(sent_event["message"], "Message sent");
+    let (received, received_event) = message("received");
+    assert_eq!(received["command"], "Funny");
+    assert_eq!(received["length"], "3");
+    assert_eq!(received_event["payload"], "3 bytes");
+    assert_eq!(received_event["message"], "Message received");
+}
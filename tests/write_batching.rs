@@ -0,0 +1,188 @@
+#[path = "../benches/example_protocol.rs"]
+#[allow(dead_code)]
+mod example_protocol;
+
+use example_protocol::*;
+use rust_tcp_ipc::{
+    Direction, MockPeer, PayloadLogging, Protocol, SessionRecorder, SessionReplay, TcpIpc,
+    TcpIpcConfig, WriteMessageErrors,
+};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn config() -> TcpIpcConfig {
+    TcpIpcConfig {
+        after_connect_wait_time: None,
+        read_iteration_wait_time: Some(Duration::from_micros(100)),
+        shutdown_wait_time: Some(Duration::from_millis(100)),
+        check_count: 1,
+        payload_logging: PayloadLogging::LengthOnly,
+    }
+}
+
+// a recording target which can be read while the recorder still exists
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+impl Write for SharedBuffer {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .lock()
+            .expect("lock poisoned")
+            .extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// each outgoing record is one write
+fn writes(recording: &SharedBuffer) -> Vec<Vec<u8>> {
+    let bytes = recording.0.lock().expect("lock poisoned").clone();
+    SessionReplay::from_reader(&bytes[..])
+        .expect("invalid recording")
+        .records()
+        .iter()
+        .filter(|record| record.direction == Direction::Outgoing)
+        .map(|record| record.bytes.clone())
+        .collect()
+}
+
+fn frame(command: CommandsExample, payload: &[u8]) -> Vec<u8> {
+    ProtocolExample::construct_message(command, payload).expect("constructing failed")
+}
+
+// a client whose writes are recorded
+fn recorded_client(address: std::net::SocketAddr) -> (TcpIpc<ProtocolExample>, SharedBuffer) {
+    let mut client =
+        TcpIpc::<ProtocolExample>::client(address, config(), None).expect("connecting failed");
+    let recording = SharedBuffer::default();
+    client.set_recorder(Some(
+        SessionRecorder::new(recording.clone()).expect("unable to create recording"),
+    ));
+    (client, recording)
+}
+
+#[test]
+fn batched_messages_are_sent() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        .expect_payload(CommandsExample::Start, b"1".to_vec())
+        .expect_payload(CommandsExample::Funny, b"".to_vec())
+        .expect_payload(CommandsExample::Start, vec![2; 1000])
+        .expect_payload(CommandsExample::Funny, b"3".to_vec())
+        .expect_payload(CommandsExample::Funny, b"4".to_vec())
+        .start()
+        .expect("starting the mock peer failed");
+    let mut client = TcpIpc::<ProtocolExample>::client(peer.address(), config(), None)
+        .expect("connecting failed");
+    client
+        .write_batch(&[
+            (CommandsExample::Start, b"1"),
+            (CommandsExample::Funny, b""),
+            (CommandsExample::Start, &[2; 1000]),
+        ])
+        .expect("writing failed");
+    client
+        .set_write_batching(Some(1024))
+        .expect("flushing failed");
+    client
+        .write_message(CommandsExample::Funny, b"3")
+        .expect("queueing failed");
+    client
+        .write_message(CommandsExample::Funny, b"4")
+        .expect("queueing failed");
+    client.flush_batch().expect("writing failed");
+    peer.assert_finished();
+}
+
+#[test]
+fn batch_is_written_at_once() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        .expect_payload(CommandsExample::Start, b"1".to_vec())
+        .expect_payload(CommandsExample::Funny, b"2".to_vec())
+        .expect_payload(CommandsExample::Start, vec![3; 1000])
+        .start()
+        .expect("starting the mock peer failed");
+    let (mut client, recording) = recorded_client(peer.address());
+    client
+        .write_batch(&[
+            (CommandsExample::Start, b"1"),
+            (CommandsExample::Funny, b"2"),
+            (CommandsExample::Start, &[3; 1000]),
+        ])
+        .expect("writing failed");
+    assert_eq!(
+        writes(&recording),
+        vec![[
+            frame(CommandsExample::Start, b"1"),
+            frame(CommandsExample::Funny, b"2"),
+            frame(CommandsExample::Start, &[3; 1000]),
+        ]
+        .concat()]
+    );
+    assert_eq!(client.stats().total().messages_sent, 3);
+    peer.assert_finished();
+}
+
+#[test]
+fn batch_is_written_once_the_batch_size_is_reached() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        .expect_payload(CommandsExample::Funny, b"abc".to_vec())
+        .expect_payload(CommandsExample::Funny, b"def".to_vec())
+        .expect_payload(CommandsExample::Funny, b"ghi".to_vec())
+        .start()
+        .expect("starting the mock peer failed");
+    let (mut client, recording) = recorded_client(peer.address());
+    // a frame has 8 bytes, so the second one completes the batch
+    client
+        .set_write_batching(Some(10))
+        .expect("flushing failed");
+    client
+        .write_message(CommandsExample::Funny, b"abc")
+        .expect("queueing failed");
+    assert!(writes(&recording).is_empty());
+    client
+        .write_message(CommandsExample::Funny, b"def")
+        .expect("writing failed");
+    let batch = [
+        frame(CommandsExample::Funny, b"abc"),
+        frame(CommandsExample::Funny, b"def"),
+    ]
+    .concat();
+    assert_eq!(writes(&recording), vec![batch.clone()]);
+    client
+        .write_message(CommandsExample::Funny, b"ghi")
+        .expect("queueing failed");
+    assert_eq!(writes(&recording).len(), 1);
+    // disabling write batching flushes the queued message
+    client.set_write_batching(None).expect("flushing failed");
+    assert_eq!(
+        writes(&recording),
+        vec![batch, frame(CommandsExample::Funny, b"ghi")]
+    );
+    peer.assert_finished();
+}
+
+#[test]
+fn failed_construction_sends_nothing() {
+    let peer = MockPeer::<ProtocolExample>::new()
+        .expect_payload(CommandsExample::Funny, b"after".to_vec())
+        .start()
+        .expect("starting the mock peer failed");
+    let (mut client, recording) = recorded_client(peer.address());
+    // the length does not fit into the header
+    let too_long = vec![0; 1 << 24];
+    match client.write_batch(&[
+        (CommandsExample::Start, b"fine"),
+        (CommandsExample::Start, &too_long),
+    ]) {
+        Err(WriteMessageErrors::MessageConstructionFailed) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    assert!(writes(&recording).is_empty());
+    client
+        .write_message(CommandsExample::Funny, b"after")
+        .expect("writing failed");
+    peer.assert_finished();
+}